@@ -0,0 +1,398 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{channel, Receiver};
+
+use enumflags2::BitFlags;
+use serde::Deserialize;
+use serum_dex::matching::Side;
+use serum_dex::state::{AccountFlag, Market, MarketState};
+use solana_program::account_info::AccountInfo;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
+use solana_sdk::signature::Keypair;
+
+use crate::backtest::series::Tick;
+use crate::config::Settings;
+use crate::grid::generator::{GridGenerator, MarketLots, TAKER_FEE_BPS};
+use crate::mongodb::client::MongoClient;
+use crate::mongodb::models::{GridSpacing, GridStatus, StopReason, TokenInfo, Trader, TraderStatus};
+use crate::rpc::mock::MockCluster;
+use crate::workers::base::{BotConfig, BotThread};
+use crate::workers::error::TradeBotResult;
+use crate::workers::fills::FillTracker;
+use crate::workers::recorder::BookRecorder;
+use crate::workers::message::ThreadMessage;
+use crate::workers::stop::check_stop;
+use crate::workers::trade::{Price, TraderData, TraderThread};
+
+fn default_fee_bps() -> u64 {
+    TAKER_FEE_BPS
+}
+
+/// Grid parameters to backtest, amounts in native units and prices in price lots
+#[derive(Deserialize, Debug, Clone)]
+pub struct BacktestConfig {
+    pub lower_price_range: u64,
+    pub upper_price_range: u64,
+    pub grids_count: u64,
+    #[serde(default)]
+    pub grid_spacing: Option<GridSpacing>,
+    pub amount_per_grid: u64,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub base_balance: u64,
+    pub quote_balance: u64,
+    #[serde(default)]
+    pub stopping_price_low: Option<u64>,
+    #[serde(default)]
+    pub stopping_price_high: Option<u64>,
+    /// Charged on every fill, defaults to the taker fee so results stay on the safe side
+    #[serde(default = "default_fee_bps")]
+    pub fee_bps: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BacktestReport {
+    pub ticks: u64,
+    pub buys: u64,
+    pub sells: u64,
+    /// Orders the grid wanted to place without the balance to back them
+    pub rejected_orders: u64,
+    pub fees: u64,
+    /// Profit of the base sold over what it was bought for, first in first out
+    pub realized_profit: i64,
+    pub starting_base: u64,
+    pub final_base: u64,
+    pub starting_value: u64,
+    pub final_value: u64,
+    pub max_drawdown_bps: u64,
+    pub stop: Option<(StopReason, u64)>,
+}
+
+impl fmt::Display for BacktestReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut str = "".to_string();
+        str.push_str(&format!("[+] Ticks: {}\n", self.ticks));
+        str.push_str(&format!("[+] Fills: {} buys, {} sells, {} rejected orders\n", self.buys, self.sells, self.rejected_orders));
+        str.push_str(&format!("[+] Realized grid profit: {}\n", self.realized_profit));
+        str.push_str(&format!("[+] Fees: {}\n", self.fees));
+        str.push_str(&format!(
+            "[+] Inventory drift: {} base ({} -> {})\n",
+            self.final_base as i128 - self.starting_base as i128, self.starting_base, self.final_base,
+        ));
+        str.push_str(&format!(
+            "[+] Value: {} -> {} ({})\n",
+            self.starting_value, self.final_value, self.final_value as i128 - self.starting_value as i128,
+        ));
+        str.push_str(&format!("[+] Max drawdown: {}.{:02}%\n", self.max_drawdown_bps / 100, self.max_drawdown_bps % 100));
+        if let Some((reason, time)) = &self.stop {
+            str.push_str(&format!("[+] Stopped: {:?} at {}\n", reason, time));
+        }
+        write!(fmt, "{}", str)
+    }
+}
+
+/// Replays a price series through the grid logic of `TraderThread`, orders rest
+/// on their grid level and fill in full at that price once the book crosses it
+pub struct Backtester {
+    thread: TraderThread,
+    messages: Receiver<ThreadMessage>,
+    mongo_client: MongoClient,
+    market_key: Pubkey,
+    market_account: Account,
+    serum_program: Pubkey,
+    config: BacktestConfig,
+    free_base: u64,
+    free_quote: u64,
+    /// Price and quantity of the base held, oldest first
+    cost_basis: VecDeque<(u64, u64)>,
+    peak_value: u64,
+    report: BacktestReport,
+}
+
+impl Backtester {
+    pub fn new(config: BacktestConfig, first_tick: &Tick) -> TradeBotResult<Self> {
+        let serum_program = Pubkey::new_unique();
+        let market_key = Pubkey::new_unique();
+        let lots = MarketLots {
+            coin_lot_size: config.coin_lot_size,
+            pc_lot_size: config.pc_lot_size,
+        };
+        let mut trader = Trader {
+            base_trader_wallet: Pubkey::new_unique().to_string(),
+            quote_trader_wallet: Pubkey::new_unique().to_string(),
+            serum_open_orders: vec![Pubkey::new_unique().to_string()],
+            grids_count: config.grids_count,
+            grid_spacing: config.grid_spacing.clone(),
+            amount_per_grid: config.amount_per_grid,
+            upper_price_range: config.upper_price_range,
+            lower_price_range: config.lower_price_range,
+            stopping_price_high: config.stopping_price_high,
+            stopping_price_low: config.stopping_price_low,
+            starting_price_buy: first_tick.bid,
+            starting_price_sell: first_tick.ask,
+            starting_base_balance: config.base_balance,
+            starting_quote_balance: config.quote_balance,
+            base_balance: config.base_balance,
+            quote_balance: config.quote_balance,
+            register_date: first_tick.time,
+            status: TraderStatus::Initialized,
+            ..Trader::new(
+                market_key.to_string(),
+                "backtest".to_string(),
+                TokenInfo { symbol: "BASE".to_string(), address: Pubkey::new_unique().to_string(), decimals: 0 },
+                TokenInfo { symbol: "QUOTE".to_string(), address: Pubkey::new_unique().to_string(), decimals: 0 },
+            )
+        };
+        trader.grids = GridGenerator::from_trader(&trader, lots).generate()?;
+
+        let (stdout, messages) = channel::<ThreadMessage>();
+        let bot_config = BotConfig {
+            serum_program,
+            token_program: spl_token::id(),
+            associated_token_program: Pubkey::new_unique(),
+            trader: trader.clone(),
+            rpc_url: "".to_string(),
+            fee_payer: Keypair::new(),
+            connection: Arc::new(MockCluster::new()),
+            session_dir: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            settings: Arc::new(Settings::default()),
+            market: None,
+            market_data: None,
+        };
+        let thread = TraderThread {
+            stdout,
+            config: Arc::new(bot_config),
+            data: None,
+            trader,
+            fills: FillTracker::new(),
+            recorder: BookRecorder::new(),
+            cancelled_on_shutdown: vec![],
+            market_feed: None,
+        };
+
+        let mut backtester = Backtester {
+            thread,
+            messages,
+            mongo_client: MongoClient::with_database("gridbot_backtest"),
+            market_key,
+            market_account: Self::market_account(&serum_program, &market_key, lots),
+            serum_program,
+            config: config.clone(),
+            free_base: config.base_balance,
+            free_quote: config.quote_balance,
+            cost_basis: VecDeque::from(vec![(first_tick.mid(), config.base_balance)]),
+            peak_value: 0,
+            report: BacktestReport::default(),
+        };
+        backtester.report.starting_base = config.base_balance;
+        backtester.report.starting_value = backtester.value(first_tick.mid());
+        backtester.peak_value = backtester.report.starting_value;
+        Ok(backtester)
+    }
+
+    /// A market account carrying only what the grid logic reads, the lot sizes
+    fn market_account(serum_program: &Pubkey, market_key: &Pubkey, lots: MarketLots) -> Account {
+        let mut market: MarketState = bytemuck::Zeroable::zeroed();
+        let flags: BitFlags<AccountFlag> = AccountFlag::Initialized | AccountFlag::Market;
+        market.account_flags = flags.bits();
+        market.own_address = bytemuck::cast(market_key.to_bytes());
+        market.coin_lot_size = lots.coin_lot_size;
+        market.pc_lot_size = lots.pc_lot_size;
+        market.bids = bytemuck::cast(Pubkey::new_unique().to_bytes());
+        market.asks = bytemuck::cast(Pubkey::new_unique().to_bytes());
+        market.event_q = bytemuck::cast(Pubkey::new_unique().to_bytes());
+        market.req_q = bytemuck::cast(Pubkey::new_unique().to_bytes());
+        market.coin_vault = bytemuck::cast(Pubkey::new_unique().to_bytes());
+        market.pc_vault = bytemuck::cast(Pubkey::new_unique().to_bytes());
+        let mut data = b"serum".to_vec();
+        data.extend_from_slice(bytemuck::bytes_of(&market));
+        data.extend_from_slice(b"padding");
+        Account {
+            lamports: 0,
+            data,
+            owner: *serum_program,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn quote_for(&self, base: u64, price: u64) -> u64 {
+        base / self.config.coin_lot_size * price * self.config.pc_lot_size
+    }
+
+    /// Free balances plus what resting orders hold, valued in quote at `mid_price`
+    fn value(&self, mid_price: u64) -> u64 {
+        let (base, quote) = self.total_balances();
+        self.quote_for(base, mid_price) + quote
+    }
+
+    fn total_balances(&self) -> (u64, u64) {
+        let mut base = self.free_base;
+        let mut quote = self.free_quote;
+        for grid in &self.thread.trader.grids {
+            if let (Some(side), Some(order)) = (grid.awaiting_side(), &grid.order) {
+                match side {
+                    Side::Bid => quote += self.quote_for(order.remaining_base_quantity, order.price),
+                    Side::Ask => base += order.remaining_base_quantity,
+                }
+            }
+        }
+        (base, quote)
+    }
+
+    pub fn run(mut self, ticks: &[Tick]) -> BacktestReport {
+        for tick in ticks {
+            if !self.step(tick) {
+                break
+            }
+        }
+        let last_mid = ticks.last().map_or(0, |tick| tick.mid());
+        self.report.final_base = self.total_balances().0;
+        self.report.final_value = self.value(last_mid);
+        self.report
+    }
+
+    /// Returns false once a stopping price is hit
+    fn step(&mut self, tick: &Tick) -> bool {
+        self.report.ticks += 1;
+        self.fill_orders(tick);
+
+        let value = self.value(tick.mid());
+        self.peak_value = self.peak_value.max(value);
+        if self.peak_value > 0 {
+            let drawdown_bps = (self.peak_value - value) * 10000 / self.peak_value;
+            self.report.max_drawdown_bps = self.report.max_drawdown_bps.max(drawdown_bps);
+        }
+
+        if let Some(reason) = check_stop(&self.thread.trader, tick.mid()) {
+            self.report.stop = Some((reason, tick.time));
+            return false
+        }
+
+        let has_work = self.thread.trader.grids
+            .iter()
+            .any(|grid| grid.status == GridStatus::Idle || grid.status == GridStatus::Violated);
+        if has_work {
+            self.thread.data = Some(TraderData {
+                last_price: Some(Price { buy: tick.bid, sell: tick.ask }),
+            });
+            let connection = self.thread.config.connection.clone();
+            let mut market_account = self.market_account.clone();
+            let market_account_info = AccountInfo::new(
+                &self.market_key,
+                false,
+                false,
+                &mut market_account.lamports,
+                &mut market_account.data,
+                &self.serum_program,
+                false,
+                0,
+            );
+            let serum_market = Market::load(&market_account_info, &self.serum_program, true).unwrap();
+            if let Err(e) = self.thread.compile_ixs(connection.as_ref(), &serum_market, &self.mongo_client) {
+                eprintln!("[-] Grid logic failed at {}: {}", tick.time, e);
+            }
+            drop(serum_market);
+            self.place_pending_orders();
+        }
+        while self.messages.try_recv().is_ok() {}
+        true
+    }
+
+    /// Locks the balance behind every order the grid logic staged, orders that can't be backed are dropped
+    fn place_pending_orders(&mut self) {
+        for grid_index in 0..self.thread.trader.grids.len() {
+            let pending = match &self.thread.trader.grids[grid_index].pending {
+                Some(pending) => pending.order.clone(),
+                None => continue
+            };
+            let placed = match pending.side {
+                Side::Bid => {
+                    let quote = self.quote_for(pending.base_quantity, pending.price);
+                    if self.free_quote >= quote {
+                        self.free_quote -= quote;
+                        true
+                    } else {
+                        false
+                    }
+                }
+                Side::Ask => {
+                    if self.free_base >= pending.base_quantity {
+                        self.free_base -= pending.base_quantity;
+                        true
+                    } else {
+                        false
+                    }
+                }
+            };
+            let grid = &mut self.thread.trader.grids[grid_index];
+            if placed {
+                grid.commit_pending();
+            } else {
+                grid.rollback_pending();
+                self.report.rejected_orders += 1;
+            }
+        }
+    }
+
+    fn fill_orders(&mut self, tick: &Tick) {
+        for grid_index in 0..self.thread.trader.grids.len() {
+            let grid = &self.thread.trader.grids[grid_index];
+            let (side, order) = match (grid.awaiting_side(), &grid.order) {
+                (Some(side), Some(order)) => (side, order.clone()),
+                _ => continue
+            };
+            let crossed = match side {
+                Side::Bid => tick.ask <= order.price,
+                Side::Ask => tick.bid >= order.price,
+            };
+            if !crossed {
+                continue
+            }
+            let base = order.remaining_base_quantity;
+            let quote = self.quote_for(base, order.price);
+            let fee = quote * self.config.fee_bps / 10000;
+            self.report.fees += fee;
+            match side {
+                Side::Bid => {
+                    self.report.buys += 1;
+                    self.free_base += base;
+                    self.free_quote = self.free_quote.saturating_sub(fee);
+                    self.cost_basis.push_back((order.price, base));
+                }
+                Side::Ask => {
+                    self.report.sells += 1;
+                    self.free_quote += quote - fee;
+                    self.realize(base, order.price);
+                }
+            }
+            let grid = &mut self.thread.trader.grids[grid_index];
+            if let Some(order) = grid.order.as_mut() {
+                order.apply_fill(base);
+                order.is_filled = true;
+            }
+            grid.status = GridStatus::Violated;
+        }
+    }
+
+    fn realize(&mut self, mut base: u64, price: u64) {
+        while base > 0 {
+            let (bought_at, held) = match self.cost_basis.front_mut() {
+                Some(front) => front,
+                None => return
+            };
+            let matched = base.min(*held);
+            let lots = (matched / self.config.coin_lot_size) as i64;
+            self.report.realized_profit += (price as i64 - *bought_at as i64) * lots * self.config.pc_lot_size as i64;
+            *held -= matched;
+            base -= matched;
+            if *held == 0 {
+                self.cost_basis.pop_front();
+            }
+        }
+    }
+}
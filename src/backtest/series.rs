@@ -0,0 +1,56 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::mongodb::models::BookSnapshot;
+
+/// Top of the book at a point in time, prices in price lots like the grid levels
+#[derive(Debug, Clone, Copy)]
+pub struct Tick {
+    pub time: u64,
+    pub bid: u64,
+    pub ask: u64,
+}
+
+impl Tick {
+    pub fn mid(&self) -> u64 {
+        (self.bid + self.ask) / 2
+    }
+
+    /// Best bid and ask of a recorded book, `None` when either side was empty
+    pub fn from_snapshot(snapshot: &BookSnapshot) -> Option<Self> {
+        Some(Tick {
+            time: snapshot.time,
+            bid: snapshot.bids.first()?.price,
+            ask: snapshot.asks.first()?.price,
+        })
+    }
+}
+
+/// Reads `time,price` trades or `time,bid,ask` quotes, a header line is skipped
+pub fn load_csv(path: &Path) -> Result<Vec<Tick>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut ticks = vec![];
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let columns: Vec<&str> = line.split(',').map(|column| column.trim()).collect();
+        if line.trim().is_empty() || (line_number == 0 && columns[0].parse::<u64>().is_err()) {
+            continue
+        }
+        let values: Vec<u64> = columns
+            .iter()
+            .map(|column| column.parse::<u64>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_number + 1, e)))?;
+        let tick = match values.as_slice() {
+            [time, price] => Tick { time: *time, bid: *price, ask: *price },
+            [time, bid, ask] => Tick { time: *time, bid: *bid, ask: *ask },
+            _ => return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("line {}: expected time,price or time,bid,ask", line_number + 1),
+            ))
+        };
+        ticks.push(tick);
+    }
+    Ok(ticks)
+}
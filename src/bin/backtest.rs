@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::path::Path;
+
+use tradebotv3::backtest::engine::{BacktestConfig, Backtester};
+use tradebotv3::backtest::series::{load_csv, Tick};
+use tradebotv3::mongodb::client::MongoClient;
+use tradebotv3::workers::recorder::load_snapshots;
+
+const USAGE: &str = "Usage: backtest <config.json> <prices.csv>\n       backtest <config.json> --snapshots <market_address>";
+
+/// Usage: backtest <config.json> <prices.csv>
+///        backtest <config.json> --snapshots <market_address>
+///
+/// Snapshots are read from the book snapshots the traders recorded in the live database
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 || (args[2] == "--snapshots" && args.len() < 4) {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let config: BacktestConfig = serde_json::from_reader(File::open(&args[1]).unwrap()).unwrap();
+    let (ticks, source) = if args[2] == "--snapshots" {
+        (snapshot_ticks(&args[3]), format!("market {}", args[3]))
+    } else {
+        (load_csv(Path::new(&args[2])).unwrap(), args[2].clone())
+    };
+    let first_tick = match ticks.first() {
+        Some(tick) => *tick,
+        None => {
+            eprintln!("[-] {} has no prices", source);
+            std::process::exit(1);
+        }
+    };
+    let backtester = match Backtester::new(config, &first_tick) {
+        Ok(backtester) => backtester,
+        Err(e) => {
+            eprintln!("[-] Invalid grid: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let report = backtester.run(&ticks);
+    println!("{}", report);
+}
+
+/// Best bid and ask of every recorded snapshot of the market, snapshots of an empty side are skipped
+fn snapshot_ticks(market_address: &str) -> Vec<Tick> {
+    match load_snapshots(&MongoClient::new(), market_address) {
+        Ok(snapshots) => snapshots.iter().filter_map(Tick::from_snapshot).collect(),
+        Err(e) => {
+            eprintln!("[-] Failed to load the snapshots of {}: {}", market_address, e);
+            std::process::exit(1);
+        }
+    }
+}
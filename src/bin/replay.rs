@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use tradebotv3::mongodb::client::MongoClient;
+use tradebotv3::replay::harness::{replay_iteration, REPLAY_DATABASE};
+use tradebotv3::replay::session::load_session;
+
+/// Usage: replay <session.jsonl> [iteration]
+///
+/// Sessions are recorded by running the bot with `RECORD_SESSION_DIR` set. Without an
+/// iteration every iteration of the session is replayed and only the ones whose
+/// instructions differ from the recording are printed in full
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: replay <session.jsonl> [iteration]");
+        std::process::exit(1);
+    }
+    let records = load_session(Path::new(&args[1])).unwrap();
+    let iteration = args.get(2).map(|iteration| iteration.parse::<u64>().unwrap());
+    let mongo_client = MongoClient::with_database(REPLAY_DATABASE);
+
+    let mut replayed = 0;
+    let mut mismatches = 0;
+    for record in records.iter().filter(|record| iteration.map_or(true, |iteration| record.iteration == iteration)) {
+        match replay_iteration(record, &mongo_client) {
+            Ok(outcome) => {
+                replayed += 1;
+                if !outcome.matches() {
+                    mismatches += 1;
+                }
+                if iteration.is_some() || !outcome.matches() {
+                    println!("{}", outcome);
+                }
+            }
+            Err(e) => {
+                eprintln!("[-] {}", e);
+            }
+        }
+    }
+    println!("[+] Replayed {} iterations, {} differ from the recording", replayed, mismatches);
+}
@@ -0,0 +1,279 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use solana_program::pubkey::Pubkey;
+use thiserror::Error;
+
+use crate::str_to_pubkey;
+
+/// Instructions per transaction unless set otherwise, the cleanup thread needs 3 of them for itself
+pub const DEFAULT_MAX_IXS: usize = 10;
+pub const DEFAULT_MAX_NEW_ORDER_IXS: usize = 10;
+const MIN_MAX_IXS: usize = 4;
+const DEFAULT_SYNC_INTERVAL_SECS: u64 = 30;
+const DEFAULT_RPC_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_MARKET_DATA_INTERVAL_MS: u64 = 500;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Can't read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid settings file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid settings: {0}")]
+    Invalid(String),
+}
+
+/// Solana cluster the bot trades on, picks the RPC endpoint and the serum program
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Cluster {
+    Mainnet,
+    Devnet,
+    Localnet,
+}
+
+impl Cluster {
+    pub fn rpc_url(&self) -> &'static str {
+        match self {
+            Cluster::Mainnet => "https://hedgehog.rpcpool.com",
+            Cluster::Devnet => "https://api.devnet.solana.com",
+            Cluster::Localnet => "http://127.0.0.1:8899",
+        }
+    }
+
+    /// Serum dex v3 deployment of the cluster, a local validator has none of its own
+    pub fn serum_program(&self) -> Option<&'static str> {
+        match self {
+            Cluster::Mainnet => Some("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"),
+            Cluster::Devnet => Some("DESVgJVGajEgKGXhb6XmqDHGz3VjdgP7rEVESBgxmroY"),
+            Cluster::Localnet => None,
+        }
+    }
+}
+
+impl FromStr for Cluster {
+    type Err = String;
+
+    fn from_str(cluster: &str) -> Result<Self, Self::Err> {
+        match cluster {
+            "mainnet" | "mainnet-beta" => Ok(Cluster::Mainnet),
+            "devnet" => Ok(Cluster::Devnet),
+            "localnet" | "localhost" => Ok(Cluster::Localnet),
+            _ => Err(format!("unknown cluster {}, expected mainnet, devnet or localnet", cluster)),
+        }
+    }
+}
+
+impl fmt::Display for Cluster {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cluster::Mainnet => fmt.write_str("mainnet"),
+            Cluster::Devnet => fmt.write_str("devnet"),
+            Cluster::Localnet => fmt.write_str("localnet"),
+        }
+    }
+}
+
+/// Settings of the bot, read from a TOML file. Everything is optional, unset
+/// endpoints and program ids come from the cluster preset
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Settings {
+    pub cluster: Cluster,
+    pub rpc_url: Option<String>,
+    pub rpc_timeout_secs: u64,
+    /// Websocket endpoint, derived from the RPC endpoint when unset
+    pub ws_url: Option<String>,
+    /// Books are re-read when their accounts change instead of on every tick, polling
+    /// takes over while the websocket is down
+    pub subscriptions: bool,
+    pub serum_program: Option<String>,
+    pub token_program: String,
+    pub associated_token_program: String,
+    pub mongodb_url: String,
+    pub database: String,
+    /// Instructions per transaction
+    pub max_ixs: usize,
+    /// New orders the trader thread places per transaction
+    pub max_new_order_ixs: usize,
+    /// Seconds between two balance syncs of a trader
+    pub sync_interval_secs: u64,
+    /// Milliseconds between two reads of the book of a market, shared by all of its traders
+    pub market_data_interval_ms: u64,
+    /// Orders are executed against a local copy of the market instead of being sent
+    pub paper_trading: bool,
+    /// Every worker iteration is recorded to a session file in this directory when set
+    pub session_dir: Option<PathBuf>,
+    /// Markets traders can reference by name
+    pub markets_file: PathBuf,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            cluster: Cluster::Mainnet,
+            rpc_url: None,
+            rpc_timeout_secs: DEFAULT_RPC_TIMEOUT_SECS,
+            ws_url: None,
+            subscriptions: true,
+            serum_program: None,
+            token_program: "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA".to_string(),
+            associated_token_program: "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL".to_string(),
+            mongodb_url: "mongodb://localhost:27017/gridbot".to_string(),
+            database: "gridbot".to_string(),
+            max_ixs: DEFAULT_MAX_IXS,
+            max_new_order_ixs: DEFAULT_MAX_NEW_ORDER_IXS,
+            sync_interval_secs: DEFAULT_SYNC_INTERVAL_SECS,
+            market_data_interval_ms: DEFAULT_MARKET_DATA_INTERVAL_MS,
+            paper_trading: false,
+            session_dir: None,
+            markets_file: PathBuf::from("markets.json"),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        Self::from_toml(&content)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// The environment variables the bot was configured with before the settings
+    /// file existed still take precedence over it
+    pub fn apply_env(&mut self) {
+        if let Ok(url) = std::env::var("MONGODB_URL") {
+            self.mongodb_url = url;
+        }
+        if let Ok(value) = std::env::var("PAPER_TRADING") {
+            self.paper_trading = value == "1" || value == "true";
+        }
+        if let Ok(dir) = std::env::var("RECORD_SESSION_DIR") {
+            self.session_dir = Some(PathBuf::from(dir));
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let rpc_url = self.rpc_url();
+        if !rpc_url.starts_with("http://") && !rpc_url.starts_with("https://") {
+            return Err(ConfigError::Invalid(format!("rpc_url {} is not an http endpoint", rpc_url)))
+        }
+        if let Some(ws_url) = &self.ws_url {
+            if !ws_url.starts_with("ws://") && !ws_url.starts_with("wss://") {
+                return Err(ConfigError::Invalid(format!("ws_url {} is not a websocket endpoint", ws_url)))
+            }
+        }
+        match &self.serum_program {
+            Some(serum_program) => validate_pubkey("serum_program", serum_program)?,
+            None if self.cluster.serum_program().is_none() => {
+                return Err(ConfigError::Invalid(format!("serum_program has to be set on {}", self.cluster)))
+            }
+            None => {}
+        }
+        validate_pubkey("token_program", &self.token_program)?;
+        validate_pubkey("associated_token_program", &self.associated_token_program)?;
+        if !self.mongodb_url.starts_with("mongodb://") && !self.mongodb_url.starts_with("mongodb+srv://") {
+            return Err(ConfigError::Invalid(format!("mongodb_url {} is not a mongodb connection string", self.mongodb_url)))
+        }
+        if self.database.is_empty() {
+            return Err(ConfigError::Invalid("database is empty".to_string()))
+        }
+        if self.max_ixs < MIN_MAX_IXS {
+            return Err(ConfigError::Invalid(format!("max_ixs has to be at least {}", MIN_MAX_IXS)))
+        }
+        if self.max_new_order_ixs == 0 || self.max_new_order_ixs > self.max_ixs {
+            return Err(ConfigError::Invalid(format!("max_new_order_ixs has to be between 1 and max_ixs ({})", self.max_ixs)))
+        }
+        if self.sync_interval_secs == 0 || self.rpc_timeout_secs == 0 || self.market_data_interval_ms == 0 {
+            return Err(ConfigError::Invalid("sync_interval_secs, rpc_timeout_secs and market_data_interval_ms can't be 0".to_string()))
+        }
+        Ok(())
+    }
+
+    pub fn rpc_url(&self) -> String {
+        self.rpc_url.clone().unwrap_or_else(|| self.cluster.rpc_url().to_string())
+    }
+
+    /// Websocket endpoint of the cluster, none when subscriptions are turned off. Like the
+    /// solana cli, the RPC endpoint with a ws scheme and the port after its own
+    pub fn ws_url(&self) -> Option<String> {
+        if !self.subscriptions {
+            return None
+        }
+        if let Some(ws_url) = &self.ws_url {
+            return Some(ws_url.clone())
+        }
+        let rpc_url = self.rpc_url();
+        let ws_url = if let Some(rest) = rpc_url.strip_prefix("https://") {
+            format!("wss://{}", rest)
+        } else {
+            format!("ws://{}", rpc_url.trim_start_matches("http://"))
+        };
+        Some(ws_url.replace(":8899", ":8900"))
+    }
+
+    pub fn rpc_timeout(&self) -> Duration {
+        Duration::from_secs(self.rpc_timeout_secs)
+    }
+
+    /// Only call on validated settings
+    pub fn serum_program(&self) -> Pubkey {
+        match &self.serum_program {
+            Some(serum_program) => str_to_pubkey(serum_program),
+            None => str_to_pubkey(self.cluster.serum_program().unwrap()),
+        }
+    }
+
+    pub fn token_program(&self) -> Pubkey {
+        str_to_pubkey(&self.token_program)
+    }
+
+    pub fn associated_token_program(&self) -> Pubkey {
+        str_to_pubkey(&self.associated_token_program)
+    }
+
+    pub fn sync_interval(&self) -> Duration {
+        Duration::from_secs(self.sync_interval_secs)
+    }
+
+    pub fn market_data_interval(&self) -> Duration {
+        Duration::from_millis(self.market_data_interval_ms)
+    }
+}
+
+impl fmt::Display for Settings {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(fmt, "[+] Cluster: {}", self.cluster)?;
+        writeln!(fmt, "[+] RPC: {} (timeout {}s)", self.rpc_url(), self.rpc_timeout_secs)?;
+        match self.ws_url() {
+            Some(ws_url) => writeln!(fmt, "[+] Websocket: {}", ws_url)?,
+            None => writeln!(fmt, "[+] Websocket: off, polling the books")?,
+        }
+        writeln!(fmt, "[+] Serum program: {}", self.serum_program())?;
+        writeln!(fmt, "[+] Token program: {}", self.token_program)?;
+        writeln!(fmt, "[+] Associated token program: {}", self.associated_token_program)?;
+        writeln!(fmt, "[+] MongoDB: {} database {}", self.mongodb_url, self.database)?;
+        writeln!(fmt, "[+] Instructions per transaction: {}, new orders: {}", self.max_ixs, self.max_new_order_ixs)?;
+        writeln!(fmt, "[+] Sync interval: {}s", self.sync_interval_secs)?;
+        writeln!(fmt, "[+] Market data interval: {}ms", self.market_data_interval_ms)?;
+        writeln!(fmt, "[+] Paper trading: {}", self.paper_trading)?;
+        writeln!(fmt, "[+] Markets: {}", self.markets_file.display())?;
+        match &self.session_dir {
+            Some(dir) => writeln!(fmt, "[+] Recording sessions to {}", dir.display()),
+            None => writeln!(fmt, "[+] Not recording sessions"),
+        }
+    }
+}
+
+fn validate_pubkey(name: &str, address: &str) -> Result<(), ConfigError> {
+    Pubkey::from_str(address)
+        .map(|_| ())
+        .map_err(|_| ConfigError::Invalid(format!("{} {} is not a valid address", name, address)))
+}
@@ -0,0 +1,181 @@
+use crate::mongodb::models::{GridPosition, GridSpacing, GridStatus, Trader};
+use crate::workers::error::{TradeBotErrors, TradeBotResult};
+
+/// Taker fee of the base serum fee tier, in basis points. Grid orders usually rest
+/// as makers but any of them can cross the book when placed
+pub const TAKER_FEE_BPS: u64 = 22;
+
+/// Lot sizes of the market the grid is generated for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketLots {
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+}
+
+impl MarketLots {
+    /// Whole base lots of a native base amount, the rest can't be traded
+    pub fn base_lots(&self, base_native: u64) -> u64 {
+        base_native / self.coin_lot_size
+    }
+
+    /// Native quote a bid of `base_lots` at `price` lots locks up, the max pc quantity
+    /// of the new order instruction
+    pub fn quote_native(&self, base_lots: u64, price: u64) -> u64 {
+        base_lots * self.pc_lot_size * price
+    }
+}
+
+pub struct GridGenerator {
+    pub lower_price: u64,
+    pub upper_price: u64,
+    pub grids_count: u64,
+    pub spacing: GridSpacing,
+    /// Base quantity traded on every grid, in native units
+    pub amount_per_grid: u64,
+    pub lots: MarketLots,
+}
+
+impl GridGenerator {
+    pub fn from_trader(trader: &Trader, lots: MarketLots) -> Self {
+        GridGenerator {
+            lower_price: trader.lower_price_range,
+            upper_price: trader.upper_price_range,
+            grids_count: trader.grids_count,
+            spacing: trader.grid_spacing.clone().unwrap_or(GridSpacing::Arithmetic),
+            amount_per_grid: trader.amount_per_grid,
+            lots,
+        }
+    }
+
+    /// Price levels in lots from the lowest to the highest, rounded to whole price lots
+    pub fn levels(&self) -> TradeBotResult<Vec<u64>> {
+        if self.grids_count < 2 || self.lower_price == 0 || self.lower_price >= self.upper_price {
+            return Err(TradeBotErrors::InvalidGridConfig);
+        }
+        let lower = self.lower_price as f64;
+        let upper = self.upper_price as f64;
+        let steps = (self.grids_count - 1) as f64;
+        let mut levels: Vec<u64> = vec![];
+        for i in 0..self.grids_count {
+            let price = match self.spacing {
+                GridSpacing::Arithmetic => lower + (upper - lower) * i as f64 / steps,
+                GridSpacing::Geometric => lower * (upper / lower).powf(i as f64 / steps),
+            };
+            let price = price.round() as u64;
+            // levels collapsing onto the same tick mean the range is too tight for the grid count
+            if levels.last() == Some(&price) {
+                return Err(TradeBotErrors::InvalidGridConfig);
+            }
+            levels.push(price);
+        }
+        Ok(levels)
+    }
+
+    /// Buying on one level and selling on the next has to make more than the fees of both legs.
+    /// A grid whose quote amounts don't fit in a u64 is invalid
+    pub fn check_profit(&self, levels: &[u64]) -> TradeBotResult<()> {
+        let base_lots = self.amount_per_grid / self.lots.coin_lot_size;
+        if base_lots == 0 {
+            return Err(TradeBotErrors::InvalidGridConfig);
+        }
+        let quote_per_price_lot = base_lots.checked_mul(self.lots.pc_lot_size)
+            .ok_or(TradeBotErrors::InvalidGridConfig)?;
+        for pair in levels.windows(2) {
+            let (buy, sell) = (pair[0], pair[1]);
+            let profit = (sell - buy).checked_mul(quote_per_price_lot)
+                .ok_or(TradeBotErrors::InvalidGridConfig)?;
+            let fees = buy.checked_add(sell)
+                .and_then(|prices| prices.checked_mul(quote_per_price_lot))
+                .and_then(|quote| quote.checked_mul(TAKER_FEE_BPS))
+                .ok_or(TradeBotErrors::InvalidGridConfig)? / 10000;
+            if profit <= fees {
+                return Err(TradeBotErrors::ProfitTooLow);
+            }
+        }
+        Ok(())
+    }
+
+    /// Grids ordered from the highest price down, the way the trader thread keeps them
+    pub fn generate(&self) -> TradeBotResult<Vec<GridPosition>> {
+        let levels = self.levels()?;
+        self.check_profit(&levels)?;
+        Ok(levels
+            .into_iter()
+            .rev()
+            .map(|price| GridPosition {
+                price,
+                status: GridStatus::Idle,
+                order: None,
+                generation: 0,
+                client_order_id: None,
+                pending: None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(lower_price: u64, upper_price: u64, grids_count: u64, spacing: GridSpacing) -> GridGenerator {
+        GridGenerator {
+            lower_price,
+            upper_price,
+            grids_count,
+            spacing,
+            amount_per_grid: 1000,
+            lots: MarketLots { coin_lot_size: 100, pc_lot_size: 10 },
+        }
+    }
+
+    #[test]
+    fn arithmetic_levels_are_evenly_spaced() {
+        let levels = generator(100, 200, 5, GridSpacing::Arithmetic).levels().unwrap();
+        assert_eq!(levels, vec![100, 125, 150, 175, 200]);
+    }
+
+    #[test]
+    fn geometric_levels_keep_the_same_ratio() {
+        let levels = generator(100, 800, 4, GridSpacing::Geometric).levels().unwrap();
+        assert_eq!(levels, vec![100, 200, 400, 800]);
+    }
+
+    #[test]
+    fn levels_rounding_onto_one_tick_are_rejected() {
+        // 100.5 rounds up onto 101, the next level
+        assert_eq!(generator(100, 102, 5, GridSpacing::Arithmetic).levels(), Err(TradeBotErrors::InvalidGridConfig));
+        assert_eq!(generator(100, 100, 2, GridSpacing::Arithmetic).levels(), Err(TradeBotErrors::InvalidGridConfig));
+        assert_eq!(generator(100, 200, 1, GridSpacing::Arithmetic).levels(), Err(TradeBotErrors::InvalidGridConfig));
+    }
+
+    #[test]
+    fn spacing_tighter_than_the_fees_is_not_profitable() {
+        // a tick between levels around 1000 earns 0.1%, both legs pay 0.44% in fees
+        assert_eq!(generator(1000, 1004, 5, GridSpacing::Arithmetic).generate().err(), Some(TradeBotErrors::ProfitTooLow));
+        assert!(generator(1000, 1100, 5, GridSpacing::Arithmetic).generate().is_ok());
+    }
+
+    #[test]
+    fn amount_below_one_lot_is_rejected() {
+        let mut generator = generator(100, 200, 5, GridSpacing::Arithmetic);
+        generator.amount_per_grid = 99;
+        assert_eq!(generator.generate().err(), Some(TradeBotErrors::InvalidGridConfig));
+    }
+
+    #[test]
+    fn overflowing_quote_amounts_are_rejected() {
+        let mut generator = generator(100, 200, 5, GridSpacing::Arithmetic);
+        generator.amount_per_grid = u64::MAX;
+        generator.lots.coin_lot_size = 1;
+        assert_eq!(generator.generate().err(), Some(TradeBotErrors::InvalidGridConfig));
+    }
+
+    #[test]
+    fn grids_are_generated_from_the_highest_price_down() {
+        let grids = generator(100, 200, 5, GridSpacing::Arithmetic).generate().unwrap();
+        let prices: Vec<u64> = grids.iter().map(|grid| grid.price).collect();
+        assert_eq!(prices, vec![200, 175, 150, 125, 100]);
+        assert!(grids.iter().all(|grid| grid.status == GridStatus::Idle && grid.order.is_none()));
+    }
+}
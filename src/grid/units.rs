@@ -0,0 +1,307 @@
+use thiserror::Error;
+
+use crate::grid::generator::MarketLots;
+use crate::mongodb::models::{GridUiConfig, Trader};
+use crate::serum::markets::MarketDetails;
+
+/// Digits a decimal string can have, keeps every conversion within u128
+const MAX_DECIMAL_DIGITS: usize = 24;
+/// Mints have at most 9 decimals in practice, anything above is a broken config
+const MAX_TOKEN_DECIMALS: u64 = 18;
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum UnitsError {
+    #[error("{0} is not a decimal number")]
+    InvalidDecimal(String),
+    #[error("{0} is out of range for the market")]
+    Overflow(String),
+    #[error("{0} {1} is below one lot of the market ({2})")]
+    BelowMinimum(&'static str, String, String),
+    #[error("Lower price {0} is not below upper price {1} once rounded to the market's price lots")]
+    EmptyRange(String, String),
+    #[error("Token decimals {0} are not supported")]
+    InvalidTokenDecimals(u64),
+    #[error("Token decimals of the trader ({0}/{1}) don't match the market ({2}/{3})")]
+    DecimalsMismatch(u64, u64, u8, u8),
+}
+
+/// How a value that falls between two lots is rounded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    Down,
+    Up,
+    /// Half a lot rounds up
+    Nearest,
+}
+
+/// Converts between UI prices and sizes and the lots and native amounts serum works in.
+///
+/// A price of `p` quote tokens per base token is `p * 10^quote_decimals * coin_lot_size /
+/// (10^base_decimals * pc_lot_size)` price lots, a size of `s` base tokens is
+/// `s * 10^base_decimals / coin_lot_size` base lots
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketUnits {
+    pub base_decimals: u32,
+    pub quote_decimals: u32,
+    pub lots: MarketLots,
+}
+
+impl MarketUnits {
+    pub fn new(base_decimals: u64, quote_decimals: u64, lots: MarketLots) -> Result<Self, UnitsError> {
+        for decimals in [base_decimals, quote_decimals].iter() {
+            if *decimals > MAX_TOKEN_DECIMALS {
+                return Err(UnitsError::InvalidTokenDecimals(*decimals))
+            }
+        }
+        Ok(MarketUnits {
+            base_decimals: base_decimals as u32,
+            quote_decimals: quote_decimals as u32,
+            lots,
+        })
+    }
+
+    pub fn from_details(details: &MarketDetails) -> Result<Self, UnitsError> {
+        Self::new(details.base_decimals as u64, details.quote_decimals as u64, MarketLots {
+            coin_lot_size: details.coin_lot_size,
+            pc_lot_size: details.pc_lot_size,
+        })
+    }
+
+    /// Units of a trader from the decimals of its tokens, checked against the market
+    /// registry when the market was verified
+    pub fn for_trader(trader: &Trader, lots: MarketLots, details: Option<&MarketDetails>) -> Result<Self, UnitsError> {
+        let (base_decimals, quote_decimals) = (trader.base_token_info.decimals, trader.quote_token_info.decimals);
+        if let Some(details) = details {
+            if base_decimals != details.base_decimals as u64 || quote_decimals != details.quote_decimals as u64 {
+                return Err(UnitsError::DecimalsMismatch(base_decimals, quote_decimals, details.base_decimals, details.quote_decimals))
+            }
+        }
+        Self::new(base_decimals, quote_decimals, lots)
+    }
+
+    /// Price lots of a UI price
+    pub fn price_to_lots(&self, price: &str, rounding: Rounding) -> Result<u64, UnitsError> {
+        let (mantissa, scale) = parse_decimal(price)?;
+        let overflow = || UnitsError::Overflow(price.to_string());
+        let numerator = pow10(self.quote_decimals)
+            .and_then(|unit| mantissa.checked_mul(unit))
+            .and_then(|n| n.checked_mul(self.lots.coin_lot_size as u128))
+            .ok_or_else(overflow)?;
+        let denominator = pow10(scale + self.base_decimals)
+            .and_then(|unit| unit.checked_mul(self.lots.pc_lot_size as u128))
+            .ok_or_else(overflow)?;
+        to_u64(divide(numerator, denominator, rounding)).ok_or_else(overflow)
+    }
+
+    /// Native base amount of a UI size, rounded down to whole base lots so a grid never
+    /// trades more than configured. A size below one lot is refused
+    pub fn size_to_native(&self, size: &str) -> Result<u64, UnitsError> {
+        let (mantissa, scale) = parse_decimal(size)?;
+        let overflow = || UnitsError::Overflow(size.to_string());
+        let numerator = pow10(self.base_decimals)
+            .and_then(|unit| mantissa.checked_mul(unit))
+            .ok_or_else(overflow)?;
+        let denominator = pow10(scale)
+            .and_then(|unit| unit.checked_mul(self.lots.coin_lot_size as u128))
+            .ok_or_else(overflow)?;
+        let base_lots = divide(numerator, denominator, Rounding::Down);
+        if base_lots == 0 {
+            return Err(UnitsError::BelowMinimum("Size", size.to_string(), self.size_to_ui(self.lots.coin_lot_size)))
+        }
+        base_lots
+            .checked_mul(self.lots.coin_lot_size as u128)
+            .and_then(to_u64)
+            .ok_or_else(overflow)
+    }
+
+    /// UI price of price lots, exact up to the quote decimals
+    pub fn price_to_ui(&self, price_lots: u64) -> String {
+        // price in quote native units per whole base token
+        let numerator = (price_lots as u128 * self.lots.pc_lot_size as u128).saturating_mul(unit(self.base_decimals));
+        let quote_native = divide(numerator, self.lots.coin_lot_size as u128, Rounding::Nearest);
+        format_decimal(quote_native, self.quote_decimals)
+    }
+
+    pub fn size_to_ui(&self, base_native: u64) -> String {
+        format_decimal(base_native as u128, self.base_decimals)
+    }
+
+    /// Writes the grid range and size of `config` to the lot and native fields of the
+    /// trader. The range is rounded inwards so the grid never leaves what was configured,
+    /// stop prices go to the nearest price lot
+    pub fn apply(&self, config: &GridUiConfig, trader: &mut Trader) -> Result<(), UnitsError> {
+        let lower_price = self.price_to_lots(&config.lower_price, Rounding::Up)?;
+        let upper_price = self.price_to_lots(&config.upper_price, Rounding::Down)?;
+        if lower_price == 0 {
+            return Err(UnitsError::BelowMinimum("Price", config.lower_price.clone(), self.price_to_ui(1)))
+        }
+        if lower_price >= upper_price {
+            return Err(UnitsError::EmptyRange(config.lower_price.clone(), config.upper_price.clone()))
+        }
+        let stop_price = |price: &Option<String>| -> Result<Option<u64>, UnitsError> {
+            price.as_ref().map(|price| self.price_to_lots(price, Rounding::Nearest)).transpose()
+        };
+        let stopping_price_low = stop_price(&config.stopping_price_low)?;
+        let stopping_price_high = stop_price(&config.stopping_price_high)?;
+
+        trader.amount_per_grid = self.size_to_native(&config.amount_per_grid)?;
+        trader.lower_price_range = lower_price;
+        trader.upper_price_range = upper_price;
+        trader.stopping_price_low = stopping_price_low;
+        trader.stopping_price_high = stopping_price_high;
+        Ok(())
+    }
+}
+
+/// Splits "12.345" into 12345 and 3
+pub fn parse_decimal(value: &str) -> Result<(u128, u32), UnitsError> {
+    let invalid = || UnitsError::InvalidDecimal(value.to_string());
+    let trimmed = value.trim();
+    let (integer, fraction) = match trimmed.find('.') {
+        Some(dot) => (&trimmed[..dot], &trimmed[dot + 1..]),
+        None => (trimmed, ""),
+    };
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(invalid())
+    }
+    if !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(invalid())
+    }
+    // trailing zeros of the fraction carry no precision
+    let fraction = fraction.trim_end_matches('0');
+    let digits = format!("{}{}", integer, fraction);
+    let digits = digits.trim_start_matches('0');
+    if digits.len() > MAX_DECIMAL_DIGITS {
+        return Err(UnitsError::Overflow(value.to_string()))
+    }
+    let mantissa = if digits.is_empty() { 0 } else { digits.parse::<u128>().map_err(|_| invalid())? };
+    Ok((mantissa, fraction.len() as u32))
+}
+
+/// Writes `value / 10^decimals` without trailing zeros
+pub fn format_decimal(value: u128, decimals: u32) -> String {
+    let one = unit(decimals);
+    let fraction = format!("{:0width$}", value % one, width = decimals as usize);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        format!("{}", value / one)
+    } else {
+        format!("{}.{}", value / one, fraction)
+    }
+}
+
+fn divide(numerator: u128, denominator: u128, rounding: Rounding) -> u128 {
+    let (quotient, remainder) = (numerator / denominator, numerator % denominator);
+    let round_up = match rounding {
+        Rounding::Down => false,
+        Rounding::Up => remainder > 0,
+        Rounding::Nearest => remainder >= denominator - remainder,
+    };
+    if round_up { quotient + 1 } else { quotient }
+}
+
+fn pow10(exponent: u32) -> Option<u128> {
+    10u128.checked_pow(exponent)
+}
+
+/// Token decimals are checked on construction, they always fit
+fn unit(decimals: u32) -> u128 {
+    10u128.pow(decimals)
+}
+
+fn to_u64(value: u128) -> Option<u64> {
+    if value > u64::MAX as u128 { None } else { Some(value as u64) }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mongodb::models::TokenInfo;
+
+    use super::*;
+
+    /// SOL/USDC like, a price lot is 0.01 USDC and a base lot 0.001 SOL
+    fn units() -> MarketUnits {
+        MarketUnits::new(9, 6, MarketLots { coin_lot_size: 1_000_000, pc_lot_size: 10 }).unwrap()
+    }
+
+    fn ui_grid(lower_price: &str, upper_price: &str) -> GridUiConfig {
+        GridUiConfig {
+            lower_price: lower_price.to_string(),
+            upper_price: upper_price.to_string(),
+            amount_per_grid: "1.5".to_string(),
+            stopping_price_low: None,
+            stopping_price_high: None,
+        }
+    }
+
+    fn test_trader() -> Trader {
+        let token = |symbol: &str, decimals: u64| TokenInfo { symbol: symbol.to_string(), address: "".to_string(), decimals };
+        Trader::new("".to_string(), "".to_string(), token("SOL", 9), token("USDC", 6))
+    }
+
+    #[test]
+    fn half_a_lot_rounds_up_to_the_nearest_lot() {
+        let units = units();
+        assert_eq!(units.price_to_lots("23.455", Rounding::Nearest), Ok(2346));
+        assert_eq!(units.price_to_lots("23.4549", Rounding::Nearest), Ok(2345));
+        assert_eq!(units.price_to_lots("23.455", Rounding::Down), Ok(2345));
+        assert_eq!(units.price_to_lots("23.451", Rounding::Up), Ok(2346));
+        assert_eq!(units.price_to_lots("23.45", Rounding::Up), Ok(2345));
+    }
+
+    #[test]
+    fn leading_and_trailing_zeros_carry_no_precision() {
+        assert_eq!(parse_decimal("0012.3400"), Ok((1234, 2)));
+        assert_eq!(parse_decimal("0.000"), Ok((0, 0)));
+        assert_eq!(units().price_to_lots("023.450", Rounding::Down), Ok(2345));
+        assert_eq!(units().price_to_ui(2340), "23.4");
+    }
+
+    #[test]
+    fn a_side_of_the_dot_may_be_left_out() {
+        assert_eq!(parse_decimal(".5"), Ok((5, 1)));
+        assert_eq!(parse_decimal("5."), Ok((5, 0)));
+        for invalid in [".", "", "1.2.3", "-1", "1e3", "1,5"].iter() {
+            assert_eq!(parse_decimal(invalid), Err(UnitsError::InvalidDecimal(invalid.to_string())));
+        }
+    }
+
+    #[test]
+    fn values_out_of_range_overflow() {
+        let too_many_digits = "1".repeat(MAX_DECIMAL_DIGITS + 1);
+        assert_eq!(parse_decimal(&too_many_digits), Err(UnitsError::Overflow(too_many_digits.clone())));
+        // 10^20 USDC is 10^22 price lots, more than a u64 holds
+        let price = format!("1{}", "0".repeat(20));
+        assert_eq!(units().price_to_lots(&price, Rounding::Down), Err(UnitsError::Overflow(price.clone())));
+        assert_eq!(units().size_to_native(&price), Err(UnitsError::Overflow(price.clone())));
+    }
+
+    #[test]
+    fn sizes_round_down_to_whole_base_lots() {
+        assert_eq!(units().size_to_native("1.0005"), Ok(1_000_000_000));
+        assert!(matches!(units().size_to_native("0.0009"), Err(UnitsError::BelowMinimum(..))));
+    }
+
+    #[test]
+    fn a_range_inverted_by_rounding_is_refused() {
+        let mut trader = test_trader();
+        // the lower price rounds up and the upper one down, both to 23.46
+        let config = ui_grid("23.451", "23.46");
+        assert_eq!(
+            units().apply(&config, &mut trader),
+            Err(UnitsError::EmptyRange("23.451".to_string(), "23.46".to_string())),
+        );
+        let config = ui_grid("23.459", "23.451");
+        assert!(matches!(units().apply(&config, &mut trader), Err(UnitsError::EmptyRange(..))));
+        assert_eq!(trader.lower_price_range, 0);
+    }
+
+    #[test]
+    fn applies_the_range_and_size_to_the_trader() {
+        let mut trader = test_trader();
+        units().apply(&ui_grid("20.001", "25.009"), &mut trader).unwrap();
+        assert_eq!(trader.lower_price_range, 2001);
+        assert_eq!(trader.upper_price_range, 2500);
+        assert_eq!(trader.amount_per_grid, 1_500_000_000);
+    }
+}
@@ -0,0 +1,19 @@
+use rust_base58::FromBase58;
+use solana_program::pubkey::Pubkey;
+
+pub use crate::mongodb::client::MongoClient;
+pub use crate::mongodb::models::TraderStatus;
+
+pub mod workers;
+pub mod mongodb;
+pub mod serum;
+pub mod grid;
+pub mod rpc;
+pub mod backtest;
+pub mod replay;
+pub mod config;
+
+pub fn str_to_pubkey(address: &str) -> Pubkey {
+    let bytes = FromBase58::from_base58(address).unwrap();
+    return Pubkey::new(bytes.as_slice());
+}
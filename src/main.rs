@@ -1,92 +1,212 @@
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use chrono::DateTime;
+use clap::{Parser, Subcommand};
+use mongodb::bson::doc;
 use rust_base58::FromBase58;
+use solana_client::rpc_client::RpcClient;
 use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::signature::Keypair;
 
-use crate::mongodb::client::MongoClient;
-use crate::mongodb::models::TraderStatus;
-use crate::workers::base::{BotConfig, BotThread};
-use crate::workers::cleanup::CleanupThread;
-use crate::workers::message::{ThreadLogLevel, ThreadMessage, ThreadMessageKind, ThreadMessageSource};
-use crate::workers::sync::SyncThread;
-use crate::workers::trade::{TraderData, TraderThread};
+use tradebotv3::config::{Cluster, Settings};
+use tradebotv3::mongodb::client::MongoClient;
+use tradebotv3::mongodb::models::Trader;
+use tradebotv3::rpc::client::RpcConnection;
+use tradebotv3::rpc::paper::PaperCluster;
+use tradebotv3::serum::markets::MarketRegistry;
+use tradebotv3::workers::base::BotConfig;
+use tradebotv3::workers::market_data::MarketDataHub;
+use tradebotv3::workers::message::{ThreadLogLevel, ThreadMessage, ThreadMessageKind, ThreadMessageSource};
+use tradebotv3::workers::registry::TraderRegistry;
 
-pub mod workers;
-pub mod mongodb;
-pub mod serum;
+/// Read when no settings file is given and it exists
+const DEFAULT_SETTINGS_FILE: &str = "gridbot.toml";
 
-const RPC_URL: &str = "https://hedgehog.rpcpool.com";
+/// Grid trading bot for Serum markets
+#[derive(Parser)]
+#[clap(name = "tradebotv3")]
+struct Cli {
+    /// Settings file, gridbot.toml is used when it exists
+    #[clap(long, short)]
+    config: Option<PathBuf>,
+    /// Cluster preset picking the RPC endpoint and the serum program, overrides the settings file
+    #[clap(long)]
+    cluster: Option<Cluster>,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the workers of every trader in the database
+    Run,
+    /// Lists the traders in the database
+    ListTraders,
+    /// Prints a trader and its grids
+    ShowTrader {
+        /// Owner of the trader
+        owner: String,
+        /// Market of the trader, every trader of the owner is printed when unset
+        #[clap(long)]
+        market: Option<String>,
+    },
+    /// Checks the settings and the markets file and prints them resolved against the cluster preset
+    ValidateConfig,
+}
 
-//"https://mango.devnet.rpcpool.com";
 fn main() {
-    let mongo_client = MongoClient::new();
-    let registered_traders_cursor = mongo_client.traders.find(None, None).unwrap();
-    let (thread_message_tx, thread_message_rx) = std::sync::mpsc::channel::<ThreadMessage>();
+    let cli = Cli::parse();
+    let settings = match load_settings(&cli) {
+        Ok(settings) => Arc::new(settings),
+        Err(e) => {
+            eprintln!("[-] {}", e);
+            std::process::exit(1);
+        }
+    };
+    match cli.command {
+        Command::Run => run(settings),
+        Command::ListTraders => list_traders(&settings),
+        Command::ShowTrader { owner, market } => show_trader(&settings, &owner, market),
+        Command::ValidateConfig => validate_config(&settings),
+    }
+}
 
-    for trader_result in registered_traders_cursor {
-        match trader_result {
-            Ok(trader) => {
-                let payer = Keypair::from_base58_string(&trader.trader_keypair);
-                let serum_program: Pubkey = str_to_pubkey("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin"/*"73A1rYyFwTpRzEsGjJc1P45ee7qMo8vXuMZUDC42Wzwe"*/);
-                let market: Pubkey = str_to_pubkey(&trader.market_address);
-                let token_program: Pubkey = str_to_pubkey("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
-                let spl_associated_token_account_program_id: Pubkey = str_to_pubkey(
-                    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"
-                );
-                let bot_config = BotConfig {
-                    serum_program,
-                    token_program,
-                    associated_token_program: spl_associated_token_account_program_id,
-                    trader: trader.clone(),
-                    rpc_url: RPC_URL.to_string(),
-                    fee_payer: payer,
-                };
-                let safe_bot_config = Arc::new(bot_config);
+fn load_settings(cli: &Cli) -> Result<Settings, String> {
+    let mut settings = match &cli.config {
+        Some(path) => Settings::load(path).map_err(|e| e.to_string())?,
+        None if Path::new(DEFAULT_SETTINGS_FILE).exists() => {
+            Settings::load(Path::new(DEFAULT_SETTINGS_FILE)).map_err(|e| e.to_string())?
+        }
+        None => Settings::default(),
+    };
+    if let Some(cluster) = cli.cluster {
+        settings.cluster = cluster;
+    }
+    settings.apply_env();
+    settings.validate().map_err(|e| e.to_string())?;
+    Ok(settings)
+}
 
+fn validate_config(settings: &Settings) {
+    print!("{}", settings);
+    match MarketRegistry::load(&settings.markets_file) {
+        Ok(markets) => println!("[+] {} markets listed", markets.markets().len()),
+        Err(e) => {
+            eprintln!("[-] {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-                let _trader_thread_message_tx = thread_message_tx.clone();
-                let mut trader = TraderThread {
-                    stdout: _trader_thread_message_tx,
-                    config: safe_bot_config.clone(),
-                    data: None,
-                    trader: trader.clone(),
-                };
-                let _trader_thread = std::thread::spawn(move || trader.worker());
-                let sync_thread_message_tx = thread_message_tx.clone();
-                let mut sync = SyncThread {
-                    stdout: sync_thread_message_tx,
-                    config: safe_bot_config.clone(),
-                };
-                let _sync_thread = std::thread::spawn(move || sync.worker());
+/// Loads the markets file and drops the markets that don't match their state on chain
+fn load_markets(settings: &Settings) -> MarketRegistry {
+    let mut markets = match MarketRegistry::load(&settings.markets_file) {
+        Ok(markets) => markets,
+        Err(e) => {
+            eprintln!("[-] {}", e);
+            std::process::exit(1);
+        }
+    };
+    let connection = RpcClient::new_with_timeout_and_commitment(
+        settings.rpc_url(),
+        settings.rpc_timeout(),
+        CommitmentConfig::confirmed(),
+    );
+    for (market, reason) in markets.verify(&connection, &settings.serum_program()) {
+        eprintln!("[-] Dropping market {} ({}): {}", market.name, market.address, reason);
+    }
+    println!("[?] {} markets verified", markets.markets().len());
+    markets
+}
 
+fn list_traders(settings: &Settings) {
+    let mongo_client = MongoClient::connect(&settings.mongodb_url, &settings.database);
+    let traders_cursor = mongo_client.traders.find(None, None).unwrap();
+    for trader_result in traders_cursor {
+        match trader_result {
+            Ok(trader) => println!(
+                "[+] {} {} {}/{} {:?} grids: {} value: {}",
+                trader.market_address,
+                trader.owner,
+                trader.base_token_info.symbol,
+                trader.quote_token_info.symbol,
+                trader.status,
+                trader.grids.len(),
+                trader.value,
+            ),
+            Err(e) => eprintln!("[-] Unreadable trader: {}", e),
+        }
+    }
+}
 
-                let cleanup_thread_message_tx = thread_message_tx.clone();
-                let mut clean_up = CleanupThread {
-                    stdout: cleanup_thread_message_tx,
-                    config: safe_bot_config.clone(),
-                };
-                let _cleanup_thread = std::thread::spawn(move || clean_up.worker());
+fn show_trader(settings: &Settings, owner: &str, market: Option<String>) {
+    let mongo_client = MongoClient::connect(&settings.mongodb_url, &settings.database);
+    let filter = match market {
+        Some(market) => doc! { "owner": owner, "market_address": market },
+        None => doc! { "owner": owner },
+    };
+    let mut found = false;
+    for trader_result in mongo_client.traders.find(filter, None).unwrap() {
+        match trader_result {
+            Ok(trader) => {
+                found = true;
+                println!("[+] Market: {}", trader.market_address);
+                println!("{}", trader);
             }
+            Err(e) => eprintln!("[-] Unreadable trader: {}", e),
+        }
+    }
+    if !found {
+        eprintln!("[-] No trader found for {}", owner);
+        std::process::exit(1);
+    }
+}
 
+fn run(settings: Arc<Settings>) {
+    let (thread_message_tx, thread_message_rx) = std::sync::mpsc::channel::<ThreadMessage>();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let signal_shutdown = shutdown.clone();
+    // SIGINT and SIGTERM, the workers stop after the transaction they are confirming
+    ctrlc::set_handler(move || {
+        if signal_shutdown.swap(true, Ordering::SeqCst) {
+            eprintln!("[-] Exiting without waiting on the workers");
+            std::process::exit(1);
+        }
+        println!("[?] Shutting down, send the signal again to exit right away");
+    }).expect("Error setting the signal handler");
 
-            // let settler_thread_message_tx = thread_message_tx.clone();
-            // let settler = SettlerThread {
-            //     stdout: settler_thread_message_tx,
-            //     config: safe_bot_config.clone(),
-            // };
-            // let _settle_thread = std::thread::spawn(move || settler.worker());
-
-
-
+    let markets = Arc::new(load_markets(&settings));
+    // paper traders see their own orders in their copy of the book and recorded sessions
+    // need every account read of the iteration, both read the market themselves
+    let market_data = if settings.paper_trading || settings.session_dir.is_some() {
+        None
+    } else {
+        Some(MarketDataHub::new(
+            Arc::new(RpcClient::new_with_timeout_and_commitment(
+                settings.rpc_url(),
+                settings.rpc_timeout(),
+                CommitmentConfig::confirmed(),
+            )),
+            settings.serum_program(),
+            settings.market_data_interval(),
+            settings.ws_url(),
+        ))
+    };
+    let (config_settings, config_markets) = (settings.clone(), markets.clone());
+    let registry = TraderRegistry::new(
+        thread_message_tx,
+        settings,
+        markets,
+        Box::new(move |trader: &Trader| bot_config(trader, config_settings.clone(), &config_markets, market_data.clone())),
+    );
+    let _registry_thread = std::thread::spawn(move || registry.run(shutdown));
 
-            Err(_) => {}
-        }
-    }
     let logs_dir = Path::new("logs");
     if !logs_dir.exists() {
         std::fs::create_dir(logs_dir).unwrap();
@@ -110,6 +230,12 @@ fn main() {
                     ThreadMessageSource::Sync => {
                         logs_dir.to_str().unwrap().to_owned() + &*"/sync".to_owned()
                     }
+                    ThreadMessageSource::Initializer => {
+                        logs_dir.to_str().unwrap().to_owned() + &*"/initializer".to_owned()
+                    }
+                    ThreadMessageSource::Supervisor => {
+                        logs_dir.to_str().unwrap().to_owned() + &*"/supervisor".to_owned()
+                    }
                 };
                 let logs_dir_path = Path::new(&logs_dir);
                 if !logs_dir_path.exists() {
@@ -132,47 +258,6 @@ fn main() {
             }
         }
     }
-
-    // let safe_bot_config = Arc::new(bot_config);
-    // let (thread_message_tx, thread_message_rx) = std::sync::mpsc::channel::<ThreadMessage>();
-    // let _trader_thread_message_tx = thread_message_tx.clone();
-    // let trader = TraderThread {
-    //     stdout: _trader_thread_message_tx,
-    //     config: safe_bot_config.clone(),
-    // };
-    // let _trader_thread = std::thread::spawn(move || trader.worker());
-
-    // let settler_thread_message_tx = thread_message_tx.clone();
-    // let settler = SettlerThread {
-    //     stdout: settler_thread_message_tx,
-    //     config: safe_bot_config.clone(),
-    // };
-    // let _settle_thread = std::thread::spawn(move || settler.worker());
-
-    // let sync_thread_message_tx = thread_message_tx.clone();
-    // let sync = SyncThread {
-    //     stdout: sync_thread_message_tx,
-    //     config: safe_bot_config.clone(),
-    // };
-    // let _sync_thread = std::thread::spawn(move || sync.worker());
-
-
-    // let event_consumer_thread_message_tx = thread_message_tx.clone();
-    // let event_consumer = EventConsumer {
-    //     stdout: event_consumer_thread_message_tx,
-    //     config: safe_bot_config.clone(),
-    // };
-    // let _event_consumer_thread = std::thread::spawn(move || event_consumer.worker());
-
-    // let cleanup_thread_message_tx = thread_message_tx.clone();
-    // let clean_up = CleanUpThread {
-    //     stdout: cleanup_thread_message_tx,
-    //     config: safe_bot_config.clone(),
-    // };
-    // let _cleanup_thread = std::thread::spawn(move || clean_up.worker());
-
-
-    println!("Hello, world!");
 }
 
 fn get_log_file(level: ThreadLogLevel, logs_dir: &Path) -> String {
@@ -190,92 +275,41 @@ fn get_log_file(level: ThreadLogLevel, logs_dir: &Path) -> String {
     return path_dir;
 }
 
-//
-// fn account_subscribe(account: &Pubkey) -> Result<AccountSubscription, PubsubClientError> {
-//     return solana_client::pubsub_client::PubsubClient::account_subscribe(
-//         "wss://ninja.genesysgo.net",
-//         account,
-//         Some(RpcAccountInfoConfig {
-//             encoding: Some(UiAccountEncoding::JsonParsed),
-//             data_slice: None,
-//             commitment: Some(CommitmentConfig::finalized()),
-//         }),
-//     );
-// }
-
-fn str_to_pubkey(address: &str) -> Pubkey {
-    let bytes = FromBase58::from_base58(address).unwrap();
-    return Pubkey::new(bytes.as_slice());
+/// Config shared by the workers of `trader`, fails on keys of the trader that don't parse
+fn bot_config(trader: &Trader, settings: Arc<Settings>, markets: &MarketRegistry, market_data: Option<Arc<MarketDataHub>>) -> Result<BotConfig, String> {
+    let payer = FromBase58::from_base58(trader.trader_keypair.as_str())
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|bytes| Keypair::from_bytes(&bytes).map_err(|e| e.to_string()))
+        .map_err(|e| format!("trader keypair is invalid: {}", e))?;
+    let serum_program: Pubkey = settings.serum_program();
+    let market = Pubkey::from_str(&trader.market_address)
+        .map_err(|e| format!("market address {} is invalid: {}", trader.market_address, e))?;
+    let connection = RpcClient::new_with_timeout_and_commitment(
+        settings.rpc_url(),
+        settings.rpc_timeout(),
+        CommitmentConfig::confirmed(),
+    );
+    let connection: Arc<dyn RpcConnection> = if settings.paper_trading {
+        let paper = PaperCluster::new(Arc::new(connection), serum_program, market)
+            .map_err(|e| format!("paper trading on {} failed to start: {}", market, e))?;
+        Arc::new(paper)
+    } else {
+        Arc::new(connection)
+    };
+    Ok(BotConfig {
+        serum_program,
+        token_program: settings.token_program(),
+        associated_token_program: settings.associated_token_program(),
+        trader: trader.clone(),
+        rpc_url: settings.rpc_url(),
+        fee_payer: payer,
+        connection,
+        session_dir: settings.session_dir.clone(),
+        stop: Arc::new(AtomicBool::new(false)),
+        settings,
+        market: markets.find(&trader.market_address).cloned(),
+        market_data,
+    })
 }
 
 
-// #[derive(BorshSerialize, BorshDeserialize, Eq, PartialEq, Debug, Copy, Clone)]
-// pub struct EventQueueHeader {
-//     blob: [u8; 5],
-//     account_flags: u32,
-//     blob1: [u8; 4],
-//     head: u32,
-//     blob2: [u8; 4],
-//     count: u32,
-//     blob3: [u8; 4],
-//     seq_num: u32,
-//     blob4: [u8; 4],
-// }
-//
-// #[derive(BorshSerialize, BorshDeserialize, Eq, PartialEq, Debug, Copy, Clone)]
-// pub struct Event {
-//     pub event_flag: u8,
-//     pub open_orders_slot: u8,
-//     pub fee_tier: u8,
-//     pub blob: [u8; 5],
-//     pub native_quantity_released: u64,
-//     pub native_quantity_paid: u64,
-//     pub native_fee_or_rebate: u64,
-//     pub order_id: u128,
-//     pub open_orders: [u64; 4],
-//     pub client_order_id: u64,
-// }
-//
-// #[derive(BorshSerialize, BorshDeserialize, Eq, PartialEq, Debug, Copy, Clone)]
-// pub enum EventFlags {
-//     Fill = 0x1,
-//     Out = 0x2,
-//     Bid = 0x4,
-//     Maker = 0x8,
-//     ReleaseFunds = 0x10,
-// }
-//
-// pub struct EventQueue {
-//     header: EventQueueHeader,
-//     events: Vec<Event>,
-// }
-//
-// impl EventQueue {
-//     pub fn from_buffer(buffer: &[u8], history: u32) -> Self {
-//         let data_length = buffer.len();
-//         let header_span = 37;
-//         let event_span = 88;
-//         let header = EventQueueHeader::try_from_slice(&buffer[0..header_span]).unwrap();
-//         let alloc_len: u32 = (((data_length - header_span) / event_span) as f32).floor() as u32;
-//         let mut nodes: Vec<Event> = vec![];
-//         for i in 0..min(history, alloc_len) {
-//             let node_index: usize = ((header.head + header.count + alloc_len - 1 - i) % alloc_len)
-//                 .try_into()
-//                 .unwrap();
-//             let starting_index = header_span + (node_index * event_span);
-//             let event = Event::try_from_slice(&buffer[starting_index..starting_index + event_span])
-//                 .unwrap();
-//             nodes.push(event);
-//         }
-//         return EventQueue {
-//             header,
-//             events: nodes,
-//         };
-//     }
-// }
-//
-// impl Event {
-//     fn is_fill(&self) -> bool {
-//         return &self.event_flag == &(0x2 as u8) || &self.event_flag == &(0x5 as u8);
-//     }
-// }
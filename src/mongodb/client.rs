@@ -1,26 +1,41 @@
 use mongodb::sync::Client;
-use crate::mongodb::models::{ Trader};
+use crate::mongodb::models::{BookSnapshot, Fill, Trader};
 
 pub struct MongoClient {
     pub database: mongodb::sync::Database,
     pub traders: mongodb::sync::Collection<Trader>,
+    pub fills: mongodb::sync::Collection<Fill>,
+    pub snapshots: mongodb::sync::Collection<BookSnapshot>,
 }
 
 impl MongoClient {
     pub fn new() -> Self {
+        Self::with_database("gridbot")
+    }
+
+    /// Same server as `new`, different database, so offline tools never write to the live collections
+    pub fn with_database(name: &str) -> Self {
         let mut mongodb_url = "mongodb://localhost:27017/gridbot".to_string();
         match std::env::var("MONGODB_URL") {
             Ok(url) => { mongodb_url = url }
             Err(_) => {}
         }
+        Self::connect(&mongodb_url, name)
+    }
 
-        let client = Client::with_uri_str(&mongodb_url).unwrap();
-        let database = client.database("gridbot");
+    /// Connects to the database named in the settings
+    pub fn connect(mongodb_url: &str, name: &str) -> Self {
+        let client = Client::with_uri_str(mongodb_url).unwrap();
+        let database = client.database(name);
         let traders = database.collection::<Trader>("traders");
+        let fills = database.collection::<Fill>("fills");
+        let snapshots = database.collection::<BookSnapshot>("book_snapshots");
 
         MongoClient {
             database,
             traders,
+            fills,
+            snapshots,
         }
     }
 }
\ No newline at end of file
@@ -1,3 +1,5 @@
+use std::cmp::min;
+
 use serde::{Serialize, Deserialize};
 use serum_dex::matching::Side;
 
@@ -11,6 +13,8 @@ pub struct TokenInfo {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Trader {
     pub market_address: String,
+    /// Market of the registry like "SOL/USDC", resolves `market_address` when that is left empty
+    pub market_name: Option<String>,
     pub base_token_info: TokenInfo,
     pub quote_token_info: TokenInfo,
     pub trader_keypair: String,
@@ -19,12 +23,32 @@ pub struct Trader {
     pub serum_open_orders: Vec<String>,
     pub owner: String,
     pub grids_count: u64,
+    pub grid_spacing: Option<GridSpacing>,
     pub grids: Vec<GridPosition>,
+    /// Sequence number of the last fill applied to `grids`, saved together with them so a
+    /// fill reaches the grids exactly once
+    pub last_fill_seq_num: Option<u64>,
     pub amount_per_grid: u64,
     pub upper_price_range: u64,
     pub lower_price_range: u64,
     pub stopping_price_high: Option<u64>,
     pub stopping_price_low: Option<u64>,
+    /// Range and size in UI units, converted to the fields above when the trader is initialized
+    pub ui_grid: Option<GridUiConfig>,
+    pub min_settle_base: Option<u64>,
+    pub min_settle_quote: Option<u64>,
+    pub consume_events_interval: Option<u64>,
+    /// Price levels per side recorded in book snapshots, nothing is recorded when unset
+    pub record_book_depth: Option<u64>,
+    /// Seconds between book snapshots
+    pub record_book_interval: Option<u64>,
+    pub stop_exit: Option<StopExit>,
+    /// Exit orders sent since the trader started stopping, kept so restarts don't retry forever
+    pub stop_exit_attempts: Option<u32>,
+    pub stop_reason: Option<StopReason>,
+    pub stopped_price: Option<u64>,
+    /// Orders are kept on the book when the bot shuts down unless set otherwise
+    pub shutdown_policy: Option<ShutdownPolicy>,
     pub starting_price_buy: u64,
     pub starting_price_sell: u64,
     pub starting_base_balance: u64,
@@ -42,6 +66,61 @@ pub struct Trader {
     pub status: TraderStatus,
     pub orders: Vec<Order>
 }
+
+impl Trader {
+    /// A registered trader with no grid, balances or settings yet, the rest is filled in
+    /// with struct update syntax by the backtester and the tests
+    pub fn new(market_address: String, owner: String, base_token_info: TokenInfo, quote_token_info: TokenInfo) -> Self {
+        Trader {
+            market_address,
+            market_name: None,
+            base_token_info,
+            quote_token_info,
+            trader_keypair: "".to_string(),
+            base_trader_wallet: "".to_string(),
+            quote_trader_wallet: "".to_string(),
+            serum_open_orders: vec![],
+            owner,
+            grids_count: 0,
+            grid_spacing: None,
+            grids: vec![],
+            last_fill_seq_num: None,
+            amount_per_grid: 0,
+            upper_price_range: 0,
+            lower_price_range: 0,
+            stopping_price_high: None,
+            stopping_price_low: None,
+            ui_grid: None,
+            min_settle_base: None,
+            min_settle_quote: None,
+            consume_events_interval: None,
+            record_book_depth: None,
+            record_book_interval: None,
+            stop_exit: None,
+            stop_exit_attempts: None,
+            stop_reason: None,
+            stopped_price: None,
+            shutdown_policy: None,
+            starting_price_buy: 0,
+            starting_price_sell: 0,
+            starting_base_balance: 0,
+            starting_quote_balance: 0,
+            deposited_base_balance: None,
+            deposited_quote_balance: None,
+            withdrawn_base_balance: None,
+            withdrawn_quote_balance: None,
+            starting_value: 0,
+            base_balance: 0,
+            quote_balance: 0,
+            value: 0,
+            total_txs: 0,
+            register_date: 0,
+            status: TraderStatus::Registered,
+            orders: vec![],
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct OrderPair {
     buy: Order,
@@ -55,7 +134,41 @@ pub struct Order {
     pub client_order_id: u64,
     pub is_filled: bool,
     pub owner: String,
-    pub order_id: String
+    pub order_id: String,
+    /// Base quantity in native units the order was placed with
+    #[serde(default)]
+    pub base_quantity: u64,
+    #[serde(default)]
+    pub filled_base_quantity: u64,
+    #[serde(default)]
+    pub remaining_base_quantity: u64,
+}
+
+impl Order {
+    /// An order about to be placed, owner and order id are filled in once it shows up on the book
+    pub fn new(price: u64, side: Side, client_order_id: u64, base_quantity: u64) -> Self {
+        Order {
+            price,
+            side,
+            client_order_id,
+            is_filled: false,
+            owner: "".to_string(),
+            order_id: "".to_string(),
+            base_quantity,
+            filled_base_quantity: 0,
+            remaining_base_quantity: base_quantity,
+        }
+    }
+
+    /// Records a fill of `base_filled` native base units on the order, never more than its size
+    pub fn apply_fill(&mut self, base_filled: u64) {
+        self.filled_base_quantity = min(self.filled_base_quantity.saturating_add(base_filled), self.base_quantity);
+        self.remaining_base_quantity = self.base_quantity.saturating_sub(self.filled_base_quantity);
+    }
+
+    pub fn is_partially_filled(&self) -> bool {
+        self.filled_base_quantity > 0 && self.remaining_base_quantity > 0
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -63,9 +176,55 @@ pub enum TraderStatus {
     Registered,
     Initialized,
     Decommissioned,
-    Stopped
+    Stopping,
+    Stopped,
+    /// Decommissioned and all of its accounts are closed, nothing runs for it anymore
+    Closed
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum GridSpacing {
+    Arithmetic,
+    Geometric,
 }
 
+/// Grid range and size in UI units, decimal strings like "23.45" so nothing is lost
+/// to floating point before the values are rounded to the market's lots
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GridUiConfig {
+    /// Quote tokens per base token
+    pub lower_price: String,
+    pub upper_price: String,
+    /// Base tokens traded on every grid
+    pub amount_per_grid: String,
+    pub stopping_price_low: Option<String>,
+    pub stopping_price_high: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StopReason {
+    StopLoss,
+    TakeProfit,
+    /// The trader can't be initialized as configured, it waits for the config to be fixed
+    InvalidConfig(String),
+}
+
+/// What to do with the inventory once a stopping price is hit
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum StopExit {
+    Hold,
+    Quote,
+    Base,
+}
+
+/// What happens to the resting orders of a trader when the bot shuts down
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ShutdownPolicy {
+    /// Orders stay on the book and are picked up again on the next start
+    KeepOrders,
+    /// Orders are cancelled and the funds settled before the bot exits
+    CancelOrders,
+}
 
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -74,13 +233,126 @@ pub enum GridStatus {
     Idle,
     AwaitingBuy,
     AwaitingSell,
+    PartiallyFilled,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fill {
+    pub market_address: String,
+    pub owner: String,
+    pub open_orders: String,
+    pub seq_num: u64,
+    pub side: Side,
+    pub maker: bool,
+    pub price: u64,
+    pub native_qty_paid: u64,
+    pub native_qty_received: u64,
+    pub native_fee_or_rebate: u64,
+    pub order_id: String,
+    pub client_order_id: u64,
+    pub grid_price: Option<u64>,
+    pub time: u64,
+}
+
+/// Version of the book snapshot format, bumped on any change to the fields below
+pub const BOOK_SNAPSHOT_VERSION: u32 = 1;
+
+/// All the orders resting at one price, prices in price lots and quantities in base lots
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BookLevel {
+    pub price: u64,
+    pub quantity: u64,
+    pub orders: u64,
+}
+
+/// One of our orders as it was resting on the book
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OwnOrder {
+    pub side: Side,
+    pub price: u64,
+    /// Remaining quantity in base lots
+    pub quantity: u64,
+    pub client_order_id: u64,
+    pub order_id: String,
+}
+
+/// The top of the order book as the trader saw it, stored in the `book_snapshots` collection.
+///
+/// Version 1: `bids` best first down, `asks` best first up, `time` in unix seconds and
+/// `slot` the slot the cluster was at right after the book was read
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BookSnapshot {
+    pub version: u32,
+    pub market_address: String,
+    pub owner: String,
+    pub slot: Option<u64>,
+    pub time: u64,
+    pub bids: Vec<BookLevel>,
+    pub asks: Vec<BookLevel>,
+    pub own_orders: Vec<OwnOrder>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GridPosition {
     pub price: u64,
     pub status: GridStatus,
-    pub order: Option<Order>
+    pub order: Option<Order>,
+    /// Bumped every time an order is placed on this grid
+    #[serde(default)]
+    pub generation: u64,
+    /// Client order id of the last order placed on this grid
+    #[serde(default)]
+    pub client_order_id: Option<u64>,
+    /// Order sent in a transaction that is not confirmed yet, never persisted
+    #[serde(skip)]
+    pub pending: Option<GridIntent>,
+}
+
+/// An order about to be placed on a grid, it only becomes the status and the
+/// order of the grid once the transaction placing it is confirmed
+#[derive(Debug, Clone)]
+pub struct GridIntent {
+    pub status: GridStatus,
+    pub order: Order,
+}
+
+impl GridIntent {
+    pub fn new(order: Order) -> Self {
+        GridIntent {
+            status: if order.side == Side::Bid { GridStatus::AwaitingBuy } else { GridStatus::AwaitingSell },
+            order,
+        }
+    }
+}
+
+impl GridPosition {
+    /// The side of the order resting on this grid, if any
+    pub fn awaiting_side(&self) -> Option<Side> {
+        match self.status {
+            GridStatus::AwaitingBuy => Some(Side::Bid),
+            GridStatus::AwaitingSell => Some(Side::Ask),
+            GridStatus::PartiallyFilled => self.order.as_ref().map(|order| order.side),
+            _ => None
+        }
+    }
+
+    /// Makes the pending order the one resting on this grid, returns whether there was one
+    pub fn commit_pending(&mut self) -> bool {
+        match self.pending.take() {
+            Some(intent) => {
+                self.status = intent.status;
+                self.client_order_id = Some(intent.order.client_order_id);
+                self.order = Some(intent.order);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Drops the pending order, the generation stays bumped so its client order id is never reused
+    pub fn rollback_pending(&mut self) -> bool {
+        self.pending.take().is_some()
+    }
 }
 
 use std::fmt;
@@ -94,7 +366,11 @@ impl fmt::Display for Trader {
         str.push_str(&format!("[+] QuoteBalance: {} Ui: {}\n",  self.quote_balance, self.quote_balance / (10 as u64).pow(self.quote_token_info.decimals as u32)));
         str.push_str("[+] Grids\n");
         for grid in &self.grids {
-            str.push_str(&format!("[ Price: {}, Status: {:?} ]\n", grid.price, grid.status));
+            if let (GridStatus::PartiallyFilled, Some(order)) = (&grid.status, &grid.order) {
+                str.push_str(&format!("[ Price: {}, Status: {:?}, Filled: {}/{} ]\n", grid.price, grid.status, order.filled_base_quantity, order.base_quantity));
+            } else {
+                str.push_str(&format!("[ Price: {}, Status: {:?} ]\n", grid.price, grid.status));
+            }
 
         }
 
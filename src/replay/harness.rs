@@ -0,0 +1,190 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::channel;
+
+use mongodb::bson::doc;
+use mongodb::options::ReplaceOptions;
+use solana_program::account_info::AccountInfo;
+use solana_program::instruction::Instruction;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::config::Settings;
+use crate::mongodb::client::MongoClient;
+use crate::mongodb::models::{GridPosition, Trader};
+use crate::replay::session::{IterationRecord, RecordedInstruction};
+use crate::rpc::client::RpcConnection;
+use crate::rpc::recording::ReplayConnection;
+use crate::str_to_pubkey;
+use crate::workers::base::{BotConfig, BotThread};
+use crate::workers::error::TradeBotResult;
+use crate::workers::fills::FillTracker;
+use crate::workers::recorder::BookRecorder;
+use crate::workers::trade::TraderThread;
+
+/// Writes made while replaying go to this database instead of the live one
+pub const REPLAY_DATABASE: &str = "gridbot_replay";
+
+/// What `setup` and `compile_ixs` make of a recorded iteration
+pub struct ReplayOutcome {
+    pub iteration: u64,
+    pub grids_before: Vec<GridPosition>,
+    /// Grids after `setup` reconciled them with the recorded book, pending intents
+    /// are the orders `compile_ixs` staged
+    pub grids_after: Vec<GridPosition>,
+    pub recorded: Vec<RecordedInstruction>,
+    pub replayed: Vec<RecordedInstruction>,
+    /// Error `setup` or `compile_ixs` failed with in production and in the replay
+    pub recorded_error: Option<String>,
+    pub replayed_error: Option<String>,
+}
+
+impl ReplayOutcome {
+    /// Whether the grid logic still builds the instructions it built in production, or
+    /// still fails where it failed. Errors are compared by whether there was one, the
+    /// recorded reads don't reproduce every message word for word
+    pub fn matches(&self) -> bool {
+        self.recorded == self.replayed && self.recorded_error.is_some() == self.replayed_error.is_some()
+    }
+}
+
+impl fmt::Display for ReplayOutcome {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut str = "".to_string();
+        str.push_str(&format!("[?] Iteration {}\n", self.iteration));
+        for (index, (before, after)) in self.grids_before.iter().zip(self.grids_after.iter()).enumerate() {
+            let staged = match &after.pending {
+                Some(intent) => format!(" -> staged {:?} {:?} {}", intent.status, intent.order.side, intent.order.base_quantity),
+                None => "".to_string(),
+            };
+            str.push_str(&format!(
+                "    grid {:>3} at {:>10}: {:?} -> {:?}{}\n",
+                index, before.price, before.status, after.status, staged,
+            ));
+        }
+        if let Some(error) = &self.recorded_error {
+            str.push_str(&format!("[?] Recorded error: {}\n", error));
+        }
+        if let Some(error) = &self.replayed_error {
+            str.push_str(&format!("[?] Replayed error: {}\n", error));
+        }
+        str.push_str(&format!("[?] {} instructions recorded, {} replayed\n", self.recorded.len(), self.replayed.len()));
+        for index in 0..self.recorded.len().max(self.replayed.len()) {
+            let recorded = self.recorded.get(index);
+            let replayed = self.replayed.get(index);
+            if recorded != replayed {
+                str.push_str(&format!("[-] Instruction {} differs\n    recorded: {:?}\n    replayed: {:?}\n", index, recorded, replayed));
+            }
+        }
+        if self.matches() {
+            str.push_str("[+] Replay matches the recording\n");
+        }
+        write!(fmt, "{}", str)
+    }
+}
+
+/// Runs the trader thread grid logic over a recorded iteration, reads are answered
+/// from the recording and nothing is sent
+pub fn replay_iteration(record: &IterationRecord, mongo_client: &MongoClient) -> Result<ReplayOutcome, String> {
+    let state = record.before.clone()
+        .ok_or_else(|| format!("Iteration {} of the {} thread has no grid state to replay", record.iteration, record.thread))?;
+    let connection = Arc::new(ReplayConnection::new(&record.reads));
+    let market_key = str_to_pubkey(&record.market_address);
+    let serum_program = str_to_pubkey(&record.serum_program);
+    let fee_payer = Keypair::new();
+    let replay_payer = fee_payer.pubkey();
+
+    let mut trader = state.trader.clone();
+    // the recorder would store snapshots of a book from the past
+    trader.record_book_depth = None;
+    let grids_before = trader.grids.clone();
+
+    let (stdout, _messages) = channel();
+    let bot_config = BotConfig {
+        serum_program,
+        token_program: spl_token::id(),
+        associated_token_program: str_to_pubkey("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"),
+        trader: trader.clone(),
+        rpc_url: "".to_string(),
+        fee_payer,
+        connection: connection.clone(),
+        session_dir: None,
+        stop: Arc::new(AtomicBool::new(false)),
+        settings: Arc::new(Settings::default()),
+        market: None,
+        market_data: None,
+    };
+    let mut thread = TraderThread {
+        stdout,
+        config: Arc::new(bot_config),
+        data: None,
+        trader,
+        fills: FillTracker {
+            last_seq_num: state.last_seq_num,
+            polled_seq_num: None,
+        },
+        recorder: BookRecorder::new(),
+        cancelled_on_shutdown: vec![],
+        market_feed: None,
+    };
+
+    let account = connection.get_account(&market_key).map_err(|e| e.to_string())?;
+    let mut account_clone = account.clone();
+    let serum_market_account_info = AccountInfo {
+        key: &market_key,
+        is_signer: false,
+        is_writable: false,
+        lamports: Rc::new(RefCell::new(&mut account_clone.lamports)),
+        data: Rc::new(RefCell::new(&mut account_clone.data)),
+        owner: &account.owner.clone(),
+        executable: false,
+        rent_epoch: account.rent_epoch,
+    };
+    let serum_market = serum_dex::state::Market::load(&serum_market_account_info, &serum_program, true)
+        .map_err(|e| format!("Recorded market account does not load: {:?}", e))?;
+
+    restore_trader(mongo_client, &thread.trader)
+        .map_err(|e| format!("Failed to store the trader of iteration {} in the replay database: {}", record.iteration, e))?;
+    let staged = thread.setup(connection.as_ref(), &serum_market, mongo_client)
+        .and_then(|_| thread.compile_ixs(connection.as_ref(), &serum_market, mongo_client));
+    let (ixs, replayed_error) = match staged {
+        Ok(ixs) => (ixs, None),
+        Err(e) => (vec![], Some(e.to_string())),
+    };
+
+    let recorded_payer = str_to_pubkey(&record.fee_payer);
+    Ok(ReplayOutcome {
+        iteration: record.iteration,
+        grids_before,
+        grids_after: thread.trader.grids.clone(),
+        recorded: record.instructions.clone(),
+        replayed: ixs.iter().map(|ix| with_payer(ix, &replay_payer, &recorded_payer)).collect(),
+        recorded_error: record.error.clone(),
+        replayed_error,
+    })
+}
+
+/// Stores the trader as it was when the iteration started, `setup` reads it from the
+/// database like it did in production
+fn restore_trader(mongo_client: &MongoClient, trader: &Trader) -> TradeBotResult<()> {
+    let filter = doc! {
+        "market_address": trader.market_address.clone(),
+        "owner": trader.owner.clone(),
+    };
+    mongo_client.traders.replace_one(filter, trader, ReplaceOptions::builder().upsert(true).build())?;
+    Ok(())
+}
+
+/// The replay signs with a throwaway keypair, its key is swapped back for the recorded one before comparing
+fn with_payer(ix: &Instruction, replay_payer: &Pubkey, recorded_payer: &Pubkey) -> RecordedInstruction {
+    let mut ix = ix.clone();
+    for meta in ix.accounts.iter_mut() {
+        if meta.pubkey == *replay_payer {
+            meta.pubkey = *recorded_payer;
+        }
+    }
+    RecordedInstruction::from(&ix)
+}
@@ -0,0 +1,199 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::Account;
+
+use crate::mongodb::models::Trader;
+use crate::str_to_pubkey;
+
+/// Version of the session format, bumped on any change to the records below
+pub const SESSION_VERSION: u32 = 2;
+
+/// What a worker thread keeps between iterations, the trader is stored without its keypair
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionState {
+    pub trader: Trader,
+    pub last_seq_num: Option<u64>,
+}
+
+impl SessionState {
+    pub fn new(trader: &Trader, last_seq_num: Option<u64>) -> Self {
+        let mut trader = trader.clone();
+        trader.trader_keypair = "".to_string();
+        SessionState {
+            trader,
+            last_seq_num,
+        }
+    }
+}
+
+/// An account as it was returned to the worker, or the error it got instead
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordedAccount {
+    pub pubkey: String,
+    pub owner: Option<String>,
+    pub lamports: u64,
+    /// Base64 encoded account data
+    pub data: Option<String>,
+    pub executable: bool,
+    pub rent_epoch: u64,
+    pub error: Option<String>,
+}
+
+impl RecordedAccount {
+    pub fn new(pubkey: &Pubkey, result: std::result::Result<&Account, String>) -> Self {
+        match result {
+            Ok(account) => RecordedAccount {
+                pubkey: pubkey.to_string(),
+                owner: Some(account.owner.to_string()),
+                lamports: account.lamports,
+                data: Some(base64::encode(&account.data)),
+                executable: account.executable,
+                rent_epoch: account.rent_epoch,
+                error: None,
+            },
+            Err(error) => RecordedAccount {
+                pubkey: pubkey.to_string(),
+                owner: None,
+                lamports: 0,
+                data: None,
+                executable: false,
+                rent_epoch: 0,
+                error: Some(error),
+            }
+        }
+    }
+
+    pub fn to_account(&self) -> std::result::Result<Account, String> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        Ok(Account {
+            lamports: self.lamports,
+            data: base64::decode(self.data.as_ref().unwrap()).unwrap(),
+            owner: str_to_pubkey(self.owner.as_ref().unwrap()),
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedAccountMeta {
+    pub pubkey: String,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedInstruction {
+    pub program_id: String,
+    pub accounts: Vec<RecordedAccountMeta>,
+    /// Base64 encoded instruction data
+    pub data: String,
+}
+
+impl From<&Instruction> for RecordedInstruction {
+    fn from(instruction: &Instruction) -> Self {
+        RecordedInstruction {
+            program_id: instruction.program_id.to_string(),
+            accounts: instruction.accounts.iter().map(|meta: &AccountMeta| RecordedAccountMeta {
+                pubkey: meta.pubkey.to_string(),
+                is_signer: meta.is_signer,
+                is_writable: meta.is_writable,
+            }).collect(),
+            data: base64::encode(&instruction.data),
+        }
+    }
+}
+
+/// One pass of a worker loop, one json object per line of the session file.
+///
+/// `reads` holds every `get_account` result in the order the worker asked for them,
+/// starting with the market account loaded when the thread started. `confirmed` is
+/// `None` when there was nothing to send, `error` is set when `setup` or `compile_ixs`
+/// failed and nothing was sent. `before` holds the trader as it was stored when the
+/// iteration started. The states are only kept for threads with grid state, the trader thread
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IterationRecord {
+    pub version: u32,
+    pub thread: String,
+    pub iteration: u64,
+    pub time: u64,
+    pub market_address: String,
+    pub serum_program: String,
+    pub fee_payer: String,
+    pub before: Option<SessionState>,
+    pub reads: Vec<RecordedAccount>,
+    pub instructions: Vec<RecordedInstruction>,
+    pub confirmed: Option<bool>,
+    pub error: Option<String>,
+    pub after: Option<SessionState>,
+}
+
+/// Appends the iterations of one worker thread to `<dir>/<market>-<owner>-<thread>.jsonl`
+pub struct SessionRecorder {
+    pub path: PathBuf,
+    writer: BufWriter<File>,
+    iteration: u64,
+}
+
+impl SessionRecorder {
+    pub fn create(dir: &Path, trader: &Trader, thread_name: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}-{}-{}.jsonl", trader.market_address, trader.owner, thread_name.to_lowercase()));
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(SessionRecorder {
+            path,
+            writer: BufWriter::new(file),
+            iteration: 0,
+        })
+    }
+
+    pub fn next_iteration(&mut self) -> u64 {
+        self.iteration += 1;
+        self.iteration
+    }
+
+    pub fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }
+
+    /// Every record is flushed right away so a crash keeps the iterations leading up to it
+    pub fn record(&mut self, record: &IterationRecord) {
+        let result = serde_json::to_writer(&mut self.writer, record)
+            .map_err(|e| Error::new(ErrorKind::Other, e))
+            .and_then(|_| self.writer.write_all(b"\n"))
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = result {
+            eprintln!("[-] Failed to record session iteration {}: {:?}", record.iteration, e);
+        }
+    }
+}
+
+/// Reads every iteration of a session file, in the order they were recorded
+pub fn load_session(path: &Path) -> Result<Vec<IterationRecord>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records = vec![];
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue
+        }
+        let record: IterationRecord = serde_json::from_str(&line)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_number + 1, e)))?;
+        if record.version != SESSION_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("line {}: session version {} is not supported", line_number + 1, record.version),
+            ));
+        }
+        records.push(record);
+    }
+    Ok(records)
+}
@@ -0,0 +1,220 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::RwLock;
+
+use enumflags2::BitFlags;
+use serum_dex::critbit::{LeafNode, Slab};
+use serum_dex::state::{AccountFlag, MarketState, OpenOrders};
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_program::hash::Hash;
+use solana_program::program_option::COption;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{self, Transaction, TransactionError};
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use spl_token::state::AccountState;
+
+use crate::rpc::client::RpcConnection;
+
+const ACCOUNT_HEAD_PADDING: &[u8; 5] = b"serum";
+const ACCOUNT_TAIL_PADDING: &[u8; 7] = b"padding";
+const SLAB_HEADER_LEN: usize = 32;
+const SLAB_NODE_LEN: usize = 72;
+
+/// What happens to the next transaction sent to the mock cluster
+#[derive(Debug, Clone)]
+pub enum ScriptedOutcome {
+    Confirmed,
+    Failed(TransactionError),
+    /// Accepted but never lands, the status stays unknown
+    Dropped,
+}
+
+struct ClusterState {
+    accounts: HashMap<Pubkey, Account>,
+    blockhash: Hash,
+    valid_blockhashes: Vec<Hash>,
+    slot: u64,
+    outcomes: VecDeque<ScriptedOutcome>,
+    statuses: HashMap<Signature, Option<transaction::Result<()>>>,
+    sent: Vec<Transaction>,
+}
+
+/// In-memory cluster holding raw account data. Transactions are recorded, not
+/// executed, and get whatever outcome was scripted for them, confirmed by default
+pub struct MockCluster {
+    state: RwLock<ClusterState>,
+}
+
+impl MockCluster {
+    pub fn new() -> Self {
+        let blockhash = Hash::new_unique();
+        MockCluster {
+            state: RwLock::new(ClusterState {
+                accounts: HashMap::new(),
+                blockhash,
+                valid_blockhashes: vec![blockhash],
+                slot: 0,
+                outcomes: VecDeque::new(),
+                statuses: HashMap::new(),
+                sent: vec![],
+            })
+        }
+    }
+
+    pub fn set_account(&self, pubkey: &Pubkey, account: Account) {
+        self.state.write().unwrap().accounts.insert(*pubkey, account);
+    }
+
+    pub fn remove_account(&self, pubkey: &Pubkey) -> Option<Account> {
+        self.state.write().unwrap().accounts.remove(pubkey)
+    }
+
+    /// Stores `data` as a rent exempt account owned by `owner`
+    pub fn set_account_data(&self, pubkey: &Pubkey, owner: &Pubkey, data: Vec<u8>) {
+        self.set_account(pubkey, Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: *owner,
+            executable: false,
+            rent_epoch: 0,
+        });
+    }
+
+    pub fn set_serum_market(&self, pubkey: &Pubkey, serum_program: &Pubkey, market: &MarketState) {
+        self.set_account_data(pubkey, serum_program, serum_account_data(bytemuck::bytes_of(market)));
+    }
+
+    pub fn set_open_orders(&self, pubkey: &Pubkey, serum_program: &Pubkey, open_orders: &OpenOrders) {
+        self.set_account_data(pubkey, serum_program, serum_account_data(bytemuck::bytes_of(open_orders)));
+    }
+
+    /// Builds a bids or asks account with room for `capacity` nodes holding `orders`
+    pub fn set_slab(&self, pubkey: &Pubkey, serum_program: &Pubkey, side_flag: AccountFlag, capacity: usize, orders: &[LeafNode]) {
+        let flags: BitFlags<AccountFlag> = AccountFlag::Initialized | side_flag;
+        let mut slab_bytes = vec![0u8; SLAB_HEADER_LEN + capacity * SLAB_NODE_LEN];
+        let slab = Slab::new(&mut slab_bytes);
+        for order in orders {
+            slab.insert_leaf(order).unwrap();
+        }
+        let mut data = flags.bits().to_le_bytes().to_vec();
+        data.extend_from_slice(&slab_bytes);
+        self.set_account_data(pubkey, serum_program, serum_account_data(&data));
+    }
+
+    pub fn set_token_account(&self, pubkey: &Pubkey, mint: &Pubkey, owner: &Pubkey, amount: u64) {
+        let token_account = spl_token::state::Account {
+            mint: *mint,
+            owner: *owner,
+            amount,
+            delegate: COption::None,
+            state: AccountState::Initialized,
+            is_native: COption::None,
+            delegated_amount: 0,
+            close_authority: COption::None,
+        };
+        let mut data = vec![0u8; spl_token::state::Account::LEN];
+        spl_token::state::Account::pack(token_account, &mut data).unwrap();
+        self.set_account_data(pubkey, &spl_token::id(), data);
+    }
+
+    /// Queues the outcome of the next transaction sent, outcomes are used in order
+    pub fn push_outcome(&self, outcome: ScriptedOutcome) {
+        self.state.write().unwrap().outcomes.push_back(outcome);
+    }
+
+    /// Moves to a new blockhash, the previous ones stop being valid
+    pub fn expire_blockhash(&self) -> Hash {
+        let mut state = self.state.write().unwrap();
+        state.blockhash = Hash::new_unique();
+        state.valid_blockhashes = vec![state.blockhash];
+        state.slot += 1;
+        state.blockhash
+    }
+
+    /// Every transaction sent so far, in order
+    pub fn sent_transactions(&self) -> Vec<Transaction> {
+        self.state.read().unwrap().sent.clone()
+    }
+}
+
+/// Wraps account bytes in the padding serum puts around all of its accounts
+fn serum_account_data(bytes: &[u8]) -> Vec<u8> {
+    let mut data = ACCOUNT_HEAD_PADDING.to_vec();
+    data.extend_from_slice(bytes);
+    data.extend_from_slice(ACCOUNT_TAIL_PADDING);
+    data
+}
+
+fn custom_error(message: String) -> ClientError {
+    ClientError::from(ClientErrorKind::Custom(message))
+}
+
+impl RpcConnection for MockCluster {
+    fn get_account(&self, pubkey: &Pubkey) -> ClientResult<Account> {
+        self.state.read().unwrap().accounts.get(pubkey).cloned()
+            .ok_or_else(|| custom_error(format!("AccountNotFound: pubkey={}", pubkey)))
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        let state = self.state.read().unwrap();
+        Ok(pubkeys.iter().map(|pubkey| state.accounts.get(pubkey).cloned()).collect())
+    }
+
+    fn get_minimum_balance_for_rent_exemption(&self, data_len: usize) -> ClientResult<u64> {
+        Ok(Rent::default().minimum_balance(data_len))
+    }
+
+    fn get_slot(&self) -> ClientResult<u64> {
+        Ok(self.state.read().unwrap().slot)
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        Ok(self.state.read().unwrap().blockhash)
+    }
+
+    fn get_latest_blockhash_with_commitment(&self, _commitment: CommitmentConfig) -> ClientResult<(Hash, u64)> {
+        let state = self.state.read().unwrap();
+        Ok((state.blockhash, state.slot + 150))
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash, _commitment: CommitmentConfig) -> ClientResult<bool> {
+        Ok(self.state.read().unwrap().valid_blockhashes.contains(blockhash))
+    }
+
+    fn send_transaction(&self, transaction: &Transaction) -> ClientResult<Signature> {
+        let mut state = self.state.write().unwrap();
+        if !state.valid_blockhashes.contains(&transaction.message.recent_blockhash) {
+            return Err(ClientError::from(ClientErrorKind::TransactionError(TransactionError::BlockhashNotFound)));
+        }
+        let signature = transaction.signatures.get(0).cloned().unwrap_or_default();
+        let status = match state.outcomes.pop_front().unwrap_or(ScriptedOutcome::Confirmed) {
+            ScriptedOutcome::Confirmed => Some(Ok(())),
+            ScriptedOutcome::Failed(err) => Some(Err(err)),
+            ScriptedOutcome::Dropped => None,
+        };
+        state.statuses.insert(signature, status);
+        state.sent.push(transaction.clone());
+        Ok(signature)
+    }
+
+    fn get_signature_status_with_commitment(
+        &self,
+        signature: &Signature,
+        _commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        Ok(self.state.read().unwrap().statuses.get(signature).cloned().flatten())
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        _config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        Err(custom_error(format!("Transaction details of {} are not kept by the mock cluster", signature)))
+    }
+}
@@ -0,0 +1,5 @@
+pub mod client;
+pub mod mock;
+pub mod paper;
+pub mod recording;
+pub mod subscription;
@@ -0,0 +1,641 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::mem::size_of;
+use std::num::NonZeroU64;
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+
+use enumflags2::BitFlags;
+use serum_dex::critbit::{LeafNode, Slab, SlabView};
+use serum_dex::instruction::{MarketInstruction, SelfTradeBehavior};
+use serum_dex::matching::{OrderType, Side};
+use serum_dex::state::{AccountFlag, MarketState, OpenOrders};
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_program::account_info::AccountInfo;
+use solana_program::entrypoint::ProgramResult;
+use solana_program::hash::Hash;
+use solana_program::instruction::{AccountMeta, Instruction, InstructionError};
+use solana_program::program_error::ProgramError;
+use solana_program::program_option::COption;
+use solana_program::program_pack::Pack;
+use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_program::{system_program, sysvar};
+use solana_sdk::account::{create_account_for_test, Account};
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{self, Transaction, TransactionError};
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use spl_token::state::AccountState;
+
+use crate::rpc::client::RpcConnection;
+use crate::str_to_pubkey;
+
+/// Offset of the serum account data after the head padding
+const SERUM_HEAD: usize = 5;
+const SERUM_TAIL: usize = 7;
+/// Offset of the slab after the head padding and the account flags of a bids or asks account
+const SLAB_START: usize = SERUM_HEAD + 8;
+/// Live orders crossing the paper orders are replayed against them at most this often
+const PAPER_SYNC_INTERVAL: Duration = Duration::from_secs(1);
+/// Balance of the simulated counterparty, it never runs out
+const SIMULATOR_BALANCE: u64 = u64::MAX / 4;
+/// Statuses nobody asked for at confirmed commitment, like those of failed transactions, are dropped after this long
+const STATUS_RETENTION: Duration = Duration::from_secs(120);
+
+static INSTALL_STUBS: Once = Once::new();
+
+/// Routes the token transfers serum makes through cross program invocations to
+/// the spl token processor, everything runs in-process
+struct PaperSyscallStubs;
+
+impl SyscallStubs for PaperSyscallStubs {
+    fn sol_log(&self, _message: &str) {}
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        if instruction.program_id != spl_token::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut infos = vec![];
+        for meta in &instruction.accounts {
+            let mut info = account_infos
+                .iter()
+                .find(|info| info.key == &meta.pubkey)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?
+                .clone();
+            // the vault signer signs with seeds, those are trusted here
+            info.is_signer = info.is_signer || meta.is_signer;
+            infos.push(info);
+        }
+        spl_token::processor::Processor::process(&instruction.program_id, &infos, &instruction.data)
+    }
+}
+
+/// Addresses taken from the market state
+struct MarketAccounts {
+    bids: Pubkey,
+    asks: Pubkey,
+    event_q: Pubkey,
+    req_q: Pubkey,
+    coin_vault: Pubkey,
+    pc_vault: Pubkey,
+    coin_mint: Pubkey,
+    pc_mint: Pubkey,
+}
+
+/// Counterparty used to replay live orders crossing the paper orders
+struct Simulator {
+    owner: Pubkey,
+    open_orders: Pubkey,
+    coin_wallet: Pubkey,
+    pc_wallet: Pubkey,
+}
+
+struct PaperState {
+    /// Accounts written by paper transactions, they shadow the live ones from then on
+    accounts: HashMap<Pubkey, Account>,
+    /// Open orders accounts that placed paper orders
+    paper_owners: HashSet<Pubkey>,
+    resting_bids: Vec<LeafNode>,
+    resting_asks: Vec<LeafNode>,
+    /// Live orders already replayed against the paper orders, as long as they rest on the live book
+    replayed: HashSet<u128>,
+    statuses: HashMap<Signature, (transaction::Result<()>, Instant)>,
+    last_sync: Option<Instant>,
+}
+
+/// Runs transactions against local copies of the accounts of a market with the
+/// serum and spl token processors. The live cluster is only ever read, the book
+/// readers see is the live one with the paper orders added to it, and live orders
+/// crossing a paper order are replayed as taker orders from a simulated counterparty
+pub struct PaperCluster {
+    live: Arc<dyn RpcConnection>,
+    serum_program: Pubkey,
+    market: Pubkey,
+    market_accounts: MarketAccounts,
+    simulator: Simulator,
+    state: Mutex<PaperState>,
+}
+
+fn words_to_pubkey(words: [u64; 4]) -> Pubkey {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    Pubkey::new(&bytes)
+}
+
+fn custom_error(message: String) -> ClientError {
+    ClientError::from(ClientErrorKind::Custom(message))
+}
+
+fn empty_account() -> Account {
+    Account {
+        lamports: 0,
+        data: vec![],
+        owner: system_program::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    let mut data = vec![0u8; spl_token::state::Account::LEN];
+    spl_token::state::Account::pack(spl_token::state::Account {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        delegate: COption::None,
+        state: AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
+    }, &mut data).unwrap();
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: spl_token::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn slab_bytes(data: &mut [u8]) -> &mut Slab {
+    let end = data.len() - SERUM_TAIL;
+    Slab::new(&mut data[SLAB_START..end])
+}
+
+fn slab_leaves(data: &[u8]) -> Vec<LeafNode> {
+    let mut data = data.to_vec();
+    let slab = slab_bytes(&mut data);
+    let mut leaves = vec![];
+    for i in 0..slab.capacity() {
+        if let Some(node) = slab.get(i as u32) {
+            if let Some(leaf) = node.as_leaf() {
+                leaves.push(*leaf);
+            }
+        }
+    }
+    leaves
+}
+
+fn leaf_price(leaf: &LeafNode) -> u64 {
+    leaf.price().get()
+}
+
+/// Decompiles the instructions of a transaction, every account is writable as far as the processors care
+fn transaction_instructions(transaction: &Transaction) -> Vec<Instruction> {
+    let message = &transaction.message;
+    message.instructions.iter().map(|ix| Instruction {
+        program_id: message.account_keys[ix.program_id_index as usize],
+        accounts: ix.accounts.iter().map(|index| {
+            let index = *index as usize;
+            AccountMeta::new(message.account_keys[index], message.is_signer(index))
+        }).collect(),
+        data: ix.data.clone(),
+    }).collect()
+}
+
+impl PaperCluster {
+    pub fn new(live: Arc<dyn RpcConnection>, serum_program: Pubkey, market: Pubkey) -> ClientResult<Self> {
+        INSTALL_STUBS.call_once(|| {
+            set_syscall_stubs(Box::new(PaperSyscallStubs));
+        });
+        let market_account = live.get_account(&market)?;
+        if market_account.data.len() < SERUM_HEAD + size_of::<MarketState>() + SERUM_TAIL {
+            return Err(custom_error(format!("{} is not a serum market", market)));
+        }
+        let market_state: MarketState = bytemuck::pod_read_unaligned(
+            &market_account.data[SERUM_HEAD..SERUM_HEAD + size_of::<MarketState>()],
+        );
+        let market_accounts = MarketAccounts {
+            bids: words_to_pubkey(market_state.bids),
+            asks: words_to_pubkey(market_state.asks),
+            event_q: words_to_pubkey(market_state.event_q),
+            req_q: words_to_pubkey(market_state.req_q),
+            coin_vault: words_to_pubkey(market_state.coin_vault),
+            pc_vault: words_to_pubkey(market_state.pc_vault),
+            coin_mint: words_to_pubkey(market_state.coin_mint),
+            pc_mint: words_to_pubkey(market_state.pc_mint),
+        };
+        let simulator = Simulator {
+            owner: Pubkey::new_unique(),
+            open_orders: Pubkey::new_unique(),
+            coin_wallet: Pubkey::new_unique(),
+            pc_wallet: Pubkey::new_unique(),
+        };
+
+        let mut accounts = HashMap::new();
+        accounts.insert(simulator.coin_wallet, token_account(&market_accounts.coin_mint, &simulator.owner, SIMULATOR_BALANCE));
+        accounts.insert(simulator.pc_wallet, token_account(&market_accounts.pc_mint, &simulator.owner, SIMULATOR_BALANCE));
+        accounts.insert(simulator.open_orders, Self::simulator_open_orders(&serum_program, &market, &simulator.owner));
+
+        println!("[?] Paper trading on {}, the live book is only read", market);
+        Ok(PaperCluster {
+            live,
+            serum_program,
+            market,
+            market_accounts,
+            simulator,
+            state: Mutex::new(PaperState {
+                accounts,
+                paper_owners: HashSet::new(),
+                resting_bids: vec![],
+                resting_asks: vec![],
+                replayed: HashSet::new(),
+                statuses: HashMap::new(),
+                last_sync: None,
+            }),
+        })
+    }
+
+    fn simulator_open_orders(serum_program: &Pubkey, market: &Pubkey, owner: &Pubkey) -> Account {
+        let mut open_orders: OpenOrders = bytemuck::Zeroable::zeroed();
+        let flags: BitFlags<AccountFlag> = AccountFlag::Initialized | AccountFlag::OpenOrders;
+        open_orders.account_flags = flags.bits();
+        open_orders.market = bytemuck::cast(market.to_bytes());
+        open_orders.owner = bytemuck::cast(owner.to_bytes());
+        open_orders.free_slot_bits = !0;
+        let mut data = b"serum".to_vec();
+        data.extend_from_slice(bytemuck::bytes_of(&open_orders));
+        data.extend_from_slice(b"padding");
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: *serum_program,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    /// Local copy of an account, falling back to the live one. The queues start out
+    /// empty so only paper events ever end up on them
+    fn load(&self, state: &mut PaperState, pubkey: &Pubkey) -> Option<Account> {
+        if let Some(account) = state.accounts.get(pubkey) {
+            return Some(account.clone());
+        }
+        if *pubkey == sysvar::rent::id() {
+            return Some(create_account_for_test(&Rent::default()));
+        }
+        let mut account = self.live.get_account(pubkey).ok()?;
+        if *pubkey == self.market_accounts.event_q || *pubkey == self.market_accounts.req_q {
+            // head and count follow the account flags in both queue headers
+            for byte in account.data[SERUM_HEAD + 8..SERUM_HEAD + 24].iter_mut() {
+                *byte = 0;
+            }
+            state.accounts.insert(*pubkey, account.clone());
+        }
+        Some(account)
+    }
+
+    /// The live bids or asks with the paper orders inserted, or only the paper orders
+    fn load_book(&self, state: &PaperState, pubkey: &Pubkey, with_live_orders: bool) -> Option<Account> {
+        let mut account = self.live.get_account(pubkey).ok()?;
+        if !with_live_orders {
+            let end = account.data.len() - SERUM_TAIL;
+            for byte in account.data[SLAB_START..end].iter_mut() {
+                *byte = 0;
+            }
+        }
+        let resting = if *pubkey == self.market_accounts.bids { &state.resting_bids } else { &state.resting_asks };
+        let slab = slab_bytes(&mut account.data);
+        for leaf in resting {
+            if slab.insert_leaf(leaf).is_err() {
+                eprintln!("[-] Paper order {} does not fit on the book", leaf.order_id());
+            }
+        }
+        Some(account)
+    }
+
+    fn is_book(&self, pubkey: &Pubkey) -> bool {
+        *pubkey == self.market_accounts.bids || *pubkey == self.market_accounts.asks
+    }
+
+    /// Runs a single instruction on `accounts`, accounts it creates are added to the map
+    fn execute_instruction(&self, accounts: &mut HashMap<Pubkey, Account>, ix: &Instruction) -> ProgramResult {
+        if ix.program_id == system_program::id() {
+            return self.execute_system_instruction(accounts, ix);
+        }
+        if ix.program_id == spl_associated_token_account_id() {
+            let associated_account = ix.accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?.pubkey;
+            let wallet = ix.accounts.get(2).ok_or(ProgramError::NotEnoughAccountKeys)?.pubkey;
+            let mint = ix.accounts.get(3).ok_or(ProgramError::NotEnoughAccountKeys)?.pubkey;
+            if accounts.contains_key(&associated_account) {
+                return Err(ProgramError::AccountAlreadyInitialized);
+            }
+            accounts.insert(associated_account, token_account(&mint, &wallet, 0));
+            return Ok(());
+        }
+
+        let mut keyed: Vec<(Pubkey, bool, Account)> = vec![];
+        for meta in &ix.accounts {
+            match keyed.iter_mut().find(|(key, _, _)| *key == meta.pubkey) {
+                Some(existing) => existing.1 = existing.1 || meta.is_signer,
+                None => {
+                    let account = accounts.remove(&meta.pubkey).unwrap_or_else(empty_account);
+                    keyed.push((meta.pubkey, meta.is_signer, account));
+                }
+            }
+        }
+        let result = {
+            let unique_infos: Vec<AccountInfo> = keyed
+                .iter_mut()
+                .map(|(key, is_signer, account)| AccountInfo::new(
+                    key,
+                    *is_signer,
+                    true,
+                    &mut account.lamports,
+                    &mut account.data,
+                    &account.owner,
+                    account.executable,
+                    account.rent_epoch,
+                ))
+                .collect();
+            let infos: Vec<AccountInfo> = ix.accounts
+                .iter()
+                .map(|meta| unique_infos.iter().find(|info| info.key == &meta.pubkey).unwrap().clone())
+                .collect();
+            if ix.program_id == self.serum_program {
+                serum_dex::state::State::process(&ix.program_id, &infos, &ix.data).map_err(ProgramError::from)
+            } else if ix.program_id == spl_token::id() {
+                spl_token::processor::Processor::process(&ix.program_id, &infos, &ix.data)
+            } else {
+                Err(ProgramError::IncorrectProgramId)
+            }
+        };
+        for (key, _, account) in keyed {
+            accounts.insert(key, account);
+        }
+        result
+    }
+
+    /// Only account creation, transfers of lamports are not tracked
+    fn execute_system_instruction(&self, accounts: &mut HashMap<Pubkey, Account>, ix: &Instruction) -> ProgramResult {
+        let data = &ix.data;
+        let read_u64 = |offset: usize| -> Result<u64, ProgramError> {
+            data.get(offset..offset + 8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .ok_or(ProgramError::InvalidInstructionData)
+        };
+        let read_pubkey = |offset: usize| -> Result<Pubkey, ProgramError> {
+            data.get(offset..offset + 32)
+                .map(Pubkey::new)
+                .ok_or(ProgramError::InvalidInstructionData)
+        };
+        let variant = data.get(0..4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or(ProgramError::InvalidInstructionData)?;
+        let (lamports, space, owner) = match variant {
+            // CreateAccount
+            0 => (read_u64(4)?, read_u64(12)?, read_pubkey(20)?),
+            // Transfer
+            2 => return Ok(()),
+            // CreateAccountWithSeed
+            3 => {
+                let seed_len = read_u64(36)? as usize;
+                let offset = 44 + seed_len;
+                (read_u64(offset)?, read_u64(offset + 8)?, read_pubkey(offset + 16)?)
+            }
+            _ => return Err(ProgramError::InvalidInstructionData)
+        };
+        let new_account = ix.accounts.get(1).ok_or(ProgramError::NotEnoughAccountKeys)?.pubkey;
+        if accounts.get(&new_account).map_or(false, |account| !account.data.is_empty()) {
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+        accounts.insert(new_account, Account {
+            lamports,
+            data: vec![0u8; space as usize],
+            owner,
+            executable: false,
+            rent_epoch: 0,
+        });
+        Ok(())
+    }
+
+    /// Runs all the instructions of a transaction on a working copy that is only kept if all of them succeed
+    fn execute(&self, state: &mut PaperState, instructions: &[Instruction], with_live_orders: bool) -> transaction::Result<()> {
+        let mut loaded: HashMap<Pubkey, Account> = HashMap::new();
+        for ix in instructions {
+            for meta in &ix.accounts {
+                if loaded.contains_key(&meta.pubkey) {
+                    continue
+                }
+                let account = if self.is_book(&meta.pubkey) {
+                    self.load_book(state, &meta.pubkey, with_live_orders)
+                } else {
+                    self.load(state, &meta.pubkey)
+                };
+                if let Some(account) = account {
+                    loaded.insert(meta.pubkey, account);
+                }
+            }
+        }
+
+        let mut working = loaded.clone();
+        let mut new_owners = vec![];
+        for (i, ix) in instructions.iter().enumerate() {
+            if ix.program_id == self.serum_program {
+                if let Some(MarketInstruction::NewOrderV3(_)) = MarketInstruction::unpack(&ix.data) {
+                    let open_orders = ix.accounts[1].pubkey;
+                    if open_orders != self.simulator.open_orders {
+                        new_owners.push(open_orders);
+                    }
+                }
+            }
+            self.execute_instruction(&mut working, ix).map_err(|err| {
+                TransactionError::InstructionError(i as u8, InstructionError::from(u64::from(err)))
+            })?;
+        }
+
+        state.paper_owners.extend(new_owners);
+        for (pubkey, account) in working {
+            if self.is_book(&pubkey) {
+                let paper_orders: Vec<LeafNode> = slab_leaves(&account.data)
+                    .into_iter()
+                    .filter(|leaf| state.paper_owners.contains(&words_to_pubkey(leaf.owner())))
+                    .collect();
+                if pubkey == self.market_accounts.bids {
+                    state.resting_bids = paper_orders;
+                } else {
+                    state.resting_asks = paper_orders;
+                }
+                continue
+            }
+            let changed = match loaded.get(&pubkey) {
+                Some(before) => before.data != account.data || before.lamports != account.lamports || before.owner != account.owner,
+                None => true
+            };
+            if changed {
+                state.accounts.insert(pubkey, account);
+            }
+        }
+        Ok(())
+    }
+
+    /// Replays live orders that crossed the paper orders since the last sync as an
+    /// immediate or cancel order from the simulator, filling the paper orders as makers
+    fn sync_book(&self, state: &mut PaperState) {
+        if state.last_sync.map_or(false, |last_sync| last_sync.elapsed() < PAPER_SYNC_INTERVAL) {
+            return
+        }
+        state.last_sync = Some(Instant::now());
+        let live_bids = match self.live.get_account(&self.market_accounts.bids) {
+            Ok(account) => slab_leaves(&account.data),
+            Err(_) => return
+        };
+        let live_asks = match self.live.get_account(&self.market_accounts.asks) {
+            Ok(account) => slab_leaves(&account.data),
+            Err(_) => return
+        };
+        // a live order that left the book never crosses again
+        let live_orders: HashSet<u128> = live_bids.iter().chain(live_asks.iter()).map(|leaf| leaf.order_id()).collect();
+        state.replayed.retain(|order_id| live_orders.contains(order_id));
+
+        let best_paper_bid = state.resting_bids.iter().map(leaf_price).max();
+        let best_paper_ask = state.resting_asks.iter().map(leaf_price).min();
+        let crossing_asks: Vec<LeafNode> = live_asks
+            .into_iter()
+            .filter(|leaf| best_paper_bid.map_or(false, |bid| leaf_price(leaf) <= bid) && !state.replayed.contains(&leaf.order_id()))
+            .collect();
+        let crossing_bids: Vec<LeafNode> = live_bids
+            .into_iter()
+            .filter(|leaf| best_paper_ask.map_or(false, |ask| leaf_price(leaf) >= ask) && !state.replayed.contains(&leaf.order_id()))
+            .collect();
+
+        for (side, crossing) in vec![(Side::Ask, crossing_asks), (Side::Bid, crossing_bids)] {
+            if crossing.is_empty() {
+                continue
+            }
+            let quantity: u64 = crossing.iter().map(|leaf| leaf.quantity()).sum();
+            let limit_price = match side {
+                Side::Ask => crossing.iter().map(leaf_price).min().unwrap(),
+                Side::Bid => crossing.iter().map(leaf_price).max().unwrap(),
+            };
+            let replay_ix = self.make_replay_order_ix(side, limit_price, quantity);
+            match self.execute(state, &[replay_ix], false) {
+                Ok(_) => {
+                    println!("[?] Replayed {} live lots {:?} at {} against the paper orders", quantity, side, limit_price);
+                    state.replayed.extend(crossing.iter().map(|leaf| leaf.order_id()));
+                }
+                Err(e) => eprintln!("[-] Failed to replay live orders: {:?}", e)
+            }
+        }
+    }
+
+    fn make_replay_order_ix(&self, side: Side, limit_price: u64, max_coin_qty: u64) -> Instruction {
+        serum_dex::instruction::new_order(
+            &self.market,
+            &self.simulator.open_orders,
+            &self.market_accounts.req_q,
+            &self.market_accounts.event_q,
+            &self.market_accounts.bids,
+            &self.market_accounts.asks,
+            if side == Side::Bid { &self.simulator.pc_wallet } else { &self.simulator.coin_wallet },
+            &self.simulator.owner,
+            &self.market_accounts.coin_vault,
+            &self.market_accounts.pc_vault,
+            &spl_token::id(),
+            &sysvar::rent::id(),
+            None,
+            &self.serum_program,
+            side,
+            NonZeroU64::new(limit_price.max(1)).unwrap(),
+            NonZeroU64::new(max_coin_qty.max(1)).unwrap(),
+            OrderType::ImmediateOrCancel,
+            0,
+            SelfTradeBehavior::DecrementTake,
+            u16::MAX,
+            NonZeroU64::new(SIMULATOR_BALANCE).unwrap(),
+        ).unwrap()
+    }
+}
+
+fn spl_associated_token_account_id() -> Pubkey {
+    str_to_pubkey("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL")
+}
+
+impl RpcConnection for PaperCluster {
+    fn get_account(&self, pubkey: &Pubkey) -> ClientResult<Account> {
+        let mut state = self.state.lock().unwrap();
+        if self.is_book(pubkey) || *pubkey == self.market_accounts.event_q {
+            self.sync_book(&mut state);
+        }
+        let account = if self.is_book(pubkey) {
+            self.load_book(&state, pubkey, true)
+        } else {
+            self.load(&mut state, pubkey)
+        };
+        account.ok_or_else(|| custom_error(format!("AccountNotFound: pubkey={}", pubkey)))
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        let mut state = self.state.lock().unwrap();
+        if pubkeys.iter().any(|pubkey| self.is_book(pubkey) || *pubkey == self.market_accounts.event_q) {
+            self.sync_book(&mut state);
+        }
+        Ok(pubkeys.iter().map(|pubkey| {
+            if self.is_book(pubkey) {
+                self.load_book(&state, pubkey, true)
+            } else {
+                self.load(&mut state, pubkey)
+            }
+        }).collect())
+    }
+
+    fn get_minimum_balance_for_rent_exemption(&self, data_len: usize) -> ClientResult<u64> {
+        Ok(Rent::default().minimum_balance(data_len))
+    }
+
+    fn get_slot(&self) -> ClientResult<u64> {
+        self.live.get_slot()
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        self.live.get_latest_blockhash()
+    }
+
+    fn get_latest_blockhash_with_commitment(&self, commitment: CommitmentConfig) -> ClientResult<(Hash, u64)> {
+        self.live.get_latest_blockhash_with_commitment(commitment)
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash, commitment: CommitmentConfig) -> ClientResult<bool> {
+        self.live.is_blockhash_valid(blockhash, commitment)
+    }
+
+    fn send_transaction(&self, transaction: &Transaction) -> ClientResult<Signature> {
+        let signature = transaction.signatures.get(0).cloned().unwrap_or_default();
+        let mut state = self.state.lock().unwrap();
+        let result = self.execute(&mut state, &transaction_instructions(transaction), true);
+        if let Err(e) = &result {
+            eprintln!("[-] Paper transaction {} failed: {:?}", signature, e);
+        }
+        state.statuses.retain(|_, (_, sent)| sent.elapsed() < STATUS_RETENTION);
+        state.statuses.insert(signature, (result, Instant::now()));
+        Ok(signature)
+    }
+
+    fn get_signature_status_with_commitment(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        let mut state = self.state.lock().unwrap();
+        if commitment.is_at_least_confirmed() {
+            // paper transactions are final right away, nobody asks again once they are confirmed
+            return Ok(state.statuses.remove(signature).map(|(result, _)| result));
+        }
+        Ok(state.statuses.get(signature).map(|(result, _)| result.clone()))
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        _config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        Err(custom_error(format!("{} is a paper transaction, it has no on-chain details", signature)))
+    }
+}
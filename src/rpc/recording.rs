@@ -0,0 +1,192 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_program::hash::Hash;
+use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
+use solana_sdk::account::Account;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use solana_sdk::transaction::{self, Transaction};
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+
+use crate::replay::session::RecordedAccount;
+use crate::rpc::client::RpcConnection;
+use crate::str_to_pubkey;
+
+fn custom_error(message: String) -> ClientError {
+    ClientError::from(ClientErrorKind::Custom(message))
+}
+
+/// Passes everything through to `inner` and keeps every account read, one is
+/// created for each worker iteration
+pub struct RecordingConnection {
+    inner: Arc<dyn RpcConnection>,
+    reads: Mutex<Vec<RecordedAccount>>,
+}
+
+impl RecordingConnection {
+    pub fn new(inner: Arc<dyn RpcConnection>) -> Self {
+        RecordingConnection {
+            inner,
+            reads: Mutex::new(vec![]),
+        }
+    }
+
+    /// Records an account the worker read before the iteration started
+    pub fn record_read(&self, pubkey: &Pubkey, account: &Account) {
+        self.reads.lock().unwrap().push(RecordedAccount::new(pubkey, Ok(account)));
+    }
+
+    pub fn take_reads(&self) -> Vec<RecordedAccount> {
+        std::mem::take(&mut *self.reads.lock().unwrap())
+    }
+}
+
+impl RpcConnection for RecordingConnection {
+    fn get_account(&self, pubkey: &Pubkey) -> ClientResult<Account> {
+        let result = self.inner.get_account(pubkey);
+        let recorded = match &result {
+            Ok(account) => RecordedAccount::new(pubkey, Ok(account)),
+            Err(e) => RecordedAccount::new(pubkey, Err(e.to_string())),
+        };
+        self.reads.lock().unwrap().push(recorded);
+        result
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        let result = self.inner.get_multiple_accounts(pubkeys);
+        if let Ok(accounts) = &result {
+            let mut reads = self.reads.lock().unwrap();
+            for (pubkey, account) in pubkeys.iter().zip(accounts) {
+                reads.push(match account {
+                    Some(account) => RecordedAccount::new(pubkey, Ok(account)),
+                    None => RecordedAccount::new(pubkey, Err(format!("AccountNotFound: pubkey={}", pubkey))),
+                });
+            }
+        }
+        result
+    }
+
+    fn get_minimum_balance_for_rent_exemption(&self, data_len: usize) -> ClientResult<u64> {
+        self.inner.get_minimum_balance_for_rent_exemption(data_len)
+    }
+
+    fn get_slot(&self) -> ClientResult<u64> {
+        self.inner.get_slot()
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        self.inner.get_latest_blockhash()
+    }
+
+    fn get_latest_blockhash_with_commitment(&self, commitment: CommitmentConfig) -> ClientResult<(Hash, u64)> {
+        self.inner.get_latest_blockhash_with_commitment(commitment)
+    }
+
+    fn is_blockhash_valid(&self, blockhash: &Hash, commitment: CommitmentConfig) -> ClientResult<bool> {
+        self.inner.is_blockhash_valid(blockhash, commitment)
+    }
+
+    fn send_transaction(&self, transaction: &Transaction) -> ClientResult<Signature> {
+        self.inner.send_transaction(transaction)
+    }
+
+    fn get_signature_status_with_commitment(
+        &self,
+        signature: &Signature,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        self.inner.get_signature_status_with_commitment(signature, commitment)
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        self.inner.get_transaction_with_config(signature, config)
+    }
+}
+
+/// Answers account reads with what was recorded, in the same order. The last
+/// recorded value of an account is returned again once its reads run out
+pub struct ReplayConnection {
+    reads: Mutex<HashMap<Pubkey, VecDeque<RecordedAccount>>>,
+}
+
+impl ReplayConnection {
+    pub fn new(reads: &[RecordedAccount]) -> Self {
+        let mut by_pubkey: HashMap<Pubkey, VecDeque<RecordedAccount>> = HashMap::new();
+        for read in reads {
+            by_pubkey.entry(str_to_pubkey(&read.pubkey)).or_default().push_back(read.clone());
+        }
+        ReplayConnection {
+            reads: Mutex::new(by_pubkey),
+        }
+    }
+}
+
+impl RpcConnection for ReplayConnection {
+    fn get_account(&self, pubkey: &Pubkey) -> ClientResult<Account> {
+        let mut reads = self.reads.lock().unwrap();
+        let queue = reads.get_mut(pubkey)
+            .ok_or_else(|| custom_error(format!("AccountNotFound: pubkey={} was not read in the recorded iteration", pubkey)))?;
+        let read = if queue.len() > 1 {
+            queue.pop_front().unwrap()
+        } else {
+            queue.front().unwrap().clone()
+        };
+        read.to_account().map_err(custom_error)
+    }
+
+    fn get_multiple_accounts(&self, pubkeys: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        pubkeys.iter().map(|pubkey| match self.get_account(pubkey) {
+            Ok(account) => Ok(Some(account)),
+            Err(e) if e.to_string().contains("AccountNotFound") => Ok(None),
+            Err(e) => Err(e),
+        }).collect()
+    }
+
+    fn get_minimum_balance_for_rent_exemption(&self, data_len: usize) -> ClientResult<u64> {
+        Ok(Rent::default().minimum_balance(data_len))
+    }
+
+    fn get_slot(&self) -> ClientResult<u64> {
+        Ok(0)
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        Ok(Hash::default())
+    }
+
+    fn get_latest_blockhash_with_commitment(&self, _commitment: CommitmentConfig) -> ClientResult<(Hash, u64)> {
+        Ok((Hash::default(), 0))
+    }
+
+    fn is_blockhash_valid(&self, _blockhash: &Hash, _commitment: CommitmentConfig) -> ClientResult<bool> {
+        Ok(false)
+    }
+
+    fn send_transaction(&self, _transaction: &Transaction) -> ClientResult<Signature> {
+        Err(custom_error("Transactions are not sent during a replay".to_string()))
+    }
+
+    fn get_signature_status_with_commitment(
+        &self,
+        _signature: &Signature,
+        _commitment: CommitmentConfig,
+    ) -> ClientResult<Option<transaction::Result<()>>> {
+        Ok(None)
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        _config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        Err(custom_error(format!("Transaction details of {} are not part of the recording", signature)))
+    }
+}
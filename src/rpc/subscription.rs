@@ -0,0 +1,137 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::pubsub_client::{PubsubAccountClientSubscription, PubsubClient};
+use solana_client::rpc_config::RpcAccountInfoConfig;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::commitment_config::CommitmentConfig;
+
+enum AccountEvent {
+    Changed(Pubkey),
+    /// The websocket of the subscription closed, no more changes come for the account
+    Closed(Pubkey),
+}
+
+/// Follows accounts over the websocket of the cluster and tells which of them changed.
+/// Only the notification is used, the accounts are read over RPC afterwards so that
+/// related accounts come from the same slot.
+///
+/// The solana client opens a websocket connection per subscription, so every watched
+/// account holds its own connection and two threads, the client's and the one forwarding
+/// its notifications. A market costs three of them for its book plus one per trader's
+/// open orders account
+pub struct AccountWatcher {
+    ws_url: String,
+    subscriptions: HashMap<Pubkey, PubsubAccountClientSubscription>,
+    events_tx: Sender<AccountEvent>,
+    events_rx: Receiver<AccountEvent>,
+}
+
+impl AccountWatcher {
+    pub fn new(ws_url: &str) -> Self {
+        let (events_tx, events_rx) = channel();
+        AccountWatcher {
+            ws_url: ws_url.to_string(),
+            subscriptions: HashMap::new(),
+            events_tx,
+            events_rx,
+        }
+    }
+
+    pub fn is_watched(&self, pubkey: &Pubkey) -> bool {
+        self.subscriptions.contains_key(pubkey)
+    }
+
+    pub fn watched(&self) -> Vec<Pubkey> {
+        self.subscriptions.keys().cloned().collect()
+    }
+
+    pub fn watch(&mut self, pubkey: &Pubkey) -> Result<(), String> {
+        if self.is_watched(pubkey) {
+            return Ok(())
+        }
+        let (subscription, notifications) = PubsubClient::account_subscribe(
+            &self.ws_url,
+            pubkey,
+            Some(RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                // nothing of the account is needed, only that it changed
+                data_slice: Some(UiDataSliceConfig { offset: 0, length: 0 }),
+                commitment: Some(CommitmentConfig::confirmed()),
+            }),
+        ).map_err(|e| format!("subscribing to {} failed: {}", pubkey, e))?;
+        let (events, account) = (self.events_tx.clone(), *pubkey);
+        let forwarded = std::thread::Builder::new()
+            .name(format!("Subscription {}", account))
+            .spawn(move || {
+                for _ in notifications.iter() {
+                    if events.send(AccountEvent::Changed(account)).is_err() {
+                        return
+                    }
+                }
+                let _ = events.send(AccountEvent::Closed(account));
+            });
+        if let Err(e) = forwarded {
+            shut_down(*pubkey, subscription);
+            return Err(format!("failed to spawn the subscription thread of {}: {}", pubkey, e))
+        }
+        self.subscriptions.insert(*pubkey, subscription);
+        Ok(())
+    }
+
+    pub fn unwatch(&mut self, pubkey: &Pubkey) {
+        if let Some(subscription) = self.subscriptions.remove(pubkey) {
+            shut_down(*pubkey, subscription);
+        }
+    }
+
+    /// Waits up to `timeout` for accounts to change and returns every account that changed
+    /// by then, nothing when none did. Fails with the account whose subscription closed
+    pub fn changes(&self, timeout: Duration) -> Result<HashSet<Pubkey>, Pubkey> {
+        let mut changed = HashSet::new();
+        let first = match self.events_rx.recv_timeout(timeout) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => return Ok(changed),
+            // the watcher holds a sender itself
+            Err(RecvTimeoutError::Disconnected) => unreachable!(),
+        };
+        for event in std::iter::once(first).chain(self.events_rx.try_iter()) {
+            match event {
+                AccountEvent::Changed(pubkey) => {
+                    changed.insert(pubkey);
+                }
+                AccountEvent::Closed(pubkey) => {
+                    // closes after an unwatch are expected
+                    if self.is_watched(&pubkey) {
+                        return Err(pubkey)
+                    }
+                }
+            }
+        }
+        Ok(changed)
+    }
+}
+
+impl Drop for AccountWatcher {
+    fn drop(&mut self) {
+        for (pubkey, subscription) in self.subscriptions.drain() {
+            shut_down(pubkey, subscription);
+        }
+    }
+}
+
+/// `shutdown` joins the websocket thread of the client, which only sees the exit flag once
+/// the next message of the account arrives. An account that rarely changes would hold the
+/// caller up for as long, so the subscription is shut down on a thread of its own
+fn shut_down(pubkey: Pubkey, mut subscription: PubsubAccountClientSubscription) {
+    let spawned = std::thread::Builder::new()
+        .name(format!("Unsubscribe {}", pubkey))
+        .spawn(move || {
+            let _ = subscription.shutdown();
+        });
+    if let Err(e) = spawned {
+        eprintln!("[-] Failed to spawn the thread shutting down the subscription of {}: {}", pubkey, e);
+    }
+}
@@ -0,0 +1,134 @@
+pub use serum_dex::error::DexErrorCode;
+
+use crate::workers::error::ErrorClass;
+
+/// Custom error code of the serum dex program, codes serum does not know decode as
+/// `AssertionError` and are left undecoded
+pub fn decode_error_code(code: u32) -> Option<DexErrorCode> {
+    match DexErrorCode::from(code) {
+        DexErrorCode::AssertionError => None,
+        code => Some(code),
+    }
+}
+
+pub fn explanation(code: DexErrorCode) -> &'static str {
+    match code {
+        DexErrorCode::InvalidMarketFlags => "the market account is not an initialized serum market",
+        DexErrorCode::InvalidAskFlags | DexErrorCode::InvalidBidFlags => "the bids or asks account does not belong to a market",
+        DexErrorCode::InvalidQueueLength => "the request or event queue has the wrong size",
+        DexErrorCode::OwnerAccountNotProvided => "the open orders owner did not sign",
+        DexErrorCode::ConsumeEventsQueueFailure => "the event queue could not be consumed",
+        DexErrorCode::WrongCoinVault | DexErrorCode::WrongPcVault => "the vault passed is not the vault of the market",
+        DexErrorCode::WrongCoinMint | DexErrorCode::WrongPcMint | DexErrorCode::WrongMint => "a token account has the wrong mint for the market",
+        DexErrorCode::CoinVaultProgramId | DexErrorCode::PcVaultProgramId
+        | DexErrorCode::CoinMintProgramId | DexErrorCode::PcMintProgramId => "a vault or mint is not owned by the token program",
+        DexErrorCode::WrongCoinMintSize | DexErrorCode::WrongPcMintSize
+        | DexErrorCode::WrongCoinVaultSize | DexErrorCode::WrongPcVaultSize => "a vault or mint account has the wrong size",
+        DexErrorCode::UninitializedVault | DexErrorCode::UninitializedMint
+        | DexErrorCode::CoinMintUninitialized | DexErrorCode::PcMintUninitialized => "a vault or mint is not initialized",
+        DexErrorCode::WrongVaultOwner => "the vault is not owned by the market signer",
+        DexErrorCode::VaultHasDelegate => "the vault has a delegate",
+        DexErrorCode::AlreadyInitialized => "the account is already initialized",
+        DexErrorCode::WrongAccountDataAlignment | DexErrorCode::WrongAccountDataPaddingLength
+        | DexErrorCode::WrongAccountHeadPadding | DexErrorCode::WrongAccountTailPadding => "the account is not a serum account",
+        DexErrorCode::RequestQueueEmpty => "there are no requests to match",
+        DexErrorCode::EventQueueTooSmall | DexErrorCode::SlabTooSmall => "the queue or book account is smaller than serum needs",
+        DexErrorCode::BadVaultSignerNonce => "the vault signer nonce does not match the market",
+        DexErrorCode::InsufficientFunds => "not enough funds in the wallet or open orders account for the order",
+        DexErrorCode::SplAccountProgramId | DexErrorCode::SplAccountLen => "a token account is not owned by the token program or has the wrong size",
+        DexErrorCode::WrongFeeDiscountAccountOwner | DexErrorCode::WrongFeeDiscountMint => "the fee discount account is not a SRM or MSRM account of the owner",
+        DexErrorCode::CoinPayerProgramId | DexErrorCode::PcPayerProgramId => "the paying wallet is not a token account",
+        DexErrorCode::ClientIdNotFound => "no order with this client order id is on the book",
+        DexErrorCode::TooManyOpenOrders => "the open orders account has no free order slots",
+        DexErrorCode::BorrowError => "an account was borrowed twice, usually the same account passed in two positions",
+        DexErrorCode::WrongOrdersAccount => "the open orders account does not belong to this market or owner",
+        DexErrorCode::WrongBidsAccount | DexErrorCode::WrongAsksAccount => "the bids or asks account is not the one of the market",
+        DexErrorCode::WrongRequestQueueAccount | DexErrorCode::WrongEventQueueAccount => "the request or event queue is not the one of the market",
+        DexErrorCode::RequestQueueFull => "the request queue is full, the market needs to be cranked",
+        DexErrorCode::EventQueueFull => "the event queue is full, the market needs to be cranked",
+        DexErrorCode::MarketIsDisabled => "the market has been disabled",
+        DexErrorCode::WrongSigner => "the signer is not the owner of the open orders account",
+        DexErrorCode::TransferFailed => "the token transfer into the vault failed, usually not enough balance in the wallet",
+        DexErrorCode::ClientOrderIdIsZero => "client order id 0 is reserved by serum",
+        DexErrorCode::WrongRentSysvarAccount => "the account passed as the rent sysvar is not the rent sysvar",
+        DexErrorCode::RentNotProvided => "the rent sysvar was not passed",
+        DexErrorCode::OrdersNotRentExempt => "the open orders account is not rent exempt",
+        DexErrorCode::OrderNotFound => "the order is no longer on the book",
+        DexErrorCode::OrderNotYours => "the order belongs to another open orders account",
+        DexErrorCode::WouldSelfTrade => "the order would have matched one of our own orders",
+        DexErrorCode::InvalidOpenOrdersAuthority => "the open orders authority did not sign",
+        DexErrorCode::FakeErrorSoWeDontChangeNumbers | DexErrorCode::Unknown => "unknown serum error",
+        DexErrorCode::AssertionError => "an assertion inside serum failed",
+    }
+}
+
+/// Errors caused by the state of the book or of our balances go away on their
+/// own, anything about the accounts passed needs someone to look at it
+pub fn class(code: DexErrorCode) -> ErrorClass {
+    match code {
+        DexErrorCode::RequestQueueFull
+        | DexErrorCode::EventQueueFull
+        | DexErrorCode::TransferFailed
+        | DexErrorCode::InsufficientFunds
+        | DexErrorCode::ClientIdNotFound
+        | DexErrorCode::TooManyOpenOrders
+        | DexErrorCode::OrderNotFound
+        | DexErrorCode::WouldSelfTrade => ErrorClass::Retryable,
+        _ => ErrorClass::Fatal,
+    }
+}
+
+/// Failed assertions inside serum carry the source file in the upper byte and the line below it
+pub fn decode_assertion(code: u32) -> Option<(&'static str, u32)> {
+    let file = match code >> 24 {
+        1 => "state.rs",
+        2 => "matching.rs",
+        3 => "critbit.rs",
+        _ => return None,
+    };
+    Some((file, code & 0x00ff_ffff))
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::instruction::{Instruction, InstructionError};
+    use solana_program::pubkey::Pubkey;
+    use solana_sdk::transaction::TransactionError;
+
+    use crate::workers::error::{DecodedError, DecodedErrorKind};
+
+    use super::*;
+
+    fn decode(code: u32) -> DecodedError {
+        let serum_program = Pubkey::new_unique();
+        let ix = Instruction::new_with_bytes(serum_program, &[], vec![]);
+        DecodedError::from_transaction_error(&TransactionError::InstructionError(0, InstructionError::Custom(code)), &serum_program, &[ix])
+    }
+
+    #[test]
+    fn codes_decode_like_serum_numbers_them() {
+        assert_eq!(decode_error_code(41), Some(DexErrorCode::ClientIdNotFound));
+        assert_eq!(decode_error_code(42), Some(DexErrorCode::TooManyOpenOrders));
+        assert_eq!(decode_error_code(50), Some(DexErrorCode::RequestQueueFull));
+        assert_eq!(decode_error_code(51), Some(DexErrorCode::EventQueueFull));
+        assert_eq!(decode_error_code(61), Some(DexErrorCode::WouldSelfTrade));
+        assert_eq!(decode_error_code(1000), Some(DexErrorCode::Unknown));
+        assert_eq!(decode_error_code(999), None);
+    }
+
+    #[test]
+    fn full_queues_are_retried_and_account_errors_are_not() {
+        assert_eq!(decode(50).class, ErrorClass::Retryable);
+        assert_eq!(decode(51).class, ErrorClass::Retryable);
+        assert_eq!(decode(42).class, ErrorClass::Retryable);
+        assert_eq!(decode(52).class, ErrorClass::Fatal);
+        assert_eq!(decode(45).class, ErrorClass::Fatal);
+    }
+
+    #[test]
+    fn unknown_codes_and_assertions_are_told_apart() {
+        assert_eq!(decode(41).kind, DecodedErrorKind::Serum(DexErrorCode::ClientIdNotFound));
+        assert_eq!(decode(999).kind, DecodedErrorKind::Custom(999));
+        assert_eq!(decode(2 << 24 | 123).kind, DecodedErrorKind::SerumAssertion { file: "matching.rs", line: 123 });
+    }
+}
@@ -0,0 +1,235 @@
+use serum_dex::matching::Side;
+
+// serum accounts are wrapped in "serum" ... "padding" markers
+const ACCOUNT_HEAD_PADDING: usize = 5;
+const ACCOUNT_TAIL_PADDING: usize = 7;
+const EVENT_QUEUE_HEADER_LEN: usize = 32;
+const EVENT_LEN: usize = 88;
+
+pub const EVENT_FLAG_FILL: u8 = 0x1;
+pub const EVENT_FLAG_OUT: u8 = 0x2;
+pub const EVENT_FLAG_BID: u8 = 0x4;
+pub const EVENT_FLAG_MAKER: u8 = 0x8;
+pub const EVENT_FLAG_RELEASE_FUNDS: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct EventQueueHeader {
+    pub account_flags: u64,
+    pub head: u64,
+    pub count: u64,
+    pub seq_num: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub event_flags: u8,
+    pub owner_slot: u8,
+    pub fee_tier: u8,
+    pub native_qty_released: u64,
+    pub native_qty_paid: u64,
+    pub native_fee_or_rebate: u64,
+    pub order_id: u128,
+    pub owner: [u64; 4],
+    pub client_order_id: u64,
+    /// Position of the event in the queue's lifetime, derived from the header
+    pub seq_num: u64,
+}
+
+impl Event {
+    pub fn is_fill(&self) -> bool {
+        self.event_flags & EVENT_FLAG_FILL != 0
+    }
+
+    pub fn is_out(&self) -> bool {
+        self.event_flags & EVENT_FLAG_OUT != 0
+    }
+
+    pub fn is_maker(&self) -> bool {
+        self.event_flags & EVENT_FLAG_MAKER != 0
+    }
+
+    pub fn release_funds(&self) -> bool {
+        self.event_flags & EVENT_FLAG_RELEASE_FUNDS != 0
+    }
+
+    pub fn side(&self) -> Side {
+        if self.event_flags & EVENT_FLAG_BID != 0 { Side::Bid } else { Side::Ask }
+    }
+
+    /// The limit price in lots is stored in the upper 64 bits of the order id
+    pub fn price(&self) -> u64 {
+        (self.order_id >> 64) as u64
+    }
+
+    /// For an out event `native_qty_released` is the unlocked amount and
+    /// `native_qty_paid` the amount still locked. A maker order that got fully
+    /// filled is removed with both set to zero, anything else is a cancel
+    pub fn is_filled_out(&self) -> bool {
+        self.is_out() && self.native_qty_released == 0 && self.native_qty_paid == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventQueue {
+    pub header: EventQueueHeader,
+    pub events: Vec<Event>,
+}
+
+impl EventQueue {
+    /// Decodes the raw event queue account data, returns `None` if the buffer is
+    /// too short to be an event queue
+    pub fn from_buffer(buffer: &[u8]) -> Option<Self> {
+        if buffer.len() < ACCOUNT_HEAD_PADDING + EVENT_QUEUE_HEADER_LEN + ACCOUNT_TAIL_PADDING {
+            return None;
+        }
+        let data = &buffer[ACCOUNT_HEAD_PADDING..buffer.len() - ACCOUNT_TAIL_PADDING];
+        let header = EventQueueHeader {
+            account_flags: read_u64(data, 0),
+            head: read_u64(data, 8),
+            count: read_u64(data, 16),
+            seq_num: read_u64(data, 24),
+        };
+        let capacity = ((data.len() - EVENT_QUEUE_HEADER_LEN) / EVENT_LEN) as u64;
+        if capacity == 0 || header.count > capacity {
+            return None;
+        }
+        let mut events = vec![];
+        for i in 0..header.count {
+            let index = ((header.head + i) % capacity) as usize;
+            let start = EVENT_QUEUE_HEADER_LEN + index * EVENT_LEN;
+            let raw = &data[start..start + EVENT_LEN];
+            events.push(Event {
+                event_flags: raw[0],
+                owner_slot: raw[1],
+                fee_tier: raw[2],
+                native_qty_released: read_u64(raw, 8),
+                native_qty_paid: read_u64(raw, 16),
+                native_fee_or_rebate: read_u64(raw, 24),
+                order_id: read_u128(raw, 32),
+                owner: [read_u64(raw, 48), read_u64(raw, 56), read_u64(raw, 64), read_u64(raw, 72)],
+                client_order_id: read_u64(raw, 80),
+                seq_num: header.seq_num.wrapping_sub(header.count).wrapping_add(i),
+            });
+        }
+        Some(EventQueue { header, events })
+    }
+
+    pub fn events_for_owner(&self, owner: &[u64; 4]) -> Vec<Event> {
+        self.events
+            .iter()
+            .filter(|event| &event.owner == owner)
+            .cloned()
+            .collect()
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_u128(data: &[u8], offset: usize) -> u128 {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&data[offset..offset + 16]);
+    u128::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OURS: [u64; 4] = [1, 2, 3, 4];
+    const THEIRS: [u64; 4] = [5, 6, 7, 8];
+
+    /// Raw event with the fields the decoder reads, laid out like serum's `Event`
+    fn raw_event(event_flags: u8, native_qty_released: u64, native_qty_paid: u64, price: u64, owner: [u64; 4], client_order_id: u64) -> [u8; EVENT_LEN] {
+        let mut raw = [0u8; EVENT_LEN];
+        raw[0] = event_flags;
+        raw[1] = 3;
+        raw[8..16].copy_from_slice(&native_qty_released.to_le_bytes());
+        raw[16..24].copy_from_slice(&native_qty_paid.to_le_bytes());
+        raw[24..32].copy_from_slice(&7u64.to_le_bytes());
+        raw[32..48].copy_from_slice(&(((price as u128) << 64) | 42).to_le_bytes());
+        for (i, word) in owner.iter().enumerate() {
+            raw[48 + i * 8..56 + i * 8].copy_from_slice(&word.to_le_bytes());
+        }
+        raw[80..88].copy_from_slice(&client_order_id.to_le_bytes());
+        raw
+    }
+
+    /// Event queue account with `slots` as its ring buffer
+    fn queue_buffer(head: u64, count: u64, seq_num: u64, slots: &[[u8; EVENT_LEN]]) -> Vec<u8> {
+        let mut buffer = b"serum".to_vec();
+        for word in [0u64, head, count, seq_num].iter() {
+            buffer.extend_from_slice(&word.to_le_bytes());
+        }
+        for slot in slots {
+            buffer.extend_from_slice(slot);
+        }
+        buffer.extend_from_slice(b"padding");
+        buffer
+    }
+
+    #[test]
+    fn decodes_the_fields_of_an_event() {
+        let fill = raw_event(EVENT_FLAG_FILL | EVENT_FLAG_BID | EVENT_FLAG_MAKER, 500, 475, 95, OURS, 9);
+        let queue = EventQueue::from_buffer(&queue_buffer(0, 1, 1, &[fill, [0; EVENT_LEN]])).unwrap();
+
+        assert_eq!(queue.events.len(), 1);
+        let event = queue.events[0];
+        assert!(event.is_fill() && event.is_maker() && !event.is_out());
+        assert_eq!(event.side(), Side::Bid);
+        assert_eq!(event.owner_slot, 3);
+        assert_eq!((event.native_qty_released, event.native_qty_paid, event.native_fee_or_rebate), (500, 475, 7));
+        assert_eq!(event.price(), 95);
+        assert_eq!(event.owner, OURS);
+        assert_eq!(event.client_order_id, 9);
+        assert_eq!(event.seq_num, 0);
+    }
+
+    #[test]
+    fn reads_the_ring_from_the_head_and_numbers_events_by_the_header() {
+        let slots = [
+            raw_event(EVENT_FLAG_OUT, 0, 0, 102, OURS, 3),
+            raw_event(EVENT_FLAG_FILL, 0, 0, 100, THEIRS, 0),
+            raw_event(EVENT_FLAG_FILL, 0, 0, 101, OURS, 2),
+        ];
+        // the oldest event sits in the last slot, the newest wrapped around to the first
+        let queue = EventQueue::from_buffer(&queue_buffer(1, 3, 110, &slots)).unwrap();
+
+        let prices: Vec<u64> = queue.events.iter().map(|event| event.price()).collect();
+        assert_eq!(prices, vec![100, 101, 102]);
+        let seq_nums: Vec<u64> = queue.events.iter().map(|event| event.seq_num).collect();
+        assert_eq!(seq_nums, vec![107, 108, 109]);
+
+        let ours = queue.events_for_owner(&OURS);
+        assert_eq!(ours.iter().map(|event| event.seq_num).collect::<Vec<u64>>(), vec![108, 109]);
+        assert_eq!(ours[1].side(), Side::Ask);
+        assert!(ours[1].is_filled_out());
+    }
+
+    #[test]
+    fn cancelled_orders_are_not_filled_outs() {
+        let out = raw_event(EVENT_FLAG_OUT | EVENT_FLAG_BID, 950, 0, 95, OURS, 1);
+        let queue = EventQueue::from_buffer(&queue_buffer(0, 1, 1, &[out])).unwrap();
+        assert!(queue.events[0].is_out());
+        assert!(!queue.events[0].is_filled_out());
+    }
+
+    #[test]
+    fn buffers_that_are_not_event_queues_are_refused() {
+        assert!(EventQueue::from_buffer(&[0u8; 20]).is_none());
+        // a header without room for a single event
+        assert!(EventQueue::from_buffer(&queue_buffer(0, 0, 0, &[])).is_none());
+        // more events than slots
+        assert!(EventQueue::from_buffer(&queue_buffer(0, 3, 3, &[[0; EVENT_LEN]; 2])).is_none());
+    }
+
+    #[test]
+    fn an_empty_queue_has_no_events() {
+        let queue = EventQueue::from_buffer(&queue_buffer(5, 0, 40, &[[0; EVENT_LEN]; 8])).unwrap();
+        assert!(queue.events.is_empty());
+        assert_eq!(queue.header.seq_num, 40);
+    }
+}
@@ -0,0 +1,246 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serum_dex::state::Market;
+use solana_program::account_info::AccountInfo;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::ReadableAccount;
+use thiserror::Error;
+
+use crate::rpc::client::RpcConnection;
+use crate::str_to_pubkey;
+
+#[derive(Debug, Error)]
+pub enum MarketRegistryError {
+    #[error("Can't read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid markets file: {0}")]
+    Parse(#[from] serde_json::Error),
+    #[error("Invalid market {0}: {1}")]
+    Invalid(String, String),
+}
+
+/// A market as listed in markets.json
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketInfo {
+    pub name: String,
+    pub address: String,
+    pub event_queue: String,
+    pub request_queue: String,
+    pub bids: String,
+    pub asks: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    /// Read from the chain by `MarketRegistry::verify`
+    #[serde(skip)]
+    pub details: Option<MarketDetails>,
+}
+
+/// What prices and sizes of the market are expressed in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketDetails {
+    pub base_decimals: u8,
+    pub quote_decimals: u8,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    /// The file does not list the vaults, they are read with the market
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+}
+
+/// Accounts of a market the workers build their instructions with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MarketKeys {
+    pub market: Pubkey,
+    pub event_queue: Pubkey,
+    pub request_queue: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+}
+
+impl MarketKeys {
+    /// Keys as the market state lists them, for markets the registry doesn't know
+    pub fn from_market(market: &Market) -> Self {
+        MarketKeys {
+            market: key_to_pubkey(&market.own_address),
+            event_queue: key_to_pubkey(&market.event_q),
+            request_queue: key_to_pubkey(&market.req_q),
+            bids: key_to_pubkey(&market.bids),
+            asks: key_to_pubkey(&market.asks),
+            coin_vault: key_to_pubkey(&market.coin_vault),
+            pc_vault: key_to_pubkey(&market.pc_vault),
+        }
+    }
+}
+
+/// The markets traders can trade on, looked up by name like "SOL/USDC" or by address
+#[derive(Debug, Clone, Default)]
+pub struct MarketRegistry {
+    markets: Vec<MarketInfo>,
+}
+
+impl MarketRegistry {
+    pub fn load(path: &Path) -> Result<Self, MarketRegistryError> {
+        let file = File::open(path).map_err(|e| MarketRegistryError::Io(path.to_path_buf(), e))?;
+        let markets: Vec<MarketInfo> = serde_json::from_reader(file)?;
+        Self::from_markets(markets)
+    }
+
+    /// Checks every address parses and that no name or address is listed twice
+    pub fn from_markets(markets: Vec<MarketInfo>) -> Result<Self, MarketRegistryError> {
+        let mut names = HashSet::new();
+        let mut addresses = HashSet::new();
+        for market in &markets {
+            let invalid = |reason: String| MarketRegistryError::Invalid(market.name.clone(), reason);
+            if market.name.split('/').count() != 2 {
+                return Err(invalid("name is not BASE/QUOTE".to_string()))
+            }
+            if !names.insert(market.name.to_uppercase()) {
+                return Err(invalid("listed twice".to_string()))
+            }
+            if !addresses.insert(market.address.clone()) {
+                return Err(invalid(format!("address {} listed twice", market.address)))
+            }
+            for (field, address) in market.addresses() {
+                if Pubkey::from_str(address).is_err() {
+                    return Err(invalid(format!("{} {} is not a valid address", field, address)))
+                }
+            }
+        }
+        Ok(MarketRegistry { markets })
+    }
+
+    pub fn markets(&self) -> &[MarketInfo] {
+        &self.markets
+    }
+
+    /// Finds a market by name, regardless of case, or by address
+    pub fn find(&self, market: &str) -> Option<&MarketInfo> {
+        self.markets.iter().find(|info| info.address == market || info.name.eq_ignore_ascii_case(market))
+    }
+
+    /// Cross-checks every market with its state on chain and reads its lot sizes and
+    /// mint decimals. Markets that don't match are dropped, returned with the reason
+    pub fn verify(&mut self, connection: &dyn RpcConnection, serum_program: &Pubkey) -> Vec<(MarketInfo, String)> {
+        let mut mint_decimals: HashMap<String, u8> = HashMap::new();
+        let mut rejected = vec![];
+        let mut verified = vec![];
+        for mut market in self.markets.drain(..) {
+            match market.verify(connection, serum_program, &mut mint_decimals) {
+                Ok(details) => {
+                    market.details = Some(details);
+                    verified.push(market);
+                }
+                Err(reason) => rejected.push((market, reason)),
+            }
+        }
+        self.markets = verified;
+        rejected
+    }
+}
+
+impl MarketInfo {
+    pub fn address(&self) -> Pubkey {
+        str_to_pubkey(&self.address)
+    }
+
+    /// Base and quote symbols of the name
+    pub fn symbols(&self) -> (&str, &str) {
+        let mut symbols = self.name.splitn(2, '/');
+        (symbols.next().unwrap_or(""), symbols.next().unwrap_or(""))
+    }
+
+    /// The keys of the file, once `MarketRegistry::verify` has matched them with the chain
+    pub fn keys(&self) -> Option<MarketKeys> {
+        let details = self.details.as_ref()?;
+        Some(MarketKeys {
+            market: self.address(),
+            event_queue: str_to_pubkey(&self.event_queue),
+            request_queue: str_to_pubkey(&self.request_queue),
+            bids: str_to_pubkey(&self.bids),
+            asks: str_to_pubkey(&self.asks),
+            coin_vault: details.coin_vault,
+            pc_vault: details.pc_vault,
+        })
+    }
+
+    fn addresses(&self) -> Vec<(&'static str, &str)> {
+        vec![
+            ("address", &self.address),
+            ("eventQueue", &self.event_queue),
+            ("requestQueue", &self.request_queue),
+            ("bids", &self.bids),
+            ("asks", &self.asks),
+            ("baseMint", &self.base_mint),
+            ("quoteMint", &self.quote_mint),
+        ]
+    }
+
+    fn verify(&self, connection: &dyn RpcConnection, serum_program: &Pubkey, mint_decimals: &mut HashMap<String, u8>) -> Result<MarketDetails, String> {
+        let market_pubkey = self.address();
+        let account = connection.get_account(&market_pubkey).map_err(|e| e.to_string())?;
+        let mut account_clone = account.clone();
+        let market_account_info = AccountInfo {
+            key: &market_pubkey,
+            is_signer: false,
+            is_writable: false,
+            lamports: Rc::new(RefCell::new(&mut account_clone.lamports)),
+            data: Rc::new(RefCell::new(&mut account_clone.data)),
+            owner: &account.owner().clone(),
+            executable: false,
+            rent_epoch: account.rent_epoch,
+        };
+        let market = Market::load(&market_account_info, serum_program, true)
+            .map_err(|e| format!("not a market of {}: {:?}", serum_program, e))?;
+
+        let on_chain: [(&str, [u64; 4], &String); 6] = [
+            ("eventQueue", market.event_q, &self.event_queue),
+            ("requestQueue", market.req_q, &self.request_queue),
+            ("bids", market.bids, &self.bids),
+            ("asks", market.asks, &self.asks),
+            ("baseMint", market.coin_mint, &self.base_mint),
+            ("quoteMint", market.pc_mint, &self.quote_mint),
+        ];
+        for (field, key, listed) in on_chain.iter() {
+            let on_chain_key = key_to_pubkey(key);
+            if on_chain_key.to_string() != **listed {
+                return Err(format!("{} is {} on chain, {} in the file", field, on_chain_key, listed))
+            }
+        }
+
+        Ok(MarketDetails {
+            base_decimals: mint_decimals_of(connection, &self.base_mint, mint_decimals)?,
+            quote_decimals: mint_decimals_of(connection, &self.quote_mint, mint_decimals)?,
+            coin_lot_size: market.coin_lot_size,
+            pc_lot_size: market.pc_lot_size,
+            coin_vault: key_to_pubkey(&market.coin_vault),
+            pc_vault: key_to_pubkey(&market.pc_vault),
+        })
+    }
+}
+
+fn key_to_pubkey(key: &[u64; 4]) -> Pubkey {
+    Pubkey::new(bytemuck::cast_slice::<u64, u8>(key))
+}
+
+/// Most markets share their quote mint, each mint is only read once
+fn mint_decimals_of(connection: &dyn RpcConnection, mint: &str, cache: &mut HashMap<String, u8>) -> Result<u8, String> {
+    if let Some(decimals) = cache.get(mint) {
+        return Ok(*decimals)
+    }
+    let account = connection.get_account(&str_to_pubkey(mint)).map_err(|e| e.to_string())?;
+    let decimals = spl_token::state::Mint::unpack(account.data())
+        .map_err(|e| format!("mint {} does not unpack: {:?}", mint, e))?
+        .decimals;
+    cache.insert(mint.to_string(), decimals);
+    Ok(decimals)
+}
@@ -1 +1,4 @@
-pub mod state;
\ No newline at end of file
+pub mod state;
+pub mod events;
+pub mod errors;
+pub mod markets;
@@ -4,8 +4,76 @@ use serum_dex::matching::Side;
 pub struct Order {
     pub side: Side,
     pub price: u64,
+    /// Remaining quantity in base lots
+    pub quantity: u64,
     pub client_id: u64,
     pub owner: [u64; 4],
     pub order_id: u128,
 }
 
+
+/// Tags the upper bits of client order ids placed by the bot so they can be told
+/// apart from orders placed by hand on the same open orders account
+const CLIENT_ORDER_ID_TAG: u64 = 0x4752;
+
+/// Client order id layout: 16 bit tag | 16 bit grid index | 32 bit generation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientOrderId {
+    pub grid_index: u16,
+    pub generation: u32,
+}
+
+impl ClientOrderId {
+    pub fn new(grid_index: usize, generation: u64) -> Self {
+        ClientOrderId {
+            grid_index: grid_index as u16,
+            generation: generation as u32,
+        }
+    }
+
+    pub fn encode(&self) -> u64 {
+        CLIENT_ORDER_ID_TAG << 48 | (self.grid_index as u64) << 32 | self.generation as u64
+    }
+
+    /// Returns `None` for ids that were not generated by the bot
+    pub fn decode(client_order_id: u64) -> Option<Self> {
+        if client_order_id >> 48 != CLIENT_ORDER_ID_TAG {
+            return None;
+        }
+        Some(ClientOrderId {
+            grid_index: (client_order_id >> 32) as u16,
+            generation: client_order_id as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_order_ids_round_trip() {
+        for (grid_index, generation) in [(0, 0), (0, 1), (3, 7), (u16::MAX as usize, u32::MAX as u64)] {
+            let client_order_id = ClientOrderId::new(grid_index, generation);
+            assert_eq!(ClientOrderId::decode(client_order_id.encode()), Some(client_order_id));
+        }
+    }
+
+    #[test]
+    fn every_generation_of_a_grid_gets_its_own_id() {
+        let first = ClientOrderId::new(2, 1).encode();
+        let second = ClientOrderId::new(2, 2).encode();
+        assert_ne!(first, second);
+        assert_ne!(first, ClientOrderId::new(3, 1).encode());
+    }
+
+    #[test]
+    fn ids_not_placed_by_the_bot_are_not_decoded() {
+        // serum sends 0 for orders placed without a client order id
+        assert_eq!(ClientOrderId::decode(0), None);
+        assert_eq!(ClientOrderId::decode(12345), None);
+        assert_eq!(ClientOrderId::decode((CLIENT_ORDER_ID_TAG + 1) << 48 | 1 << 32 | 1), None);
+        assert_eq!(ClientOrderId::decode(u64::MAX), None);
+        assert_ne!(ClientOrderId::new(0, 0).encode(), 0);
+    }
+}
@@ -1,9 +1,12 @@
 use std::cell::RefCell;
 use std::cmp::min;
 use std::convert::TryFrom;
+use std::fmt;
+use std::path::PathBuf;
 
 use std::rc::Rc;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
@@ -11,43 +14,65 @@ use mongodb::bson::doc;
 use serum_dex::critbit::{Slab, SlabView};
 use serum_dex::matching::Side;
 
-use serum_dex::state::Market;
-use solana_client::client_error::{ClientError};
-use solana_client::rpc_client::RpcClient;
+use serum_dex::state::{Market, OpenOrders, ToAlignedBytes};
+use solana_client::client_error::{ClientError, ClientErrorKind};
 
 use solana_program::account_info::AccountInfo;
-use solana_program::instruction::{Instruction};
+use solana_program::instruction::{AccountMeta, Instruction};
 use solana_program::message::Message;
 use solana_program::pubkey::Pubkey;
+use solana_program::{system_program, sysvar};
 
 
 
-use solana_sdk::account::ReadableAccount;
+use solana_sdk::account::{Account, ReadableAccount};
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::signature::{Keypair, Signature};
 use solana_sdk::signature::{Signer};
 use solana_sdk::transaction::{Transaction, TransactionError, uses_durable_nonce};
 
 
+use crate::config::Settings;
 use crate::mongodb::client::{MongoClient};
-use crate::workers::message::{ThreadMessage};
-use crate::mongodb::models::Trader;
-use crate::serum::state::{Order};
+use crate::workers::message::{ThreadLogLevel, ThreadMessage};
+use crate::mongodb::models::{Trader, TraderStatus};
+use crate::replay::session::{IterationRecord, SessionRecorder, SessionState, SESSION_VERSION};
+use crate::rpc::client::RpcConnection;
+use crate::rpc::recording::RecordingConnection;
+use crate::serum::markets::{MarketInfo, MarketKeys};
+use crate::serum::events::EventQueue;
+use crate::serum::state::{ClientOrderId, Order};
 use crate::str_to_pubkey;
 use crate::workers::message::ThreadMessageSource;
-pub const MAX_IXS: usize = 10;
+use crate::workers::error::{DecodedError, ErrorBackoff, ErrorClass, TradeBotErrors, TradeBotResult};
+use crate::workers::market_data::{parse_slab, MarketDataHub};
+/// Open orders accounts passed to a single consume events instruction
+pub const MAX_CONSUME_ACCOUNTS: usize = 10;
+pub const CONSUME_EVENTS_LIMIT: u16 = 20;
+/// Shutdown transactions in a row that may fail or go unconfirmed before the thread gives
+/// up and stops anyway, every confirmed one starts the count over
+const MAX_SHUTDOWN_ATTEMPTS: u32 = 3;
+/// How often a paused thread checks whether it was asked to stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 pub trait BotThread {
     fn worker(&mut self) {
         println!("Started {} Thread", self.get_name());
         let config = self.get_config();
-        let connection = solana_client::rpc_client::RpcClient::new_with_timeout_and_commitment(
-            config.rpc_url.clone(),
-            Duration::from_secs(10),
-            CommitmentConfig::confirmed(),
-        );
+        let connection = config.connection.as_ref();
+        let mut backoff = ErrorBackoff::new();
+        let mut shutdown_attempts = 0;
 
-        let account = connection.get_account(&str_to_pubkey(&config.trader.market_address)).unwrap();
+        let account = loop {
+            if config.stop_requested() {
+                println!("[?] Stop requested, stopping {} Thread", self.get_name());
+                return
+            }
+            match connection.get_account(&str_to_pubkey(&config.trader.market_address)) {
+                Ok(account) => break account,
+                Err(e) => self.back_off(&mut backoff, &e.into()),
+            }
+        };
         let mut account_clone = account.clone();
 
         let serum_market_account_info = AccountInfo {
@@ -60,21 +85,112 @@ pub trait BotThread {
             executable: false,
             rent_epoch: account.rent_epoch,
         };
-        let serum_market = serum_dex::state::Market::load(
+        let serum_market = match serum_dex::state::Market::load(
             &serum_market_account_info,
             &config.serum_program,
             true,
-        )
-            .unwrap();
-        let mongo_client = MongoClient::new();
+        ) {
+            Ok(serum_market) => serum_market,
+            Err(e) => {
+                eprintln!("[-] {} is not a serum market, stopping {} Thread: {:?}", config.trader.market_address, self.get_name(), e);
+                return
+            }
+        };
+        let mongo_client = MongoClient::connect(&config.settings.mongodb_url, &config.settings.database);
+        let mut session = config.session_dir.as_ref().and_then(|dir| {
+            match SessionRecorder::create(dir, &config.trader, &self.get_name()) {
+                Ok(session) => {
+                    println!("[?] Recording {} Thread session to {}", self.get_name(), session.path.display());
+                    Some(session)
+                }
+                Err(e) => {
+                    eprintln!("[-] Failed to open session file: {:?}", e);
+                    None
+                }
+            }
+        });
 
         loop {
-            self.setup(&connection, &serum_market, &mongo_client);
-            let ix = self.compile_ixs(&connection, &serum_market, &mongo_client);
+            // the previous iteration is confirmed or rolled back by now, a safe point to stop at
+            let mut shutdown_ixs: Option<Vec<Instruction>> = None;
+            if config.stop_requested() {
+                if shutdown_attempts >= MAX_SHUTDOWN_ATTEMPTS {
+                    eprintln!("[-] {} Thread did not shut down cleanly, {} transactions in a row were not confirmed, stopping anyway", self.get_name(), shutdown_attempts);
+                    return
+                }
+                match self.compile_shutdown_ixs(connection, &serum_market, &mongo_client) {
+                    Ok(ixs) if !ixs.is_empty() => {
+                        shutdown_attempts += 1;
+                        shutdown_ixs = Some(ixs);
+                    }
+                    Ok(_) => {
+                        println!("[?] Stop requested, stopping {} Thread", self.get_name());
+                        return
+                    }
+                    Err(e) => {
+                        eprintln!("[-] Failed to shut {} Thread down cleanly, stopping anyway: {}", self.get_name(), e);
+                        return
+                    }
+                }
+            }
+            let stored_trader = match self.get_updated_trader(&mongo_client, &config.trader) {
+                Ok(trader) if trader.status == TraderStatus::Closed => {
+                    println!("[?] Trader closed, stopping {} Thread", self.get_name());
+                    return
+                }
+                Ok(trader) => trader,
+                Err(e) => {
+                    self.back_off(&mut backoff, &e);
+                    continue
+                }
+            };
+            let recording = session.as_ref().map(|_| {
+                let recording = RecordingConnection::new(config.connection.clone());
+                recording.record_read(&str_to_pubkey(&config.trader.market_address), &account);
+                recording
+            });
+            let connection: &dyn RpcConnection = match &recording {
+                Some(recording) => recording,
+                None => config.connection.as_ref(),
+            };
+            // setup starts from the stored trader, which is what a replay has to start from too
+            let state_before = session.as_ref()
+                .and_then(|_| self.session_state())
+                .map(|state| SessionState::new(&stored_trader, state.last_seq_num));
+            let mut outcome: Option<bool> = None;
+            let mut error: Option<DecodedError> = None;
+            let mut cleanup_result: TradeBotResult<()> = Ok(());
+            let shutting_down = shutdown_ixs.is_some();
+            let ix = match shutdown_ixs {
+                Some(ixs) => ixs,
+                None => {
+                    if let Err(e) = self.setup(connection, &serum_market, &mongo_client) {
+                        self.record_iteration(&mut session, recording.as_ref(), state_before, &[], None, Some(e.to_string()));
+                        self.back_off(&mut backoff, &e);
+                        continue
+                    }
+                    if config.stop_requested() {
+                        // setup may have paused for a while, nothing new is sent once a stop is requested
+                        continue
+                    }
+                    match self.compile_ixs(connection, &serum_market, &mongo_client) {
+                        Ok(ix) => ix,
+                        Err(e) => {
+                            // anything staged before the error never went out
+                            self.rollback(connection, &serum_market, &mongo_client);
+                            self.record_iteration(&mut session, recording.as_ref(), state_before, &[], None, Some(e.to_string()));
+                            self.back_off(&mut backoff, &e);
+                            continue
+                        }
+                    }
+                }
+            };
             if ix.len() > 0 {
                 let message = Message::new(&ix.clone(), Some(&config.fee_payer.pubkey().clone()));
                 let mut tx = Transaction::new_unsigned(message);
                 let latest_block_hash = connection.get_latest_blockhash();
+                // only a confirmed transaction commits what compile_ixs staged, anything else rolls it back
+                let mut confirmed = false;
                 if let Ok(block_hash) = latest_block_hash {
                     println!("[?] Sending Transaction");
                     tx.sign(&[&config.fee_payer], block_hash);
@@ -86,23 +202,29 @@ pub trait BotThread {
                         let sig = connection.send_transaction(&tx);
                         if let Ok(signature) = sig {
                             let recent_blockhash = if uses_durable_nonce(&tx).is_some() {
-                                let (recent_blockhash, ..) = connection
+                                connection
                                     .get_latest_blockhash_with_commitment(CommitmentConfig::processed())
-                                    .unwrap();
-                                recent_blockhash
+                                    .map_or(block_hash, |(recent_blockhash, ..)| recent_blockhash)
                             } else {
                                 block_hash
                             };
 
                             'confirmation: for status_retry in 0..usize::MAX {
+                                // a failed status request is treated like an unknown status, the transaction may still land
+                                let status = connection
+                                    .get_signature_status_with_commitment(&signature, if processed_confirmation {CommitmentConfig::confirmed()} else {CommitmentConfig::processed()})
+                                    .unwrap_or_else(|e| {
+                                        self.log_rpc_client_error_(e);
+                                        None
+                                    });
                                 let result: Result<Signature, Option<TransactionError>> =
-                                    match connection.get_signature_status_with_commitment(&signature, if processed_confirmation {CommitmentConfig::confirmed()} else {CommitmentConfig::processed()}).unwrap() {
+                                    match status {
                                     Some(Ok(_)) => Ok(signature),
                                     Some(Err(e)) => Err(Some(e.into())),
                                     None => {
                                         if !connection
                                             .is_blockhash_valid(&recent_blockhash, CommitmentConfig::processed())
-                                            .unwrap()
+                                            .unwrap_or(true)
                                         {
                                             // Block hash is not found by some reason
                                             break 'sending;
@@ -121,7 +243,7 @@ pub trait BotThread {
                                     Ok(signature) => {
                                         if processed_confirmation {
                                             println!("[+] Transaction Successful: {:?}", sig);
-                                            self.cleanup(&connection, &serum_market, &mongo_client);
+                                            confirmed = true;
                                             break 'sending
                                         } else {
                                             processed_confirmation = true;
@@ -134,35 +256,122 @@ pub trait BotThread {
                                         eprintln!("[-] Failed to confirm transaction retrying...");
                                         continue
                                     }
-                                    Err (e) => {
+                                    Err(Some(e)) => {
                                         eprintln!("[-] Transaction Failed: {:?}", e );
+                                        error = Some(self.log_transaction_error_(ClientError::from(ClientErrorKind::TransactionError(e)), &ix));
                                         break 'confirmation
 
                                     }
                                 }
                             }
                         } else {
-                            eprintln!("[-] An Error Occurred While sending tx: {:?}", sig.unwrap_err() );
+                            let err = sig.unwrap_err();
+                            eprintln!("[-] An Error Occurred While sending tx: {:?}", err );
+                            error = Some(self.log_transaction_error_(err, &ix));
                             continue
                         }
                     }
 
                 } else {
-                    eprintln!("[-] An Error Occurred: {:?}", latest_block_hash.unwrap_err());
+                    let err = latest_block_hash.unwrap_err();
+                    eprintln!("[-] An Error Occurred: {:?}", err);
+                    error = Some(self.log_transaction_error_(err, &ix));
+                }
+                outcome = Some(confirmed);
+                if shutting_down && confirmed {
+                    // the shutdown made progress, only batches that fail in a row count
+                    shutdown_attempts = 0;
+                }
+                if confirmed {
+                    cleanup_result = self.cleanup(connection, &serum_market, &mongo_client);
+                } else {
+                    println!("[-] Transaction not confirmed, rolling back {} Thread state", self.get_name());
+                    self.rollback(connection, &serum_market, &mongo_client);
                 }
             } else {
-                self.cleanup(&connection, &serum_market, &mongo_client);
+                cleanup_result = self.cleanup(connection, &serum_market, &mongo_client);
+            }
+            self.record_iteration(&mut session, recording.as_ref(), state_before, &ix, outcome, None);
+            if let Some(error) = error {
+                let wait = backoff.next(error.class);
+                println!("[-] Pausing {} Thread for {} seconds after {}", self.get_name(), wait.as_secs(), error);
+                config.pause(wait);
+            } else if let Err(e) = cleanup_result {
+                self.back_off(&mut backoff, &e);
+            } else {
+                backoff.reset();
             }
         }
     }
 
-    fn get_updated_trader(&self, mongo_client: &MongoClient, trader: &Trader) -> Trader {
+    /// Logs an error that cut an iteration short and waits as long as its class asks for
+    fn back_off(&self, backoff: &mut ErrorBackoff, err: &TradeBotErrors) {
+        let class = err.class();
+        let wait = backoff.next(class);
+        let log = format!("{} Thread: {}, retrying in {} seconds", self.get_name(), err, wait.as_secs());
+        eprintln!("[-] {}", log);
+        let level = match class {
+            ErrorClass::Retryable => ThreadLogLevel::Warn,
+            ErrorClass::Fatal => ThreadLogLevel::Error,
+        };
+        self.send_message(ThreadMessage::compile_log_message(self.get_source(), log, level));
+        self.get_config().pause(wait);
+    }
+
+    /// Appends an iteration to the session file when the thread records one, `error`
+    /// is the error that cut the iteration short before anything was sent
+    fn record_iteration(
+        &self,
+        session: &mut Option<SessionRecorder>,
+        recording: Option<&RecordingConnection>,
+        before: Option<SessionState>,
+        ix: &[Instruction],
+        confirmed: Option<bool>,
+        error: Option<String>,
+    ) {
+        if let (Some(session), Some(recording)) = (session.as_mut(), recording) {
+            let config = self.get_config();
+            let iteration = session.next_iteration();
+            session.record(&IterationRecord {
+                version: SESSION_VERSION,
+                thread: self.get_name(),
+                iteration,
+                time: SessionRecorder::now(),
+                market_address: config.trader.market_address.clone(),
+                serum_program: config.serum_program.to_string(),
+                fee_payer: config.fee_payer.pubkey().to_string(),
+                before,
+                reads: recording.take_reads(),
+                instructions: ix.iter().map(|ix| ix.into()).collect(),
+                confirmed,
+                error,
+                after: self.session_state(),
+            });
+        }
+    }
+
+    /// What the thread carries from one iteration to the next, needed to replay a
+    /// recorded iteration. Threads without grid state have nothing to replay
+    fn session_state(&self) -> Option<SessionState> {
+        None
+    }
+
+    fn get_updated_trader(&self, mongo_client: &MongoClient, trader: &Trader) -> TradeBotResult<Trader> {
         let trader_cursor = mongo_client.traders.find_one(doc! {
             "market_address": trader.market_address.clone(),
             "owner": trader.owner.clone(),
-        }, None).unwrap();
+        }, None)?;
 
-        trader_cursor.unwrap()
+        trader_cursor.ok_or_else(|| TradeBotErrors::TraderNotFound(format!("{} on {}", trader.owner, trader.market_address)))
+    }
+
+    /// `Ok(None)` when the account does not exist, any other failure is an error
+    fn get_account_if_exists(&self, connection: &dyn RpcConnection, pubkey: &Pubkey) -> TradeBotResult<Option<Account>> {
+        match connection.get_account(pubkey) {
+            Ok(account) => Ok(Some(account)),
+            Err(e) if e.to_string().contains("AccountNotFound") => Ok(None),
+            Err(e) => Err(e.into()),
+        }
     }
     fn send_message(&self, mes: ThreadMessage) {
         match self.get_stdout().send(mes) {
@@ -175,7 +384,7 @@ pub trait BotThread {
     fn load_trader_accounts_on_market(
         &self,
         market: &Pubkey,
-        _connection: &RpcClient,
+        _connection: &dyn RpcConnection,
         mongodb: &MongoClient
     ) -> Vec<Trader> {
         let mut traders = mongodb.traders.find(Some(doc! {"market_address": market.to_string()}), None);
@@ -193,6 +402,14 @@ pub trait BotThread {
         }
     }
 
+    /// Keys of the market as verified against the chain when the registry lists it, read
+    /// from the market state otherwise
+    fn market_keys(&self, serum_market: &Market) -> MarketKeys {
+        self.get_config().market.as_ref()
+            .and_then(|market| market.keys())
+            .unwrap_or_else(|| MarketKeys::from_market(serum_market))
+    }
+
     fn bytes_to_pubkey(&self, bytes: &[u64; 4]) -> Pubkey {
         Pubkey::new(&self.nums_to_bytes(bytes)[0..32])
     }
@@ -217,6 +434,7 @@ pub trait BotThread {
                             filtered.push(Order {
                                 side,
                                 price: u64::try_from(n.price()).unwrap(),
+                                quantity: n.quantity(),
                                 client_id: n.client_order_id(),
                                 owner: n.owner(),
                                 order_id: n.order_id(),
@@ -232,40 +450,195 @@ pub trait BotThread {
     }
 
     fn parse_order_book(&self, side: serum_dex::matching::Side, slab: &Slab) -> Vec<Order> {
-        let mut filtered: Vec<Order> = vec![];
-        for i in 0..slab.capacity() {
-            match slab.get(i as u32) {
-                Some(node) => match node.as_leaf() {
-                    Some(n) => filtered.push(Order {
-                        side,
-                        price: u64::try_from(n.price()).unwrap(),
-                        client_id: n.client_order_id(),
-                        owner: n.owner(),
-                        order_id: n.order_id(),
-                    }),
-                    None => {}
-                },
-                None => {}
+        parse_slab(side, slab)
+    }
+
+    /// Cancels every order resting on the trader's open orders account, by client order id
+    /// for the ones placed by the bot and by order id for anything older
+    fn make_cancel_order_ixs(&self, serum_market: &Market, trader: &Trader, open_orders: &OpenOrders) -> Vec<Instruction> {
+        let config = self.get_config();
+        let open_orders_account_pubkey = str_to_pubkey(trader.serum_open_orders.get(0).unwrap());
+        let keys = self.market_keys(serum_market);
+        let mut cancel_ixs = vec![];
+        for slot in 0..open_orders.orders.len() {
+            let order_id = open_orders.orders[slot];
+            if order_id == 0 {
+                continue
+            }
+            let client_order_id = open_orders.client_order_ids[slot];
+            if ClientOrderId::decode(client_order_id).is_some() {
+                cancel_ixs.push(serum_dex::instruction::cancel_order_by_client_order_id(
+                    &config.serum_program,
+                    &keys.market,
+                    &keys.bids,
+                    &keys.asks,
+                    &open_orders_account_pubkey,
+                    &config.fee_payer.pubkey(),
+                    &keys.event_queue,
+                    client_order_id
+                ).unwrap());
+            } else {
+                let side = if open_orders.is_bid_bits & (1u128 << slot) != 0 { Side::Bid } else { Side::Ask };
+                cancel_ixs.push(serum_dex::instruction::cancel_order(
+                    &config.serum_program,
+                    &keys.market,
+                    &keys.bids,
+                    &keys.asks,
+                    &open_orders_account_pubkey,
+                    &config.fee_payer.pubkey(),
+                    &keys.event_queue,
+                    side,
+                    order_id
+                ).unwrap());
             }
         }
-        filtered
+        cancel_ixs
+    }
+
+    /// Moves the free balances of the open orders account back to the trader wallets
+    fn make_settle_funds_ix(&self, serum_market: &Market, trader: &Trader) -> Instruction {
+        let config = self.get_config();
+        let keys = self.market_keys(serum_market);
+        let market_pubkey = keys.market;
+        let vault_signer = serum_dex::state::gen_vault_signer_key(
+            serum_market.vault_signer_nonce,
+            &market_pubkey,
+            &config.serum_program,
+        ).unwrap();
+        serum_dex::instruction::settle_funds(
+            &config.serum_program,
+            &market_pubkey,
+            &config.token_program,
+            &str_to_pubkey(trader.serum_open_orders.get(0).unwrap()),
+            &config.fee_payer.pubkey(),
+            &keys.coin_vault,
+            &str_to_pubkey(&trader.base_trader_wallet),
+            &keys.pc_vault,
+            &str_to_pubkey(&trader.quote_trader_wallet),
+            None,
+            &vault_signer,
+        ).unwrap()
+    }
+
+    /// The program stops at the first event whose owner was not passed in, so the
+    /// owners are taken in queue order with the trader's own account always included
+    fn make_consume_events_ix(&self, serum_market: &Market, trader: &Trader, event_queue: &EventQueue) -> Instruction {
+        let config = self.get_config();
+        let own_open_orders = str_to_pubkey(trader.serum_open_orders.get(0).unwrap());
+        let mut accounts: Vec<Pubkey> = vec![own_open_orders];
+        for event in &event_queue.events {
+            if accounts.len() >= MAX_CONSUME_ACCOUNTS {
+                break
+            }
+            let owner = self.bytes_to_pubkey(&event.owner);
+            if !accounts.contains(&owner) {
+                accounts.push(owner);
+            }
+        }
+        // the program looks the accounts up with a binary search
+        accounts.sort_by_key(|account| account.to_aligned_bytes());
+        let keys = self.market_keys(serum_market);
+        serum_dex::instruction::consume_events(
+            &config.serum_program,
+            accounts.iter().collect(),
+            &keys.market,
+            &keys.event_queue,
+            &str_to_pubkey(&trader.base_trader_wallet),
+            &str_to_pubkey(&trader.quote_trader_wallet),
+            CONSUME_EVENTS_LIMIT,
+        ).unwrap()
+    }
+
+    fn associated_token_address(&self, wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+        let config = self.get_config();
+        Pubkey::find_program_address(
+            &[&wallet.to_bytes(), &config.token_program.to_bytes(), &mint.to_bytes()],
+            &config.associated_token_program,
+        ).0
+    }
+
+    /// Creates the associated token account of `wallet` for `mint`, paid for by the fee payer
+    fn make_create_associated_token_account_ix(&self, wallet: &Pubkey, mint: &Pubkey) -> Instruction {
+        let config = self.get_config();
+        Instruction {
+            program_id: config.associated_token_program,
+            accounts: vec![
+                AccountMeta::new(config.fee_payer.pubkey(), true),
+                AccountMeta::new(self.associated_token_address(wallet, mint), false),
+                AccountMeta::new_readonly(*wallet, false),
+                AccountMeta::new_readonly(*mint, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+                AccountMeta::new_readonly(config.token_program, false),
+                AccountMeta::new_readonly(sysvar::rent::id(), false),
+            ],
+            data: vec![],
+        }
+    }
+
+    /// Best bid and best ask on the market, `None` if either side of the book is empty
+    fn load_best_prices(&self, connection: &dyn RpcConnection, serum_market: &Market) -> Option<(u64, u64)> {
+        let keys = self.market_keys(serum_market);
+        let bids_account_pubkey = keys.bids;
+        let bids_account = connection.get_account(&bids_account_pubkey).ok()?;
+        let mut bids_account_clone = bids_account.clone();
+        let bids_account_info = AccountInfo {
+            key: &bids_account_pubkey,
+            is_signer: false,
+            is_writable: false,
+            lamports: Rc::new(RefCell::new(&mut bids_account_clone.lamports)),
+            data: Rc::new(RefCell::new(&mut bids_account_clone.data)),
+            owner: &bids_account.owner().clone(),
+            executable: false,
+            rent_epoch: bids_account.rent_epoch,
+        };
+        let all_bids = serum_market.load_bids_mut(&bids_account_info).ok()?;
+
+        let asks_account_pubkey = keys.asks;
+        let asks_account = connection.get_account(&asks_account_pubkey).ok()?;
+        let mut asks_account_clone = asks_account.clone();
+        let asks_account_info = AccountInfo {
+            key: &asks_account_pubkey,
+            is_signer: false,
+            is_writable: false,
+            lamports: Rc::new(RefCell::new(&mut asks_account_clone.lamports)),
+            data: Rc::new(RefCell::new(&mut asks_account_clone.data)),
+            owner: &asks_account.owner().clone(),
+            executable: false,
+            rent_epoch: asks_account.rent_epoch,
+        };
+        let all_asks = serum_market.load_asks_mut(&asks_account_info).ok()?;
+
+        let best_bid = self.parse_order_book(Side::Bid, &all_bids).into_iter().map(|order| order.price).max()?;
+        let best_ask = self.parse_order_book(Side::Ask, &all_asks).into_iter().map(|order| order.price).min()?;
+        Some((best_bid, best_ask))
     }
 
-    fn setup(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) {}
-    fn cleanup(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) {}
+    fn setup(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        Ok(())
+    }
+    fn cleanup(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        Ok(())
+    }
+    /// Instructions sent before the thread stops on a stop request, asked for again after
+    /// every attempt until there is nothing left to send
+    fn compile_shutdown_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        Ok(vec![])
+    }
+    /// Runs instead of `cleanup` when the compiled transaction failed or could not be confirmed
+    fn rollback(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) {}
 
     fn get_config(&self) -> Arc<BotConfig>;
     fn get_stdout(&self) -> Sender<ThreadMessage>;
     fn get_source(&self) -> ThreadMessageSource;
     fn get_name(&self) -> String;
 
-    fn compile_ixs(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) -> Vec<Instruction>;
+    fn compile_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>>;
     fn log_rpc_client_error_(&self, err: ClientError);
-    fn log_transaction_logs_(&self, connection: &RpcClient, sig: &Signature);
+    fn log_transaction_error_(&self, err: ClientError, ixs: &[Instruction]) -> DecodedError;
+    fn log_transaction_logs_(&self, connection: &dyn RpcConnection, sig: &Signature);
 }
 
 
-#[derive(Debug)]
 pub struct BotConfig {
     pub serum_program: Pubkey,
     pub token_program: Pubkey,
@@ -274,4 +647,54 @@ pub struct BotConfig {
     pub trader: Trader,
     pub rpc_url: String,
     pub fee_payer: Keypair,
+    /// Shared by all the threads of the trader
+    pub connection: Arc<dyn RpcConnection>,
+    /// Every worker iteration is recorded to a session file in this directory when set
+    pub session_dir: Option<PathBuf>,
+    /// Set to stop the threads of the trader at the start of their next iteration
+    pub stop: Arc<AtomicBool>,
+    pub settings: Arc<Settings>,
+    /// Entry of the market registry, with its decimals and lot sizes once verified
+    pub market: Option<MarketInfo>,
+    /// Book of the market shared with the other traders on it, the trader reads it
+    /// itself when unset
+    pub market_data: Option<Arc<MarketDataHub>>,
+}
+
+impl BotConfig {
+    pub fn request_stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    pub fn stop_requested(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    /// Sleeps for `duration`, waking up early once a stop is requested
+    pub fn pause(&self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while !self.stop_requested() {
+            let now = Instant::now();
+            if now >= deadline {
+                return
+            }
+            sleep(min(deadline - now, STOP_POLL_INTERVAL));
+        }
+    }
+}
+
+impl fmt::Debug for BotConfig {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("BotConfig")
+            .field("serum_program", &self.serum_program)
+            .field("token_program", &self.token_program)
+            .field("associated_token_program", &self.associated_token_program)
+            .field("trader", &self.trader)
+            .field("rpc_url", &self.rpc_url)
+            .field("fee_payer", &self.fee_payer)
+            .field("session_dir", &self.session_dir)
+            .field("stop", &self.stop_requested())
+            .field("settings", &self.settings)
+            .finish()
+    }
 }
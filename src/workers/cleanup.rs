@@ -1,20 +1,15 @@
 use std::cell::RefCell;
-use std::cmp::min;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::mpsc::Sender;
-use std::thread::sleep;
 use std::time::Duration;
-use itertools::Itertools;
 use mongodb::bson::{doc, to_bson};
 use mongodb::options::UpdateModifications;
-use serum_dex::critbit::Slab;
-use serum_dex::matching::Side;
 
 
-use serum_dex::state::{Market};
+use serum_dex::state::{Market, ToAlignedBytes};
 use solana_client::client_error::ClientError;
-use solana_client::rpc_client::RpcClient;
+use crate::rpc::client::RpcConnection;
 use solana_program::account_info::AccountInfo;
 
 
@@ -27,28 +22,238 @@ use solana_sdk::signature::{Signature, Signer};
 
 use crate::{MongoClient, str_to_pubkey, TraderStatus};
 use crate::mongodb::models::Trader;
-use crate::serum::state::Order;
-use crate::workers::base::{BotConfig, BotThread, MAX_IXS};
-use crate::workers::message::{ThreadMessage, ThreadMessageCompiler, ThreadMessageSource};
+use crate::serum::events::EventQueue;
+use crate::workers::base::{BotConfig, BotThread};
+use crate::workers::error::{DecodedError, TradeBotErrors, TradeBotResult};
+use crate::workers::message::{ThreadLogLevel, ThreadMessage, ThreadMessageCompiler, ThreadMessageSource};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where a decommissioned trader is in winding down, derived from on-chain state every iteration
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecommissionStage {
+    Cancelling,
+    Consuming,
+    Settling,
+    Withdrawing { base: u64, quote: u64 },
+    Closing,
+    Done,
+}
 
 pub struct CleanupThread {
     pub stdout: Sender<ThreadMessage>,
     pub config: Arc<BotConfig>,
+    pub stage: Option<DecommissionStage>,
 }
 
 impl CleanupThread {
+    fn match_orders_ix(&self, serum_market: &Market, trader: &Trader) -> Instruction {
+        let keys = self.market_keys(serum_market);
+        serum_dex::instruction::match_orders(
+            &self.config.serum_program,
+            &keys.market,
+            &keys.request_queue,
+            &keys.bids,
+            &keys.asks,
+            &keys.event_queue,
+            &str_to_pubkey(&trader.base_trader_wallet),
+            &str_to_pubkey(&trader.quote_trader_wallet),
+            5
+        ).unwrap()
+    }
+
+    /// Wallets that are gone or not token accounts hold nothing
+    fn token_balance(&self, connection: &dyn RpcConnection, wallet: &Pubkey) -> TradeBotResult<u64> {
+        Ok(self.get_account_if_exists(connection, wallet)?
+            .and_then(|wallet_account| spl_token::state::Account::unpack(wallet_account.data()).ok())
+            .map_or(0, |account| account.amount))
+    }
+
+    /// Cancels, cranks and settles until the open orders account is empty
+    fn compile_open_orders_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, trader: &Trader) -> TradeBotResult<Option<Vec<Instruction>>> {
+        let open_orders_account_pubkey = match trader.serum_open_orders.get(0) {
+            Some(open_orders) => str_to_pubkey(open_orders),
+            None => return Ok(None)
+        };
+        let open_orders_account = match self.get_account_if_exists(connection, &open_orders_account_pubkey)? {
+            Some(account) => account,
+            None => return Ok(None)
+        };
+        let mut open_orders_account_clone = open_orders_account.clone();
+
+        let open_orders_account_info = AccountInfo {
+            key: &open_orders_account_pubkey,
+            is_signer: false,
+            is_writable: false,
+            lamports: Rc::new(RefCell::new(&mut open_orders_account_clone.lamports)),
+            data: Rc::new(RefCell::new(&mut open_orders_account_clone.data)),
+            owner: &open_orders_account.owner().clone(),
+            executable: false,
+            rent_epoch: open_orders_account.rent_epoch,
+        };
+        let open_orders = match serum_market
+            .load_orders_mut(
+                &open_orders_account_info,
+                None,
+                &self.config.serum_program,
+                None,
+                None,
+            ) {
+            Ok(open_orders) => open_orders,
+            // already closed, the account is no longer a serum open orders account
+            Err(_) => return Ok(None)
+        };
+
+        let cancel_ixs = self.make_cancel_order_ixs(serum_market, trader, &open_orders);
+        if !cancel_ixs.is_empty() {
+            self.stage = Some(DecommissionStage::Cancelling);
+            let match_ix = self.match_orders_ix(serum_market, trader);
+            let mut ixs = vec![match_ix.clone()];
+            ixs.extend(cancel_ixs.into_iter().take(self.config.settings.max_ixs - 3));
+            ixs.push(match_ix);
+            ixs.push(self.make_settle_funds_ix(serum_market, trader));
+            return Ok(Some(ixs))
+        }
+
+        let event_queue_account = connection.get_account(&self.market_keys(serum_market).event_queue)?;
+        let event_queue = EventQueue::from_buffer(event_queue_account.data())
+            .ok_or_else(|| TradeBotErrors::Decoding("event queue".to_string()))?;
+        if !event_queue.events_for_owner(&open_orders_account_pubkey.to_aligned_bytes()).is_empty() {
+            self.stage = Some(DecommissionStage::Consuming);
+            return Ok(Some(vec![
+                self.make_consume_events_ix(serum_market, trader, &event_queue),
+                self.make_settle_funds_ix(serum_market, trader),
+            ]))
+        }
+
+        if open_orders.native_coin_total > 0 || open_orders.native_pc_total > 0 {
+            self.stage = Some(DecommissionStage::Settling);
+            return Ok(Some(vec![self.make_settle_funds_ix(serum_market, trader)]))
+        }
+        Ok(None)
+    }
+
+    /// Sends whatever is left in the trader wallets to the owner's associated token accounts
+    fn compile_withdraw_ixs(&mut self, connection: &dyn RpcConnection, trader: &Trader) -> TradeBotResult<Option<Vec<Instruction>>> {
+        let base_wallet = str_to_pubkey(&trader.base_trader_wallet);
+        let quote_wallet = str_to_pubkey(&trader.quote_trader_wallet);
+        let base = self.token_balance(connection, &base_wallet)?;
+        let quote = self.token_balance(connection, &quote_wallet)?;
+        if base == 0 && quote == 0 {
+            return Ok(None)
+        }
+        let owner = str_to_pubkey(&trader.owner);
+        let mut ixs = vec![];
+        for (wallet, mint, amount) in vec![
+            (base_wallet, str_to_pubkey(&trader.base_token_info.address), base),
+            (quote_wallet, str_to_pubkey(&trader.quote_token_info.address), quote),
+        ] {
+            if amount == 0 {
+                continue
+            }
+            let destination = self.associated_token_address(&owner, &mint);
+            if self.get_account_if_exists(connection, &destination)?.is_none() {
+                ixs.push(self.make_create_associated_token_account_ix(&owner, &mint));
+            }
+            ixs.push(spl_token::instruction::transfer(
+                &self.config.token_program,
+                &wallet,
+                &destination,
+                &self.config.fee_payer.pubkey(),
+                &[],
+                amount,
+            ).unwrap());
+        }
+        self.stage = Some(DecommissionStage::Withdrawing { base, quote });
+        Ok(Some(ixs))
+    }
 
+    /// Closes the open orders account and the emptied token accounts, the rent goes back to the fee payer
+    fn compile_close_ixs(&mut self, connection: &dyn RpcConnection, trader: &Trader) -> TradeBotResult<Vec<Instruction>> {
+        let payer = self.config.fee_payer.pubkey();
+        let mut ixs = vec![];
+        if let Some(open_orders) = trader.serum_open_orders.get(0) {
+            let open_orders = str_to_pubkey(open_orders);
+            if self.get_account_if_exists(connection, &open_orders)?.is_some() {
+                ixs.push(serum_dex::instruction::close_open_orders(
+                    &self.config.serum_program,
+                    &open_orders,
+                    &payer,
+                    &payer,
+                    &str_to_pubkey(&trader.market_address),
+                ).unwrap());
+            }
+        }
+        for wallet in vec![&trader.base_trader_wallet, &trader.quote_trader_wallet] {
+            let wallet = str_to_pubkey(wallet);
+            if self.get_account_if_exists(connection, &wallet)?.is_some() {
+                ixs.push(spl_token::instruction::close_account(
+                    &self.config.token_program,
+                    &wallet,
+                    &payer,
+                    &payer,
+                    &[],
+                ).unwrap());
+            }
+        }
+        self.stage = Some(if ixs.is_empty() { DecommissionStage::Done } else { DecommissionStage::Closing });
+        Ok(ixs)
+    }
 
+    fn update_trader(&self, mongo_client: &MongoClient, trader: &Trader, update: mongodb::bson::Document) -> TradeBotResult<()> {
+        mongo_client.traders.find_one_and_update(
+            doc! {
+            "market_address": trader.market_address.clone(),
+            "owner": trader.owner.clone(),
+        }, UpdateModifications::Document(doc! {
+                "$set": update
+            }),
+            None
+        )?;
+        Ok(())
+    }
 }
 
 impl ThreadMessageCompiler for CleanupThread {}
 
 impl BotThread for CleanupThread {
-    fn setup(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) {
-
-
+    fn setup(&mut self, _connection: &dyn RpcConnection, _serum_market: &Market, _mongo_client: &MongoClient) -> TradeBotResult<()> {
+        self.stage = None;
+        self.config.pause(CLEANUP_INTERVAL);
+        Ok(())
+    }
 
+    /// Runs after a confirmed transaction or when there was nothing to send, so the
+    /// stage that was just compiled is committed here
+    fn cleanup(&mut self, _connection: &dyn RpcConnection, _serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        let trader = self.get_updated_trader(mongo_client, &self.config.trader)?;
+        match self.stage.take() {
+            Some(DecommissionStage::Withdrawing { base, quote }) => {
+                let withdrawn_base_balance = trader.withdrawn_base_balance.unwrap_or(0) + base;
+                let withdrawn_quote_balance = trader.withdrawn_quote_balance.unwrap_or(0) + quote;
+                self.log_str(&format!("Withdrew {} base and {} quote to {}", base, quote, trader.owner), ThreadLogLevel::Info);
+                self.update_trader(mongo_client, &trader, doc! {
+                    "withdrawn_base_balance": to_bson(&withdrawn_base_balance)?,
+                    "withdrawn_quote_balance": to_bson(&withdrawn_quote_balance)?,
+                    "base_balance": to_bson(&0u64)?,
+                    "quote_balance": to_bson(&0u64)?,
+                })?;
+            }
+            Some(DecommissionStage::Closing) | Some(DecommissionStage::Done) => {
+                self.log_str("Trader accounts closed, trader is decommissioned", ThreadLogLevel::Info);
+                self.update_trader(mongo_client, &trader, doc! {
+                    "status": to_bson(&TraderStatus::Closed)?,
+                })?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 
+    fn rollback(&mut self, _connection: &dyn RpcConnection, _serum_market: &Market, _mongo_client: &MongoClient) {
+        if let Some(stage) = self.stage.take() {
+            self.log_str(&format!("Decommission step {:?} failed, retrying", stage), ThreadLogLevel::Warn);
+        }
     }
 
     fn get_config(&self) -> Arc<BotConfig> {
@@ -62,7 +267,7 @@ impl BotThread for CleanupThread {
 
 
     fn get_source(&self) -> ThreadMessageSource {
-        return ThreadMessageSource::Settler;
+        return ThreadMessageSource::Cleanup;
     }
 
     fn get_name(&self) -> String {
@@ -70,82 +275,37 @@ impl BotThread for CleanupThread {
     }
 
 
-    fn compile_ixs(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) -> Vec<Instruction> {
-        let mut trader = self.get_updated_trader(mongo_client, &self.config.trader);
-        if trader.status != TraderStatus::Decommissioned && trader.status != TraderStatus::Stopped  {
-            return vec![]
-        }
-        let mut ixs = vec![];
-        let open_orders_account_pubkey = str_to_pubkey(trader.serum_open_orders.get(0).unwrap());
-        let open_orders_account = connection.get_account(&open_orders_account_pubkey).unwrap();
-        let mut open_orders_account_clone = open_orders_account.clone();
-
-        let open_orders_account_info = AccountInfo {
-            key: &open_orders_account_pubkey,
-            is_signer: false,
-            is_writable: false,
-            lamports: Rc::new(RefCell::new(&mut open_orders_account_clone.lamports)),
-            data: Rc::new(RefCell::new(&mut open_orders_account_clone.data)),
-            owner: &open_orders_account.owner().clone(),
-            executable: false,
-            rent_epoch: open_orders_account.rent_epoch,
-        };
-        let open_orders_result = serum_market
-            .load_orders_mut(
-                &open_orders_account_info,
-                None,
-                &self.config.serum_program,
-                None,
-                None,
-            );
-
-        if let Ok(open_orders) = open_orders_result {
-            let non_zero_orders = open_orders.orders.into_iter().filter(|o| **o != 0 as u128).collect::<Vec<&u128>>();
-            for mut i in 0..min(non_zero_orders.len(), MAX_IXS) {
-                let order = non_zero_orders.get(i).unwrap();
-                let grid_order = trader.grids.clone()
-                    .clone()
-                    .into_iter()
-                    .find_position(|grid| grid.order.is_some() && grid.order.as_ref().unwrap().order_id == order.to_string());
-                if let Some((grid_index, go)) = grid_order {
-                    let match_ix = serum_dex::instruction::match_orders(
-                        &self.config.serum_program,
-                        &str_to_pubkey(&trader.market_address),
-                        &self.bytes_to_pubkey(&serum_market.req_q),
-                        &self.bytes_to_pubkey(&serum_market.bids),
-                        &self.bytes_to_pubkey(&serum_market.asks),
-                        &self.bytes_to_pubkey(&serum_market.event_q),
-                        &str_to_pubkey(&trader.base_trader_wallet),
-                        &str_to_pubkey(&trader.quote_trader_wallet),
-                        5
-                    ).unwrap();
-                    let cancel_ix = serum_dex::instruction::cancel_order(
-                        &self.config.serum_program,
-                        &str_to_pubkey(&trader.market_address),
-                        &self.bytes_to_pubkey(&serum_market.bids),
-                        &self.bytes_to_pubkey(&serum_market.asks),
-                        &open_orders_account_pubkey,
-                        &self.config.fee_payer.pubkey(),
-                        &self.bytes_to_pubkey(&serum_market.event_q),
-                        go.order.unwrap().side,
-                        **order
-                    ).unwrap();
-
-                    ixs.push(match_ix.clone());
-                    ixs.push(cancel_ix);
-                    ixs.push(match_ix);
-                    i += 2;
+    fn compile_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        let trader = self.get_updated_trader(mongo_client, &self.config.trader)?;
+        match trader.status {
+            TraderStatus::Stopped => {
+                // a stopped trader keeps its accounts, only make sure nothing is left resting
+                let ixs = self.compile_open_orders_ixs(connection, serum_market, &trader)?.unwrap_or(vec![]);
+                self.stage = None;
+                Ok(ixs)
+            }
+            TraderStatus::Decommissioned => {
+                if let Some(ixs) = self.compile_open_orders_ixs(connection, serum_market, &trader)? {
+                    return Ok(ixs)
+                }
+                if let Some(ixs) = self.compile_withdraw_ixs(connection, &trader)? {
+                    return Ok(ixs)
                 }
+                self.compile_close_ixs(connection, &trader)
             }
+            _ => Ok(vec![])
         }
-        ixs
     }
 
     fn log_rpc_client_error_(&self, err: ClientError) {
         self.log_rpc_client_error(err);
     }
 
-    fn log_transaction_logs_(&self, connection: &RpcClient, sig: &Signature) {
+    fn log_transaction_error_(&self, err: ClientError, ixs: &[Instruction]) -> DecodedError {
+        self.log_transaction_error(err, ixs)
+    }
+
+    fn log_transaction_logs_(&self, connection: &dyn RpcConnection, sig: &Signature) {
         self.log_transaction_logs(connection, sig)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use serum_dex::state::{Market, ToAlignedBytes};
+use solana_client::client_error::ClientError;
+use crate::rpc::client::RpcConnection;
+use solana_program::instruction::Instruction;
+use solana_sdk::account::ReadableAccount;
+use solana_sdk::signature::Signature;
+
+use crate::{MongoClient, str_to_pubkey};
+use crate::serum::events::EventQueue;
+use crate::workers::base::BotConfig;
+use crate::workers::error::{DecodedError, TradeBotErrors, TradeBotResult};
+use crate::workers::base::BotThread;
+use crate::workers::message::{ThreadLogLevel, ThreadMessage, ThreadMessageCompiler, ThreadMessageSource};
+
+const DEFAULT_CONSUME_INTERVAL_SECS: u64 = 10;
+
+/// Cranks the event queue of the market so fills of our orders get credited to the
+/// open orders account even when nobody else is cranking it. The queue is left alone
+/// while none of the pending events are ours
+pub struct EventConsumer {
+    pub stdout: Sender<ThreadMessage>,
+    pub config: Arc<BotConfig>,
+}
+
+impl ThreadMessageCompiler for EventConsumer {}
+
+impl BotThread for EventConsumer {
+    fn setup(&mut self, _connection: &dyn RpcConnection, _serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        let trader = self.get_updated_trader(mongo_client, &self.config.trader)?;
+        self.config.pause(Duration::from_secs(trader.consume_events_interval.unwrap_or(DEFAULT_CONSUME_INTERVAL_SECS)));
+        Ok(())
+    }
+
+    fn get_config(&self) -> Arc<BotConfig> {
+        self.config.clone()
+    }
+
+    fn get_stdout(&self) -> Sender<ThreadMessage> {
+        self.stdout.clone()
+    }
+
+    fn get_source(&self) -> ThreadMessageSource {
+        return ThreadMessageSource::EventConsumer;
+    }
+
+    fn get_name(&self) -> String {
+        "EventConsumer".to_string()
+    }
+
+    fn compile_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        let trader = self.get_updated_trader(mongo_client, &self.config.trader)?;
+        let open_orders_account_pubkey = match trader.serum_open_orders.get(0) {
+            Some(open_orders) => str_to_pubkey(open_orders),
+            None => return Ok(vec![])
+        };
+        let event_queue_pubkey = self.market_keys(serum_market).event_queue;
+        let event_queue_account = connection.get_account(&event_queue_pubkey)?;
+        let event_queue = EventQueue::from_buffer(event_queue_account.data())
+            .ok_or_else(|| TradeBotErrors::Decoding(format!("event queue {}", event_queue_pubkey)))?;
+        let own_events = event_queue.events_for_owner(&open_orders_account_pubkey.to_aligned_bytes()).len();
+        if own_events == 0 {
+            // cranking only the others' events pays fees for nothing we trade
+            return Ok(vec![])
+        }
+        let consume_ix = self.make_consume_events_ix(serum_market, &trader, &event_queue);
+        self.log_str(
+            &format!("Consuming {} pending events ({} ours)", event_queue.events.len(), own_events),
+            ThreadLogLevel::Info,
+        );
+        Ok(vec![consume_ix])
+    }
+
+    fn log_rpc_client_error_(&self, err: ClientError) {
+        self.log_rpc_client_error(err);
+    }
+
+    fn log_transaction_error_(&self, err: ClientError, ixs: &[Instruction]) -> DecodedError {
+        self.log_transaction_error(err, ixs)
+    }
+
+    fn log_transaction_logs_(&self, connection: &dyn RpcConnection, sig: &Signature) {
+        self.log_transaction_logs(connection, sig)
+    }
+}
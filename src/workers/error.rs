@@ -1,7 +1,21 @@
-use num_enum::{ TryFromPrimitive};
+use std::fmt;
+use std::time::Duration;
+
+use num_traits::FromPrimitive;
+use serum_dex::error::DexError;
+use serum_dex::instruction::MarketInstruction;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+use solana_program::instruction::{Instruction, InstructionError};
 use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::transaction::TransactionError;
+use spl_token::error::TokenError;
 use thiserror::Error;
 
+use crate::serum::errors::{self, decode_assertion, decode_error_code, DexErrorCode};
+use crate::serum::state::ClientOrderId;
+
 pub type TradeBotResult<T> = Result<T, TradeBotErrors>;
 
 #[derive( Debug, PartialEq, Eq)]
@@ -10,8 +24,7 @@ pub enum TradeBotError {
 
 
 }
-#[repr(u8)]
-#[derive(Debug, Error, Clone, PartialEq, PartialOrd, Eq, TryFromPrimitive)]
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
 pub enum TradeBotErrors {
     #[error("Instruction Is not known by the program(a.k.a me)")]
     UnknownInstruction ,
@@ -24,7 +37,7 @@ pub enum TradeBotErrors {
     #[error("You are not authorized to perform this action")]
     Unauthorized,
     #[error("Trader already exists")]
-    TraderExists = 5,
+    TraderExists,
     #[error("Not enough tokens")]
     InsufficientTokens,
     #[error("The limit for the maximum number of open orders is passed")]
@@ -34,23 +47,83 @@ pub enum TradeBotErrors {
     #[error("Price range already has an unfilled order")]
     PriceAlreadyTraded,
     #[error("Price is lower than stop loss price")]
-    StopLossLimit = 10,
+    StopLossLimit,
     #[error("Price value is set too low to place a valid trade")]
     ProfitTooLow,
     #[error("Program Error")]
     ProgramErr,
     #[error("Unknown error")]
     UnknownError,
+    #[error("Grid configuration is invalid")]
+    InvalidGridConfig,
+    #[error("{0}")]
+    Rpc(DecodedError),
+    #[error("Database Error: {0}")]
+    Database(String),
+    #[error("Failed to decode {0}")]
+    Decoding(String),
+    #[error("Trader {0} is not in the database")]
+    TraderNotFound(String),
 
 
 }
 
-
+impl TradeBotErrors {
+    /// Error codes the variants had when they were returned as program errors
+    pub fn code(&self) -> u32 {
+        match self {
+            TradeBotErrors::UnknownInstruction => 0,
+            TradeBotErrors::InvalidInstruction => 1,
+            TradeBotErrors::MarketAlreadyInitialized => 2,
+            TradeBotErrors::MarketNotKnown => 3,
+            TradeBotErrors::Unauthorized => 4,
+            TradeBotErrors::TraderExists => 5,
+            TradeBotErrors::InsufficientTokens => 6,
+            TradeBotErrors::ExceededOpenOrdersLimit => 7,
+            TradeBotErrors::NoTradesFoundOnMarket => 8,
+            TradeBotErrors::PriceAlreadyTraded => 9,
+            TradeBotErrors::StopLossLimit => 10,
+            TradeBotErrors::ProfitTooLow => 11,
+            TradeBotErrors::ProgramErr => 12,
+            TradeBotErrors::UnknownError => 13,
+            TradeBotErrors::InvalidGridConfig => 14,
+            TradeBotErrors::Rpc(_) => 15,
+            TradeBotErrors::Database(_) => 16,
+            TradeBotErrors::Decoding(_) => 17,
+            TradeBotErrors::TraderNotFound(_) => 18,
+        }
+    }
+}
 
 impl From<TradeBotErrors> for ProgramError {
     fn from(e: TradeBotErrors) -> ProgramError {
 
-        ProgramError::Custom(e as u8 as u32)
+        ProgramError::Custom(e.code())
+    }
+}
+
+/// Errors outside of a transaction have no instructions to map back to grids
+impl From<ClientError> for TradeBotErrors {
+    fn from(err: ClientError) -> Self {
+        TradeBotErrors::Rpc(DecodedError::from_client_error(&err, &Pubkey::default(), &[]))
+    }
+}
+
+impl From<mongodb::error::Error> for TradeBotErrors {
+    fn from(err: mongodb::error::Error) -> Self {
+        TradeBotErrors::Database(err.to_string())
+    }
+}
+
+impl From<mongodb::bson::ser::Error> for TradeBotErrors {
+    fn from(err: mongodb::bson::ser::Error) -> Self {
+        TradeBotErrors::Database(err.to_string())
+    }
+}
+
+impl From<DexError> for TradeBotErrors {
+    fn from(err: DexError) -> Self {
+        TradeBotErrors::Decoding(format!("serum account: {:?}", err))
     }
 }
 
@@ -61,3 +134,198 @@ impl From<ProgramError> for TradeBotErrors {
     }
 }
 
+
+/// Whether the worker should try again as usual or back off until someone looks at it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Retryable,
+    Fatal,
+}
+
+impl TradeBotErrors {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            TradeBotErrors::Rpc(decoded) => decoded.class,
+            TradeBotErrors::InsufficientTokens
+            | TradeBotErrors::ExceededOpenOrdersLimit
+            | TradeBotErrors::NoTradesFoundOnMarket
+            | TradeBotErrors::PriceAlreadyTraded
+            | TradeBotErrors::Database(_) => ErrorClass::Retryable,
+            _ => ErrorClass::Fatal,
+        }
+    }
+}
+
+pub const FIRST_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+/// Pause after an error that will not go away by retrying
+pub const FATAL_ERROR_BACKOFF: Duration = Duration::from_secs(60);
+
+/// How long a worker waits before its next iteration after an error, retryable
+/// errors double the wait up to `MAX_RETRY_BACKOFF` until an iteration goes through
+pub struct ErrorBackoff {
+    next_retry: Duration,
+}
+
+impl ErrorBackoff {
+    pub fn new() -> Self {
+        ErrorBackoff {
+            next_retry: FIRST_RETRY_BACKOFF,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.next_retry = FIRST_RETRY_BACKOFF;
+    }
+
+    pub fn next(&mut self, class: ErrorClass) -> Duration {
+        match class {
+            ErrorClass::Retryable => {
+                let wait = self.next_retry;
+                self.next_retry = (self.next_retry * 2).min(MAX_RETRY_BACKOFF);
+                wait
+            }
+            ErrorClass::Fatal => FATAL_ERROR_BACKOFF,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodedErrorKind {
+    Serum(DexErrorCode),
+    /// A `check_assert!` that failed inside serum
+    SerumAssertion { file: &'static str, line: u32 },
+    Token(TokenError),
+    /// A custom error of a program we have no table for
+    Custom(u32),
+    Instruction(InstructionError),
+    Transaction(TransactionError),
+    /// The request never reached the cluster or the node refused it
+    Rpc(String),
+}
+
+/// An error of a transaction or rpc call with the failing instruction, and the
+/// grid whose order it was when it was a new order or a cancel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedError {
+    pub kind: DecodedErrorKind,
+    pub instruction_index: Option<u8>,
+    pub grid_index: Option<usize>,
+    pub class: ErrorClass,
+}
+
+impl DecodedError {
+    pub fn from_client_error(err: &ClientError, serum_program: &Pubkey, ixs: &[Instruction]) -> Self {
+        let transaction_error = match &err.kind {
+            ClientErrorKind::TransactionError(err) => Some(err.clone()),
+            ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+                ..
+            }) => result.err.clone(),
+            _ => None,
+        };
+        if let Some(transaction_error) = transaction_error {
+            return Self::from_transaction_error(&transaction_error, serum_program, ixs);
+        }
+        let class = match &err.kind {
+            ClientErrorKind::SigningError(_) => ErrorClass::Fatal,
+            _ => ErrorClass::Retryable,
+        };
+        DecodedError {
+            kind: DecodedErrorKind::Rpc(err.to_string()),
+            instruction_index: None,
+            grid_index: None,
+            class,
+        }
+    }
+
+    pub fn from_transaction_error(err: &TransactionError, serum_program: &Pubkey, ixs: &[Instruction]) -> Self {
+        match err {
+            TransactionError::InstructionError(index, instruction_error) => {
+                let ix = ixs.get(*index as usize);
+                let kind = match instruction_error {
+                    InstructionError::Custom(code) => decode_custom(*code, ix.map(|ix| &ix.program_id), serum_program),
+                    _ => DecodedErrorKind::Instruction(instruction_error.clone()),
+                };
+                DecodedError {
+                    class: kind.class(),
+                    kind,
+                    instruction_index: Some(*index),
+                    grid_index: ix.filter(|ix| ix.program_id == *serum_program).and_then(grid_of_instruction),
+                }
+            }
+            _ => {
+                let kind = DecodedErrorKind::Transaction(err.clone());
+                DecodedError {
+                    class: kind.class(),
+                    kind,
+                    instruction_index: None,
+                    grid_index: None,
+                }
+            }
+        }
+    }
+}
+
+impl DecodedErrorKind {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            DecodedErrorKind::Serum(code) => errors::class(*code),
+            DecodedErrorKind::Token(TokenError::InsufficientFunds) => ErrorClass::Retryable,
+            DecodedErrorKind::Transaction(TransactionError::AccountInUse)
+            | DecodedErrorKind::Transaction(TransactionError::BlockhashNotFound)
+            | DecodedErrorKind::Transaction(TransactionError::ClusterMaintenance)
+            | DecodedErrorKind::Transaction(TransactionError::WouldExceedMaxBlockCostLimit) => ErrorClass::Retryable,
+            DecodedErrorKind::Rpc(_) => ErrorClass::Retryable,
+            _ => ErrorClass::Fatal,
+        }
+    }
+}
+
+impl fmt::Display for DecodedError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut str = match &self.kind {
+            DecodedErrorKind::Serum(code) => format!("Serum {:?}: {}", code, errors::explanation(*code)),
+            DecodedErrorKind::SerumAssertion { file, line } => format!("Serum assertion failed at {}:{}", file, line),
+            DecodedErrorKind::Token(err) => format!("Token {:?}: {}", err, err),
+            DecodedErrorKind::Custom(code) => format!("Unknown Error: {} ({:#x})", code, code),
+            DecodedErrorKind::Instruction(err) => format!("Instruction Error: {:?}", err),
+            DecodedErrorKind::Transaction(err) => format!("Transaction Error: {:?}", err),
+            DecodedErrorKind::Rpc(err) => format!("Rpc Error: {}", err),
+        };
+        if let Some(index) = self.instruction_index {
+            str.push_str(&format!(", instruction {}", index));
+        }
+        if let Some(grid_index) = self.grid_index {
+            str.push_str(&format!(" placed for grid {}", grid_index));
+        }
+        str.push_str(&format!(" [{:?}]", self.class));
+        write!(fmt, "{}", str)
+    }
+}
+
+fn decode_custom(code: u32, program_id: Option<&Pubkey>, serum_program: &Pubkey) -> DecodedErrorKind {
+    match program_id {
+        Some(program_id) if program_id == serum_program => {
+            if let Some((file, line)) = decode_assertion(code) {
+                DecodedErrorKind::SerumAssertion { file, line }
+            } else {
+                decode_error_code(code).map_or(DecodedErrorKind::Custom(code), DecodedErrorKind::Serum)
+            }
+        }
+        Some(program_id) if *program_id == spl_token::id() => {
+            TokenError::from_u32(code).map_or(DecodedErrorKind::Custom(code), DecodedErrorKind::Token)
+        }
+        _ => DecodedErrorKind::Custom(code),
+    }
+}
+
+/// New orders and cancels by client id carry the grid in their client order id
+fn grid_of_instruction(ix: &Instruction) -> Option<usize> {
+    let client_order_id = match MarketInstruction::unpack(&ix.data)? {
+        MarketInstruction::NewOrderV3(new_order) => new_order.client_order_id,
+        MarketInstruction::CancelOrderByClientIdV2(client_order_id) => client_order_id,
+        _ => return None,
+    };
+    ClientOrderId::decode(client_order_id).map(|decoded| decoded.grid_index as usize)
+}
@@ -0,0 +1,112 @@
+use std::cell::RefCell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mongodb::bson::{doc, to_bson};
+use mongodb::options::UpdateOptions;
+use solana_program::pubkey::Pubkey;
+
+use crate::mongodb::client::MongoClient;
+use crate::mongodb::models::{Fill, Trader};
+use crate::serum::events::{Event, EventQueue};
+use crate::workers::error::TradeBotResult;
+
+/// Keeps track of how far into the event queue we have read so the events of our open
+/// orders account are only looked at once per run. Across restarts fills are kept from
+/// being applied twice by `Trader::last_fill_seq_num`
+pub struct FillTracker {
+    /// Last event of the queue the saved grids account for
+    pub last_seq_num: Option<u64>,
+    /// Last event read by `poll`, it only becomes `last_seq_num` once the grids are saved
+    pub polled_seq_num: Option<u64>,
+}
+
+impl FillTracker {
+    pub fn new() -> Self {
+        FillTracker {
+            last_seq_num: None,
+            polled_seq_num: None,
+        }
+    }
+
+    /// The events read by the last poll are in the saved grids, they are not polled again.
+    /// Without a commit the next poll reads them again
+    pub fn commit(&mut self) {
+        if let Some(polled_seq_num) = self.polled_seq_num.take() {
+            self.last_seq_num = Some(polled_seq_num);
+        }
+    }
+
+    /// Returns the events of `owner` that were pushed since the last poll
+    pub fn poll(&mut self, event_queue: &EventQueue, owner: &[u64; 4]) -> Vec<Event> {
+        let last_seq_num = self.last_seq_num;
+        let new_events = event_queue
+            .events_for_owner(owner)
+            .into_iter()
+            .filter(|event| last_seq_num.map_or(true, |last| event.seq_num > last))
+            .collect();
+        if let Some(last_event) = event_queue.events.last() {
+            self.polled_seq_num = Some(last_event.seq_num);
+        }
+        new_events
+    }
+
+    pub fn to_fill(&self, trader: &Trader, open_orders: &Pubkey, event: &Event, grid_price: Option<u64>) -> Fill {
+        Fill {
+            market_address: trader.market_address.clone(),
+            owner: trader.owner.clone(),
+            open_orders: open_orders.to_string(),
+            seq_num: event.seq_num,
+            side: event.side(),
+            maker: event.is_maker(),
+            price: event.price(),
+            native_qty_paid: event.native_qty_paid,
+            native_qty_received: event.native_qty_released,
+            native_fee_or_rebate: event.native_fee_or_rebate,
+            order_id: event.order_id.to_string(),
+            client_order_id: event.client_order_id,
+            grid_price,
+            time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        }
+    }
+}
+
+/// Where fills are kept as the record of what was traded, the fills collection in production
+pub trait FillStore {
+    /// Fills are keyed by open orders account and sequence number so replaying the
+    /// queue after a restart does not duplicate them. Whether a fill reached the grids
+    /// is kept on the trader, the store is only the record of what was traded
+    fn record_fill(&self, fill: &Fill) -> TradeBotResult<()>;
+}
+
+impl FillStore for MongoClient {
+    fn record_fill(&self, fill: &Fill) -> TradeBotResult<()> {
+        let fill_bson = to_bson(fill)?;
+        self.fills.update_one(
+            doc! {
+                "open_orders": fill.open_orders.clone(),
+                "seq_num": to_bson(&fill.seq_num)?,
+            },
+            doc! {
+                "$setOnInsert": fill_bson
+            },
+            UpdateOptions::builder().upsert(true).build(),
+        )?;
+        Ok(())
+    }
+}
+
+/// Keeps the fills in memory, for running a trader without a database
+#[derive(Default)]
+pub struct MemoryFillStore {
+    pub fills: RefCell<Vec<Fill>>,
+}
+
+impl FillStore for MemoryFillStore {
+    fn record_fill(&self, fill: &Fill) -> TradeBotResult<()> {
+        let mut fills = self.fills.borrow_mut();
+        if !fills.iter().any(|stored| stored.open_orders == fill.open_orders && stored.seq_num == fill.seq_num) {
+            fills.push(fill.clone());
+        }
+        Ok(())
+    }
+}
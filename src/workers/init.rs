@@ -0,0 +1,288 @@
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use mongodb::bson::{doc, to_bson};
+use mongodb::options::UpdateModifications;
+use serum_dex::state::Market;
+use solana_client::client_error::ClientError;
+use crate::rpc::client::RpcConnection;
+use solana_program::instruction::Instruction;
+use solana_program::program_pack::Pack;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_instruction;
+use solana_sdk::account::ReadableAccount;
+use solana_sdk::signature::{Signature, Signer};
+
+use crate::{MongoClient, str_to_pubkey, TraderStatus};
+use crate::grid::generator::{GridGenerator, MarketLots};
+use crate::grid::units::MarketUnits;
+use crate::mongodb::models::{StopReason, Trader};
+use crate::workers::base::{BotConfig, BotThread};
+use crate::workers::error::{DecodedError, TradeBotErrors, TradeBotResult};
+use crate::workers::message::{ThreadLogLevel, ThreadMessage, ThreadMessageCompiler, ThreadMessageSource};
+
+const INIT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Size of a serum open orders account including the account padding
+const OPEN_ORDERS_ACCOUNT_SIZE: u64 = 3228;
+
+/// Provisions the token accounts and the open orders account of registered traders,
+/// then generates their grid and moves them to `Initialized`
+pub struct InitializerThread {
+    pub stdout: Sender<ThreadMessage>,
+    pub config: Arc<BotConfig>,
+}
+
+impl InitializerThread {
+    /// Token wallets set by hand on the trader are kept, otherwise the associated token accounts are used
+    fn base_wallet(&self, trader: &Trader) -> Pubkey {
+        if trader.base_trader_wallet.is_empty() {
+            self.associated_token_address(&self.config.fee_payer.pubkey(), &str_to_pubkey(&trader.base_token_info.address))
+        } else {
+            str_to_pubkey(&trader.base_trader_wallet)
+        }
+    }
+
+    fn quote_wallet(&self, trader: &Trader) -> Pubkey {
+        if trader.quote_trader_wallet.is_empty() {
+            self.associated_token_address(&self.config.fee_payer.pubkey(), &str_to_pubkey(&trader.quote_token_info.address))
+        } else {
+            str_to_pubkey(&trader.quote_trader_wallet)
+        }
+    }
+
+    /// Open orders accounts are derived from the fee payer and the market so
+    /// initializing again never creates a second one
+    fn open_orders_seed(&self, trader: &Trader) -> String {
+        trader.market_address.chars().take(32).collect()
+    }
+
+    fn open_orders(&self, trader: &Trader) -> Pubkey {
+        match trader.serum_open_orders.get(0) {
+            Some(open_orders) => str_to_pubkey(open_orders),
+            None => Pubkey::create_with_seed(
+                &self.config.fee_payer.pubkey(),
+                &self.open_orders_seed(trader),
+                &self.config.serum_program,
+            ).unwrap()
+        }
+    }
+
+    /// Stops a trader that can't be initialized as configured, initializing it again once
+    /// the config is fixed takes setting its status back to `Registered`
+    fn reject_config(&self, mongo_client: &MongoClient, trader: &Trader, reason: String) -> TradeBotResult<()> {
+        self.log_str(&format!("Invalid trader config: {}", reason), ThreadLogLevel::Error);
+        mongo_client.traders.find_one_and_update(
+            doc! {
+            "market_address": trader.market_address.clone(),
+            "owner": trader.owner.clone(),
+        }, UpdateModifications::Document(doc! {
+                "$set": {
+                    "status": to_bson(&TraderStatus::Stopped)?,
+                    "stop_reason": to_bson(&Some(StopReason::InvalidConfig(reason)))?,
+                }
+            }),
+            None
+        )?;
+        Ok(())
+    }
+
+    fn account_exists(&self, connection: &dyn RpcConnection, account: &Pubkey) -> TradeBotResult<bool> {
+        Ok(self.get_account_if_exists(connection, account)?.is_some())
+    }
+
+    fn token_balance(&self, connection: &dyn RpcConnection, wallet: &Pubkey) -> TradeBotResult<u64> {
+        let wallet_account = connection.get_account(wallet)?;
+        let token_account = spl_token::state::Account::unpack(wallet_account.data())
+            .map_err(|e| TradeBotErrors::Decoding(format!("token account {}: {:?}", wallet, e)))?;
+        Ok(token_account.amount)
+    }
+
+    /// Records where the trader starts from, generates its grid and flips it to `Initialized`
+    fn finalize(&self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient, mut trader: Trader) -> TradeBotResult<()> {
+        let (best_bid, best_ask) = match self.load_best_prices(connection, serum_market) {
+            Some(prices) => prices,
+            None => {
+                self.log_str("Order book is empty, can't record starting prices", ThreadLogLevel::Warn);
+                return Ok(())
+            }
+        };
+        let base_wallet = self.base_wallet(&trader);
+        let quote_wallet = self.quote_wallet(&trader);
+        let base_balance = self.token_balance(connection, &base_wallet)?;
+        let quote_balance = self.token_balance(connection, &quote_wallet)?;
+        let mid_price = (best_bid + best_ask) / 2;
+
+        trader.base_trader_wallet = base_wallet.to_string();
+        trader.quote_trader_wallet = quote_wallet.to_string();
+        trader.serum_open_orders = vec![self.open_orders(&trader).to_string()];
+        trader.starting_price_buy = best_bid;
+        trader.starting_price_sell = best_ask;
+        trader.starting_base_balance = base_balance;
+        trader.starting_quote_balance = quote_balance;
+        trader.base_balance = base_balance;
+        trader.quote_balance = quote_balance;
+        trader.starting_value = base_balance / serum_market.coin_lot_size * mid_price * serum_market.pc_lot_size + quote_balance;
+        trader.value = trader.starting_value;
+
+        let lots = MarketLots {
+            coin_lot_size: serum_market.coin_lot_size,
+            pc_lot_size: serum_market.pc_lot_size,
+        };
+        if let Some(ui_grid) = trader.ui_grid.clone() {
+            let details = self.config.market.as_ref().and_then(|market| market.details.as_ref());
+            let units = match MarketUnits::for_trader(&trader, lots, details)
+                .and_then(|units| units.apply(&ui_grid, &mut trader).map(|_| units)) {
+                Ok(units) => units,
+                Err(e) => return self.reject_config(mongo_client, &trader, format!("invalid grid config: {}", e))
+            };
+            self.log_str(&format!(
+                "Grid from {} to {} trading {} per grid",
+                units.price_to_ui(trader.lower_price_range),
+                units.price_to_ui(trader.upper_price_range),
+                units.size_to_ui(trader.amount_per_grid),
+            ), ThreadLogLevel::Info);
+        }
+        let generator = GridGenerator::from_trader(&trader, lots);
+        trader.grids = match generator.generate() {
+            Ok(grids) => grids,
+            Err(e) => return self.reject_config(mongo_client, &trader, format!("failed to generate grid: {}", e))
+        };
+        trader.status = TraderStatus::Initialized;
+
+        mongo_client.traders.find_one_and_update(
+            doc! {
+            "market_address": trader.market_address.clone(),
+            "owner": trader.owner.clone(),
+        }, UpdateModifications::Document(doc! {
+                "$set": {
+                    "base_trader_wallet": trader.base_trader_wallet.clone(),
+                    "quote_trader_wallet": trader.quote_trader_wallet.clone(),
+                    "serum_open_orders": to_bson(&trader.serum_open_orders)?,
+                    "starting_price_buy": to_bson(&trader.starting_price_buy)?,
+                    "starting_price_sell": to_bson(&trader.starting_price_sell)?,
+                    "starting_base_balance": to_bson(&trader.starting_base_balance)?,
+                    "starting_quote_balance": to_bson(&trader.starting_quote_balance)?,
+                    "base_balance": to_bson(&trader.base_balance)?,
+                    "quote_balance": to_bson(&trader.quote_balance)?,
+                    "starting_value": to_bson(&trader.starting_value)?,
+                    "value": to_bson(&trader.value)?,
+                    "amount_per_grid": to_bson(&trader.amount_per_grid)?,
+                    "upper_price_range": to_bson(&trader.upper_price_range)?,
+                    "lower_price_range": to_bson(&trader.lower_price_range)?,
+                    "stopping_price_high": to_bson(&trader.stopping_price_high)?,
+                    "stopping_price_low": to_bson(&trader.stopping_price_low)?,
+                    "grids": to_bson(&trader.grids)?,
+                    "status": to_bson(&trader.status)?,
+                }
+            }),
+            None
+        )?;
+        self.log_str(
+            &format!("Trader initialized with {} grids, starting value {}", trader.grids.len(), trader.starting_value),
+            ThreadLogLevel::Info,
+        );
+        Ok(())
+    }
+}
+
+impl ThreadMessageCompiler for InitializerThread {}
+
+impl BotThread for InitializerThread {
+    fn setup(&mut self, _connection: &dyn RpcConnection, _serum_market: &Market, _mongo_client: &MongoClient) -> TradeBotResult<()> {
+        self.config.pause(INIT_POLL_INTERVAL);
+        Ok(())
+    }
+
+    fn cleanup(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        let trader = self.get_updated_trader(mongo_client, &self.config.trader)?;
+        if trader.status != TraderStatus::Registered {
+            return Ok(())
+        }
+        if self.account_exists(connection, &self.base_wallet(&trader))?
+            && self.account_exists(connection, &self.quote_wallet(&trader))?
+            && self.account_exists(connection, &self.open_orders(&trader))? {
+            self.finalize(connection, serum_market, mongo_client, trader)?;
+        }
+        Ok(())
+    }
+
+    fn get_config(&self) -> Arc<BotConfig> {
+        self.config.clone()
+    }
+
+    fn get_stdout(&self) -> Sender<ThreadMessage> {
+        self.stdout.clone()
+    }
+
+    fn get_source(&self) -> ThreadMessageSource {
+        return ThreadMessageSource::Initializer;
+    }
+
+    fn get_name(&self) -> String {
+        "Initializer".to_string()
+    }
+
+    fn compile_ixs(&mut self, connection: &dyn RpcConnection, _serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        let trader = self.get_updated_trader(mongo_client, &self.config.trader)?;
+        if trader.status != TraderStatus::Registered {
+            return Ok(vec![])
+        }
+        let mut ixs = vec![];
+        let payer = self.config.fee_payer.pubkey();
+
+        // only the associated token accounts are created, a wallet set by hand has to exist
+        let wallets = [
+            ("base", &trader.base_trader_wallet, self.base_wallet(&trader), &trader.base_token_info.address),
+            ("quote", &trader.quote_trader_wallet, self.quote_wallet(&trader), &trader.quote_token_info.address),
+        ];
+        for (name, configured, wallet, mint) in wallets.iter() {
+            if self.account_exists(connection, wallet)? {
+                continue
+            }
+            if !configured.is_empty() {
+                self.reject_config(mongo_client, &trader, format!("{} wallet {} does not exist", name, wallet))?;
+                return Ok(vec![])
+            }
+            println!("[?] Creating {} token account", name);
+            ixs.push(self.make_create_associated_token_account_ix(&payer, &str_to_pubkey(mint)));
+        }
+
+        // an open orders account set by hand is never recreated under a different address
+        let open_orders = self.open_orders(&trader);
+        if trader.serum_open_orders.is_empty() && !self.account_exists(connection, &open_orders)? {
+            println!("[?] Creating open orders account {}", open_orders);
+            let lamports = connection
+                .get_minimum_balance_for_rent_exemption(OPEN_ORDERS_ACCOUNT_SIZE as usize)?;
+            ixs.push(system_instruction::create_account_with_seed(
+                &payer,
+                &open_orders,
+                &payer,
+                &self.open_orders_seed(&trader),
+                lamports,
+                OPEN_ORDERS_ACCOUNT_SIZE,
+                &self.config.serum_program,
+            ));
+            ixs.push(serum_dex::instruction::init_open_orders(
+                &self.config.serum_program,
+                &open_orders,
+                &payer,
+                &str_to_pubkey(&trader.market_address),
+                None,
+            )?);
+        }
+        Ok(ixs)
+    }
+
+    fn log_rpc_client_error_(&self, err: ClientError) {
+        self.log_rpc_client_error(err);
+    }
+
+    fn log_transaction_error_(&self, err: ClientError, ixs: &[Instruction]) -> DecodedError {
+        self.log_transaction_error(err, ixs)
+    }
+
+    fn log_transaction_logs_(&self, connection: &dyn RpcConnection, sig: &Signature) {
+        self.log_transaction_logs(connection, sig)
+    }
+}
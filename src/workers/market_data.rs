@@ -0,0 +1,308 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use serum_dex::critbit::{Slab, SlabView};
+use serum_dex::matching::Side;
+use serum_dex::state::Market;
+use solana_program::account_info::AccountInfo;
+use solana_program::pubkey::Pubkey;
+use solana_sdk::account::{Account, ReadableAccount};
+
+use crate::rpc::client::RpcConnection;
+use crate::rpc::subscription::AccountWatcher;
+use crate::serum::markets::MarketKeys;
+use crate::serum::state::Order;
+use crate::workers::error::{TradeBotErrors, TradeBotResult};
+
+/// Re-read of a book none of whose accounts changed for this long, in case a
+/// notification got lost
+const MARKET_DATA_HEARTBEAT: Duration = Duration::from_secs(30);
+/// Wait before subscribing again once the websocket failed, the book is polled meanwhile
+const RESUBSCRIBE_BACKOFF: Duration = Duration::from_secs(30);
+/// How much longer than expected an update can take before a subscriber stops waiting
+const UPDATE_GRACE: Duration = Duration::from_secs(10);
+
+/// What the subscribers of a market receive every time its book is read
+pub type MarketUpdate = TradeBotResult<Arc<MarketData>>;
+pub type MarketFeed = Receiver<MarketUpdate>;
+
+/// Book and event queue of a market, read together in one request
+pub struct MarketData {
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+    /// Left encoded, every trader polls it for the events of its own open orders account
+    pub event_queue: Account,
+    pub fetched: Instant,
+}
+
+impl MarketData {
+    pub fn fetch(connection: &dyn RpcConnection, serum_market: &Market, keys: &MarketKeys) -> TradeBotResult<MarketData> {
+        let (bids_pubkey, asks_pubkey, event_queue_pubkey) = (keys.bids, keys.asks, keys.event_queue);
+        let mut accounts = connection.get_multiple_accounts(&[bids_pubkey, asks_pubkey, event_queue_pubkey])?.into_iter();
+        let mut next_account = |name: &str, pubkey: &Pubkey| {
+            accounts.next()
+                .flatten()
+                .ok_or_else(|| TradeBotErrors::Decoding(format!("{} {}, the account does not exist", name, pubkey)))
+        };
+        let bids_account = next_account("bids", &bids_pubkey)?;
+        let asks_account = next_account("asks", &asks_pubkey)?;
+        let event_queue = next_account("event queue", &event_queue_pubkey)?;
+
+        Ok(MarketData {
+            bids: load_book(serum_market, Side::Bid, &bids_pubkey, bids_account)?,
+            asks: load_book(serum_market, Side::Ask, &asks_pubkey, asks_account)?,
+            event_queue,
+            fetched: Instant::now(),
+        })
+    }
+
+    /// Orders of an open orders account, bids then asks
+    pub fn orders_of(&self, owner: &[u64; 4]) -> Vec<Order> {
+        self.bids.iter().chain(self.asks.iter())
+            .filter(|order| &order.owner == owner)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Every order resting on one side of the book
+pub fn parse_slab(side: Side, slab: &Slab) -> Vec<Order> {
+    let mut orders: Vec<Order> = vec![];
+    for i in 0..slab.capacity() {
+        if let Some(leaf) = slab.get(i as u32).and_then(|node| node.as_leaf()) {
+            orders.push(Order {
+                side,
+                price: u64::try_from(leaf.price()).unwrap(),
+                quantity: leaf.quantity(),
+                client_id: leaf.client_order_id(),
+                owner: leaf.owner(),
+                order_id: leaf.order_id(),
+            });
+        }
+    }
+    orders
+}
+
+fn load_book(serum_market: &Market, side: Side, pubkey: &Pubkey, account: Account) -> TradeBotResult<Vec<Order>> {
+    let mut account_clone = account.clone();
+    let account_info = AccountInfo {
+        key: pubkey,
+        is_signer: false,
+        is_writable: false,
+        lamports: Rc::new(RefCell::new(&mut account_clone.lamports)),
+        data: Rc::new(RefCell::new(&mut account_clone.data)),
+        owner: &account.owner().clone(),
+        executable: false,
+        rent_epoch: account.rent_epoch,
+    };
+    let slab = match side {
+        Side::Bid => serum_market.load_bids_mut(&account_info)?,
+        Side::Ask => serum_market.load_asks_mut(&account_info)?,
+    };
+    Ok(parse_slab(side, &slab))
+}
+
+struct Subscriber {
+    sender: Sender<MarketUpdate>,
+    /// Changes of this open orders account wake the subscriber even when the book is unchanged
+    open_orders: Option<Pubkey>,
+}
+
+/// Reads the book of every market traded on for all of its traders, instead of every
+/// trader reading it on its own. With a websocket the book is only read again when
+/// one of its accounts changed, otherwise once per tick
+pub struct MarketDataHub {
+    connection: Arc<dyn RpcConnection>,
+    serum_program: Pubkey,
+    interval: Duration,
+    ws_url: Option<String>,
+    subscribers: Mutex<HashMap<Pubkey, Vec<Subscriber>>>,
+}
+
+impl MarketDataHub {
+    pub fn new(connection: Arc<dyn RpcConnection>, serum_program: Pubkey, interval: Duration, ws_url: Option<String>) -> Arc<Self> {
+        Arc::new(MarketDataHub {
+            connection,
+            serum_program,
+            interval,
+            ws_url,
+            subscribers: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Updates of `market` from its next read on. The worker of the market is started
+    /// with its first subscriber and exits once every feed is dropped
+    pub fn subscribe(self: &Arc<Self>, market: &Pubkey, open_orders: Option<Pubkey>) -> MarketFeed {
+        let (sender, rx) = channel();
+        let subscriber = Subscriber { sender, open_orders };
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(market_subscribers) = subscribers.get_mut(market) {
+            market_subscribers.push(subscriber);
+            return rx
+        }
+        subscribers.insert(*market, vec![subscriber]);
+        let (hub, market) = (self.clone(), *market);
+        let spawned = std::thread::Builder::new()
+            .name(format!("MarketData {}", market))
+            .spawn(move || hub.run(market));
+        if let Err(e) = spawned {
+            // the feed disconnects right away and the trader reads the market itself
+            eprintln!("[-] Failed to start the market data worker of {}: {}", market, e);
+            subscribers.remove(&market);
+        }
+        rx
+    }
+
+    /// Longest a subscriber goes without an update while the worker of its market runs
+    pub fn update_timeout(&self) -> Duration {
+        let quiet = if self.ws_url.is_some() { MARKET_DATA_HEARTBEAT } else { self.interval };
+        quiet + self.interval + UPDATE_GRACE
+    }
+
+    fn run(&self, market: Pubkey) {
+        println!("[?] Market data worker of {} started", market);
+        let mut market_account: Option<Account> = None;
+        let mut watcher: Option<(AccountWatcher, Vec<Pubkey>)> = None;
+        let mut subscribe_at = Instant::now();
+        let mut latest: Option<Arc<MarketData>> = None;
+        loop {
+            let started = Instant::now();
+            if watcher.is_none() && started >= subscribe_at {
+                if let Some(ws_url) = &self.ws_url {
+                    match self.watch_book(ws_url, &market, &mut market_account) {
+                        Ok(book_watcher) => {
+                            println!("[?] Following the book of {} over {}", market, ws_url);
+                            watcher = Some(book_watcher);
+                        }
+                        Err(e) => {
+                            eprintln!("[-] Failed to subscribe to the book of {}, polling it: {}", market, e);
+                            subscribe_at = started + RESUBSCRIBE_BACKOFF;
+                        }
+                    }
+                }
+            }
+
+            // open orders accounts whose subscribers are woken with the book they already
+            // have, the book is read again when unset
+            let mut open_orders_changed: Option<HashSet<Pubkey>> = None;
+            if let Some((book_watcher, book_accounts)) = watcher.as_mut() {
+                self.watch_open_orders(book_watcher, book_accounts, &market);
+                match book_watcher.changes(MARKET_DATA_HEARTBEAT) {
+                    Ok(changed) => {
+                        if !changed.is_empty() && !changed.iter().any(|pubkey| book_accounts.contains(pubkey)) {
+                            open_orders_changed = Some(changed);
+                        }
+                    }
+                    Err(closed) => {
+                        eprintln!("[-] Subscription of {} closed, polling the book of {} until it is back", closed, market);
+                        watcher = None;
+                        subscribe_at = Instant::now() + RESUBSCRIBE_BACKOFF;
+                    }
+                }
+            }
+
+            let keep_running = match (open_orders_changed, latest.clone()) {
+                (Some(open_orders), Some(latest)) => self.publish(&market, Ok(latest), Some(&open_orders)),
+                _ => {
+                    let update = self.fetch(&market, &mut market_account).map(Arc::new);
+                    match &update {
+                        Ok(data) => latest = Some(data.clone()),
+                        Err(e) => eprintln!("[-] Failed to read the book of {}: {}", market, e),
+                    }
+                    self.publish(&market, update, None)
+                }
+            };
+            if !keep_running {
+                println!("[?] No traders left on {}, market data worker exits", market);
+                return
+            }
+            // at most one read per tick, what changes meanwhile is picked up on the next one
+            std::thread::sleep(self.interval.checked_sub(started.elapsed()).unwrap_or_default());
+        }
+    }
+
+    /// Loads the market once, its queues and slabs never move
+    fn with_market<T, F>(&self, market: &Pubkey, market_account: &mut Option<Account>, f: F) -> TradeBotResult<T>
+        where F: FnOnce(&Market) -> TradeBotResult<T> {
+        if market_account.is_none() {
+            *market_account = Some(self.connection.get_account(market)?);
+        }
+        let account = market_account.as_ref().unwrap();
+        let mut account_clone = account.clone();
+        let market_account_info = AccountInfo {
+            key: market,
+            is_signer: false,
+            is_writable: false,
+            lamports: Rc::new(RefCell::new(&mut account_clone.lamports)),
+            data: Rc::new(RefCell::new(&mut account_clone.data)),
+            owner: &account.owner().clone(),
+            executable: false,
+            rent_epoch: account.rent_epoch,
+        };
+        let serum_market = Market::load(&market_account_info, &self.serum_program, true)?;
+        f(&serum_market)
+    }
+
+    fn fetch(&self, market: &Pubkey, market_account: &mut Option<Account>) -> TradeBotResult<MarketData> {
+        self.with_market(market, market_account, |serum_market| MarketData::fetch(self.connection.as_ref(), serum_market, &MarketKeys::from_market(serum_market)))
+    }
+
+    /// Subscribes to the bids, asks and event queue of the market
+    fn watch_book(&self, ws_url: &str, market: &Pubkey, market_account: &mut Option<Account>) -> Result<(AccountWatcher, Vec<Pubkey>), String> {
+        let book_accounts = self.with_market(market, market_account, |serum_market| {
+            let keys = MarketKeys::from_market(serum_market);
+            Ok(vec![keys.bids, keys.asks, keys.event_queue])
+        }).map_err(|e| e.to_string())?;
+        let mut watcher = AccountWatcher::new(ws_url);
+        for pubkey in &book_accounts {
+            watcher.watch(pubkey)?;
+        }
+        Ok((watcher, book_accounts))
+    }
+
+    /// Follows the open orders accounts of the current subscribers and no others
+    fn watch_open_orders(&self, watcher: &mut AccountWatcher, book_accounts: &[Pubkey], market: &Pubkey) {
+        let wanted: HashSet<Pubkey> = self.subscribers.lock().unwrap()
+            .get(market)
+            .map(|market_subscribers| market_subscribers.iter().filter_map(|subscriber| subscriber.open_orders).collect())
+            .unwrap_or_default();
+        for pubkey in &wanted {
+            if let Err(e) = watcher.watch(pubkey) {
+                eprintln!("[-] {}, its trader only wakes up with the book", e);
+            }
+        }
+        for pubkey in watcher.watched() {
+            if !wanted.contains(&pubkey) && !book_accounts.contains(&pubkey) {
+                watcher.unwatch(&pubkey);
+            }
+        }
+    }
+
+    /// Sends the update to every live feed of the market, or only to the ones following
+    /// one of `open_orders`. False once no feed is left
+    fn publish(&self, market: &Pubkey, update: MarketUpdate, open_orders: Option<&HashSet<Pubkey>>) -> bool {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let market_subscribers = match subscribers.get_mut(market) {
+            Some(market_subscribers) => market_subscribers,
+            None => return false
+        };
+        market_subscribers.retain(|subscriber| {
+            let woken = match (open_orders, &subscriber.open_orders) {
+                (None, _) => true,
+                (Some(open_orders), Some(own)) => open_orders.contains(own),
+                (Some(_), None) => false,
+            };
+            !woken || subscriber.sender.send(update.clone()).is_ok()
+        });
+        if market_subscribers.is_empty() {
+            subscribers.remove(market);
+            return false
+        }
+        true
+    }
+}
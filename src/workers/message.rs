@@ -1,23 +1,25 @@
 use std::time::SystemTime;
 
 use solana_client::client_error::{ClientError, ClientErrorKind};
-use solana_client::rpc_client::RpcClient;
+use crate::rpc::client::RpcConnection;
 use solana_client::rpc_config::RpcTransactionConfig;
 use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
 use solana_client::rpc_response::RpcSimulateTransactionResult;
-use solana_program::instruction::InstructionError;
+use solana_program::instruction::Instruction;
 use solana_sdk::signature::Signature;
-use solana_sdk::transaction::TransactionError;
 use solana_transaction_status::EncodedTransaction;
 
 use crate::workers::base::BotThread;
+use crate::workers::error::{DecodedError, ErrorClass};
 
 pub enum ThreadMessageSource {
     Trader,
     Settler,
     EventConsumer,
     Cleanup,
-    Sync
+    Sync,
+    Initializer,
+    Supervisor,
 }
 
 pub enum ThreadLogLevel {
@@ -27,9 +29,9 @@ pub enum ThreadLogLevel {
 }
 
 pub struct ThreadLog {
-    pub(crate) log: String,
-    pub(crate) time: SystemTime,
-    pub(crate) level: ThreadLogLevel,
+    pub log: String,
+    pub time: SystemTime,
+    pub level: ThreadLogLevel,
 }
 
 pub enum ThreadMessageKind {
@@ -37,8 +39,8 @@ pub enum ThreadMessageKind {
 }
 
 pub struct ThreadMessage {
-    pub(crate) source: ThreadMessageSource,
-    pub(crate) kind: ThreadMessageKind,
+    pub source: ThreadMessageSource,
+    pub kind: ThreadMessageKind,
 }
 
 impl ThreadMessage {
@@ -74,10 +76,18 @@ pub trait ThreadMessageCompiler: BotThread {
     }
 
     fn log_rpc_client_error(&self, e: ClientError) {
-        let (logs, level) = self.rpc_client_error_message(e);
+        self.log_transaction_error(e, &[]);
+    }
+
+    /// Logs an error of a transaction made of `ixs` with the failing instruction
+    /// mapped back to its grid, and returns it decoded so the worker can react
+    fn log_transaction_error(&self, e: ClientError, ixs: &[Instruction]) -> DecodedError {
+        let decoded = DecodedError::from_client_error(&e, &self.get_config().serum_program, ixs);
+        let (logs, level) = self.rpc_client_error_message(e, &decoded);
         println!("{}", logs);
         let mes = self.compile_log_message(logs, level);
         self.send_message(mes);
+        decoded
     }
 
     fn log_str(&self, log: &str, level: ThreadLogLevel) {
@@ -86,12 +96,15 @@ pub trait ThreadMessageCompiler: BotThread {
     }
 
 
-    fn rpc_client_error_message(&self, e: ClientError) -> (String, ThreadLogLevel) {
+    fn rpc_client_error_message(&self, e: ClientError, decoded: &DecodedError) -> (String, ThreadLogLevel) {
         let mut logs = "".to_string();
-        let mut level = ThreadLogLevel::Error;
+        let level = match decoded.class {
+            ErrorClass::Retryable => ThreadLogLevel::Warn,
+            ErrorClass::Fatal => ThreadLogLevel::Error,
+        };
         match e.kind {
-            ClientErrorKind::TransactionError(err) => {
-                logs.push_str(&format!("{:?}\n", err));
+            ClientErrorKind::TransactionError(_) => {
+                logs.push_str(&format!("{}\n", decoded));
             }
             ClientErrorKind::RpcError(err) => match err {
                 RpcError::RpcRequestError(err) => logs.push_str(&format!("\nRequest error{:?}\n", err)),
@@ -114,26 +127,8 @@ pub trait ThreadMessageCompiler: BotThread {
                                     logs: prog_logs,
                                     units_consumed,
                                 } => {
-                                    if let Some(error) = err {
-                                        match error {
-                                            TransactionError::InstructionError(_o, instruction_err) => {
-                                                if let InstructionError::Custom(ix_err) =
-                                                instruction_err
-                                                {
-                                                    logs.push_str(&format!(
-                                                        "\nUnknown Error: {:?}",
-                                                        ix_err
-                                                    ))
-                                                } else {
-                                                    logs.push_str(&format!(
-                                                        "\nInstruction Error: {:?}",
-                                                        instruction_err
-                                                    ))
-                                                }
-                                            }
-                                            _ => logs
-                                                .push_str(&format!("\nTransaction Error: {:?}", error)),
-                                        }
+                                    if err.is_some() {
+                                        logs.push_str(&format!("\n{}", decoded));
                                     }
                                     if let Some(program_logs) = prog_logs {
                                         logs.push_str(&format!("\nProgram Logs"));
@@ -168,7 +163,7 @@ pub trait ThreadMessageCompiler: BotThread {
         return (logs, level);
     }
 
-    fn log_transaction_logs(&self, connection: &RpcClient, signature: &Signature) {
+    fn log_transaction_logs(&self, connection: &dyn RpcConnection, signature: &Signature) {
         let (mut logs, level) = self.transaction_logs(connection, signature);
         logs.push_str(&format!("\nSignature: {}", signature.to_string()));
         println!("{} {}", logs, signature);
@@ -177,7 +172,7 @@ pub trait ThreadMessageCompiler: BotThread {
     }
 
 
-    fn transaction_logs(&self, connection: &RpcClient, signature: &Signature) -> (String, ThreadLogLevel) {
+    fn transaction_logs(&self, connection: &dyn RpcConnection, signature: &Signature) -> (String, ThreadLogLevel) {
         let mut logs = "".to_string();
         let mut level = ThreadLogLevel::Info;
         let tx_result = connection
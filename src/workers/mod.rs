@@ -2,5 +2,15 @@ pub mod base;
 pub mod message;
 pub mod trade;
 pub mod sync;
-mod error;
+pub mod error;
 pub mod cleanup;
+pub mod fills;
+pub mod settle;
+pub mod consume;
+pub mod stop;
+pub mod init;
+pub mod recorder;
+pub mod supervisor;
+pub mod registry;
+pub mod shutdown;
+pub mod market_data;
@@ -0,0 +1,101 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use mongodb::bson::doc;
+use serum_dex::matching::Side;
+
+use crate::mongodb::client::MongoClient;
+use crate::mongodb::models::{BookLevel, BookSnapshot, OwnOrder, Trader, BOOK_SNAPSHOT_VERSION};
+use crate::serum::state::Order;
+use crate::workers::error::TradeBotResult;
+
+const DEFAULT_RECORD_INTERVAL_SECS: u64 = 60;
+
+/// Periodically stores the top of the book together with our own resting orders,
+/// enabled per trader by `record_book_depth`
+pub struct BookRecorder {
+    pub last_recorded: Option<Instant>,
+}
+
+impl BookRecorder {
+    pub fn new() -> Self {
+        BookRecorder {
+            last_recorded: None,
+        }
+    }
+
+    pub fn is_due(&self, trader: &Trader) -> bool {
+        if trader.record_book_depth.is_none() {
+            return false;
+        }
+        let interval = trader.record_book_interval.unwrap_or(DEFAULT_RECORD_INTERVAL_SECS);
+        self.last_recorded.map_or(true, |last| last.elapsed().as_secs() >= interval)
+    }
+
+    pub fn snapshot(&self, trader: &Trader, slot: Option<u64>, bids: &[Order], asks: &[Order], own_orders: &[Order]) -> BookSnapshot {
+        let depth = trader.record_book_depth.unwrap_or(0) as usize;
+        BookSnapshot {
+            version: BOOK_SNAPSHOT_VERSION,
+            market_address: trader.market_address.clone(),
+            owner: trader.owner.clone(),
+            slot,
+            time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            bids: aggregate_levels(bids, Side::Bid, depth),
+            asks: aggregate_levels(asks, Side::Ask, depth),
+            own_orders: own_orders.iter().map(|order| OwnOrder {
+                side: order.side,
+                price: order.price,
+                quantity: order.quantity,
+                client_order_id: order.client_id,
+                order_id: order.order_id.to_string(),
+            }).collect(),
+        }
+    }
+
+    /// Snapshots are only ever appended, a failed write is logged and skipped
+    pub fn record(&mut self, mongo_client: &MongoClient, snapshot: &BookSnapshot) {
+        self.last_recorded = Some(Instant::now());
+        if let Err(e) = mongo_client.snapshots.insert_one(snapshot, None) {
+            eprintln!("[-] Failed to record book snapshot: {:?}", e);
+        }
+    }
+}
+
+/// Recorded snapshots of a market, oldest first
+pub fn load_snapshots(mongo_client: &MongoClient, market_address: &str) -> TradeBotResult<Vec<BookSnapshot>> {
+    let cursor = mongo_client.snapshots.find(
+        doc! {
+            "market_address": market_address,
+        },
+        mongodb::options::FindOptions::builder().sort(doc! { "time": 1 }).build(),
+    )?;
+    Ok(cursor.filter_map(|snapshot| snapshot.ok()).collect())
+}
+
+/// Sums the orders of one side by price, best price first
+fn aggregate_levels(orders: &[Order], side: Side, depth: usize) -> Vec<BookLevel> {
+    let mut levels: Vec<BookLevel> = vec![];
+    let mut sorted = orders.to_vec();
+    match side {
+        Side::Bid => sorted.sort_by(|a, b| b.price.cmp(&a.price)),
+        Side::Ask => sorted.sort_by(|a, b| a.price.cmp(&b.price)),
+    }
+    for order in sorted {
+        match levels.last_mut() {
+            Some(level) if level.price == order.price => {
+                level.quantity += order.quantity;
+                level.orders += 1;
+            }
+            _ => {
+                if levels.len() == depth {
+                    break
+                }
+                levels.push(BookLevel {
+                    price: order.price,
+                    quantity: order.quantity,
+                    orders: 1,
+                });
+            }
+        }
+    }
+    levels
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use mongodb::bson::doc;
+
+use crate::config::Settings;
+use crate::mongodb::client::MongoClient;
+use crate::mongodb::models::{Trader, TraderStatus};
+use crate::serum::markets::MarketRegistry;
+use crate::workers::base::BotConfig;
+use crate::workers::cleanup::CleanupThread;
+use crate::workers::consume::EventConsumer;
+use crate::workers::error::TradeBotResult;
+use crate::workers::fills::FillTracker;
+use crate::workers::init::InitializerThread;
+use crate::workers::message::{ThreadLogLevel, ThreadMessage, ThreadMessageSource};
+use crate::workers::recorder::BookRecorder;
+use crate::workers::settle::SettlerThread;
+use crate::workers::supervisor::Supervisor;
+use crate::workers::sync::SyncThread;
+use crate::workers::trade::TraderThread;
+
+/// How often the traders collection is read again for new, changed and removed traders
+const REGISTRY_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Longest the registry waits on the workers between two checks of the collection
+const REGISTRY_TICK: Duration = Duration::from_secs(1);
+
+/// Builds the config shared by the workers of a trader, traders it fails for are skipped
+pub type ConfigBuilder = Box<dyn Fn(&Trader) -> Result<BotConfig, String> + Send>;
+
+/// Keeps a set of workers running for every trader of the traders collection, picking
+/// up traders that were added, changed or removed while the bot runs
+pub struct TraderRegistry {
+    pub stdout: Sender<ThreadMessage>,
+    settings: Arc<Settings>,
+    markets: Arc<MarketRegistry>,
+    supervisor: Supervisor,
+    build_config: ConfigBuilder,
+    running: HashMap<String, Arc<BotConfig>>,
+    /// Traders whose workers are winding down to be started again with a new config
+    reloading: HashMap<String, Trader>,
+}
+
+impl TraderRegistry {
+    pub fn new(stdout: Sender<ThreadMessage>, settings: Arc<Settings>, markets: Arc<MarketRegistry>, build_config: ConfigBuilder) -> Self {
+        TraderRegistry {
+            supervisor: Supervisor::new(stdout.clone()),
+            stdout,
+            settings,
+            markets,
+            build_config,
+            running: HashMap::new(),
+            reloading: HashMap::new(),
+        }
+    }
+
+    /// Follows the traders collection and supervises the workers of its traders until
+    /// `shutdown` is set, then returns once every worker stopped
+    pub fn run(mut self, shutdown: Arc<AtomicBool>) {
+        let mongo_client = MongoClient::connect(&self.settings.mongodb_url, &self.settings.database);
+        let mut last_reload: Option<Instant> = None;
+        loop {
+            if shutdown.load(Ordering::SeqCst) {
+                self.shut_down();
+                return
+            }
+            if last_reload.map_or(true, |last_reload| last_reload.elapsed() >= REGISTRY_POLL_INTERVAL) {
+                if let Err(e) = self.reload(&mongo_client) {
+                    self.report(format!("Failed to read the traders collection: {}", e), ThreadLogLevel::Warn);
+                }
+                last_reload = Some(Instant::now());
+            }
+            self.supervisor.poll(REGISTRY_TICK);
+            self.start_reloaded();
+        }
+    }
+
+    /// Reconciles the running worker sets with the traders collection
+    pub fn reload(&mut self, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        let mut traders: HashMap<String, Trader> = HashMap::new();
+        for trader_result in mongo_client.traders.find(None, None)? {
+            match trader_result {
+                Ok(mut trader) => {
+                    if let Err(e) = self.resolve_market(&mut trader, mongo_client) {
+                        self.report(format!("Skipping trader of {}: {}", trader.owner, e), ThreadLogLevel::Warn);
+                        continue
+                    }
+                    traders.insert(trader_key(&trader), trader);
+                }
+                Err(e) => eprintln!("[-] Skipping unreadable trader: {}", e),
+            }
+        }
+
+        let removed: Vec<String> = self.running.keys()
+            .chain(self.reloading.keys())
+            .filter(|key| !traders.contains_key(*key))
+            .cloned()
+            .collect();
+        for key in removed {
+            self.stop(&key);
+            self.reloading.remove(&key);
+            self.report(format!("Trader {} was removed, stopping its workers", key), ThreadLogLevel::Info);
+        }
+
+        for (key, trader) in traders {
+            if trader.status == TraderStatus::Closed {
+                // the workers of a closed trader exit on their own
+                if self.running.remove(&key).is_some() {
+                    self.report(format!("Trader {} closed", key), ThreadLogLevel::Info);
+                }
+                self.reloading.remove(&key);
+                continue
+            }
+            if let Some(reloading) = self.reloading.get_mut(&key) {
+                *reloading = trader;
+                continue
+            }
+            match self.running.get(&key).map(|config| config_changed(&config.trader, &trader)) {
+                None => {
+                    self.report(format!("Starting workers of trader {}", key), ThreadLogLevel::Info);
+                    self.start(trader);
+                }
+                Some(true) => {
+                    self.report(format!("Trader {} changed, restarting its workers with the new config", key), ThreadLogLevel::Info);
+                    self.stop(&key);
+                    self.reloading.insert(key, trader);
+                }
+                Some(false) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills in the market address of traders that only name their market, and
+    /// stores it so the rest of the bot finds the trader by address
+    fn resolve_market(&self, trader: &mut Trader, mongo_client: &MongoClient) -> Result<(), String> {
+        let market_name = match &trader.market_name {
+            Some(market_name) => market_name.clone(),
+            None => return Ok(())
+        };
+        let market = match self.markets.find(&market_name) {
+            Some(market) => market,
+            None => return Err(format!("market {} is not in {}", market_name, self.settings.markets_file.display()))
+        };
+        if trader.market_address == market.address {
+            return Ok(())
+        }
+        if !trader.market_address.is_empty() {
+            return Err(format!("market {} is {}, not {}", market_name, market.address, trader.market_address))
+        }
+        mongo_client.traders.find_one_and_update(
+            doc! {
+                "owner": &trader.owner,
+                "market_address": "",
+                "market_name": &market_name,
+            },
+            doc! {
+                "$set": { "market_address": &market.address }
+            },
+            None,
+        ).map_err(|e| format!("failed to store its market address: {}", e))?;
+        trader.market_address = market.address.clone();
+        Ok(())
+    }
+
+    /// Asks every worker to stop and waits for them, workers keep restarting if they
+    /// panic on the way so they still get to finish what they were sending
+    fn shut_down(&mut self) {
+        self.report(format!("Shutting down the workers of {} traders", self.running.len()), ThreadLogLevel::Info);
+        let keys: Vec<String> = self.running.keys().cloned().collect();
+        for key in &keys {
+            self.stop(key);
+        }
+        self.reloading.clear();
+        while self.supervisor.active_workers() > 0 {
+            self.supervisor.poll(REGISTRY_TICK);
+        }
+        self.report("All workers stopped".to_string(), ThreadLogLevel::Info);
+    }
+
+    /// Starts the traders whose previous workers all exited
+    fn start_reloaded(&mut self) {
+        let ready: Vec<String> = self.reloading.keys()
+            .filter(|key| !self.supervisor.group_active(key))
+            .cloned()
+            .collect();
+        for key in ready {
+            if let Some(trader) = self.reloading.remove(&key) {
+                self.start(trader);
+            }
+        }
+    }
+
+    fn stop(&mut self, key: &str) {
+        if let Some(config) = self.running.remove(key) {
+            config.request_stop();
+        }
+        self.supervisor.cancel_restarts(key);
+    }
+
+    fn start(&mut self, trader: Trader) {
+        let key = trader_key(&trader);
+        let config = match (self.build_config)(&trader) {
+            Ok(config) => Arc::new(config),
+            Err(e) => {
+                // tried again on the next reload, in case the trader gets fixed
+                self.report(format!("Skipping trader {}: {}", key, e), ThreadLogLevel::Warn);
+                return
+            }
+        };
+        self.running.insert(key.clone(), config.clone());
+
+        let (worker_config, stdout) = (config.clone(), self.stdout.clone());
+        self.supervisor.spawn(&key, worker_name("Initializer", &key), move || InitializerThread {
+            stdout: stdout.clone(),
+            config: worker_config.clone(),
+        });
+
+        let (worker_config, stdout) = (config.clone(), self.stdout.clone());
+        self.supervisor.spawn(&key, worker_name("Trader", &key), move || TraderThread {
+            stdout: stdout.clone(),
+            config: worker_config.clone(),
+            data: None,
+            trader: trader.clone(),
+            fills: FillTracker::new(),
+            recorder: BookRecorder::new(),
+            cancelled_on_shutdown: vec![],
+            market_feed: None,
+        });
+
+        let (worker_config, stdout) = (config.clone(), self.stdout.clone());
+        self.supervisor.spawn(&key, worker_name("Sync", &key), move || SyncThread {
+            stdout: stdout.clone(),
+            config: worker_config.clone(),
+        });
+
+        let (worker_config, stdout) = (config.clone(), self.stdout.clone());
+        self.supervisor.spawn(&key, worker_name("Cleanup", &key), move || CleanupThread {
+            stdout: stdout.clone(),
+            config: worker_config.clone(),
+            stage: None,
+        });
+
+        let (worker_config, stdout) = (config.clone(), self.stdout.clone());
+        self.supervisor.spawn(&key, worker_name("Settler", &key), move || SettlerThread {
+            stdout: stdout.clone(),
+            config: worker_config.clone(),
+        });
+
+        let (worker_config, stdout) = (config, self.stdout.clone());
+        self.supervisor.spawn(&key, worker_name("EventConsumer", &key), move || EventConsumer {
+            stdout: stdout.clone(),
+            config: worker_config.clone(),
+        });
+    }
+
+    fn report(&self, log: String, level: ThreadLogLevel) {
+        println!("[?] {}", log);
+        let _ = self.stdout.send(ThreadMessage::compile_log_message(ThreadMessageSource::Supervisor, log, level));
+    }
+}
+
+/// Traders are told apart by their market and owner, like everywhere in the traders collection
+fn trader_key(trader: &Trader) -> String {
+    format!("{}/{}", trader.market_address, trader.owner)
+}
+
+fn worker_name(kind: &str, key: &str) -> String {
+    format!("{} {}", kind, key)
+}
+
+/// Whether the workers of the trader have to be rebuilt, everything else is read
+/// from the collection on every iteration
+fn config_changed(running: &Trader, trader: &Trader) -> bool {
+    running.market_address != trader.market_address
+        || running.trader_keypair != trader.trader_keypair
+        || running.base_trader_wallet != trader.base_trader_wallet
+        || running.quote_trader_wallet != trader.quote_trader_wallet
+        || running.serum_open_orders != trader.serum_open_orders
+}
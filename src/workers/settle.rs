@@ -0,0 +1,121 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use serum_dex::state::Market;
+use solana_client::client_error::ClientError;
+use crate::rpc::client::RpcConnection;
+use solana_program::account_info::AccountInfo;
+use solana_program::instruction::Instruction;
+use solana_sdk::account::ReadableAccount;
+use solana_sdk::signature::Signature;
+
+use crate::{MongoClient, str_to_pubkey};
+use crate::mongodb::models::Trader;
+use crate::workers::base::{BotConfig, BotThread};
+use crate::workers::error::{DecodedError, TradeBotResult};
+use crate::workers::message::{ThreadLogLevel, ThreadMessage, ThreadMessageCompiler, ThreadMessageSource};
+
+const SETTLE_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct SettlerThread {
+    pub stdout: Sender<ThreadMessage>,
+    pub config: Arc<BotConfig>,
+}
+
+impl SettlerThread {
+    /// Free base below this is left in the open orders account, defaults to one grid
+    fn min_settle_base(&self, trader: &Trader) -> u64 {
+        trader.min_settle_base.unwrap_or(trader.amount_per_grid)
+    }
+
+    /// Free quote below this is left in the open orders account, defaults to one
+    /// grid bought at the bottom of the range
+    fn min_settle_quote(&self, trader: &Trader, serum_market: &Market) -> u64 {
+        trader.min_settle_quote.unwrap_or(
+            trader.amount_per_grid / serum_market.coin_lot_size * trader.lower_price_range * serum_market.pc_lot_size
+        )
+    }
+}
+
+impl ThreadMessageCompiler for SettlerThread {}
+
+impl BotThread for SettlerThread {
+    fn setup(&mut self, _connection: &dyn RpcConnection, _serum_market: &Market, _mongo_client: &MongoClient) -> TradeBotResult<()> {
+        self.config.pause(SETTLE_INTERVAL);
+        Ok(())
+    }
+
+    fn get_config(&self) -> Arc<BotConfig> {
+        self.config.clone()
+    }
+
+    fn get_stdout(&self) -> Sender<ThreadMessage> {
+        self.stdout.clone()
+    }
+
+    fn get_source(&self) -> ThreadMessageSource {
+        return ThreadMessageSource::Settler;
+    }
+
+    fn get_name(&self) -> String {
+        "Settler".to_string()
+    }
+
+    fn compile_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        let trader = self.get_updated_trader(mongo_client, &self.config.trader)?;
+        let open_orders_account_pubkey = match trader.serum_open_orders.get(0) {
+            Some(open_orders) => str_to_pubkey(open_orders),
+            None => return Ok(vec![])
+        };
+        let open_orders_account = connection.get_account(&open_orders_account_pubkey)?;
+        let mut open_orders_account_clone = open_orders_account.clone();
+
+        let open_orders_account_info = AccountInfo {
+            key: &open_orders_account_pubkey,
+            is_signer: false,
+            is_writable: false,
+            lamports: Rc::new(RefCell::new(&mut open_orders_account_clone.lamports)),
+            data: Rc::new(RefCell::new(&mut open_orders_account_clone.data)),
+            owner: &open_orders_account.owner().clone(),
+            executable: false,
+            rent_epoch: open_orders_account.rent_epoch,
+        };
+        let open_orders_result = serum_market
+            .load_orders_mut(
+                &open_orders_account_info,
+                None,
+                &self.config.serum_program,
+                None,
+                None,
+            );
+
+        if let Ok(open_orders) = open_orders_result {
+            if open_orders.native_coin_free < self.min_settle_base(&trader)
+                && open_orders.native_pc_free < self.min_settle_quote(&trader, serum_market) {
+                return Ok(vec![])
+            }
+            self.log_str(
+                &format!("Settling {} base and {} quote", open_orders.native_coin_free, open_orders.native_pc_free),
+                ThreadLogLevel::Info,
+            );
+            let settle_ix = self.make_settle_funds_ix(serum_market, &trader);
+            return Ok(vec![settle_ix])
+        }
+        Ok(vec![])
+    }
+
+    fn log_rpc_client_error_(&self, err: ClientError) {
+        self.log_rpc_client_error(err);
+    }
+
+    fn log_transaction_error_(&self, err: ClientError, ixs: &[Instruction]) -> DecodedError {
+        self.log_transaction_error(err, ixs)
+    }
+
+    fn log_transaction_logs_(&self, connection: &dyn RpcConnection, sig: &Signature) {
+        self.log_transaction_logs(connection, sig)
+    }
+}
@@ -0,0 +1,87 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use serum_dex::state::Market;
+use crate::rpc::client::RpcConnection;
+use solana_program::account_info::AccountInfo;
+use solana_program::instruction::Instruction;
+use solana_sdk::account::ReadableAccount;
+
+use crate::mongodb::models::{GridStatus, ShutdownPolicy, TraderStatus};
+use crate::str_to_pubkey;
+use crate::workers::base::BotThread;
+use crate::workers::error::TradeBotResult;
+use crate::workers::message::{ThreadLogLevel, ThreadMessageCompiler};
+use crate::workers::trade::TraderThread;
+
+impl TraderThread {
+    /// Cancels the resting grid orders of traders with the `CancelOrders` shutdown
+    /// policy, one batch per transaction until the open orders account is empty
+    pub(crate) fn compile_shutdown_cancel_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market) -> TradeBotResult<Vec<Instruction>> {
+        if self.trader.shutdown_policy != Some(ShutdownPolicy::CancelOrders) {
+            return Ok(vec![])
+        }
+        if self.trader.status != TraderStatus::Initialized && self.trader.status != TraderStatus::Stopping {
+            return Ok(vec![])
+        }
+        let open_orders_account_pubkey = match self.trader.serum_open_orders.get(0) {
+            Some(open_orders) => str_to_pubkey(open_orders),
+            None => return Ok(vec![])
+        };
+        let open_orders_account = connection.get_account(&open_orders_account_pubkey)?;
+        let mut open_orders_account_clone = open_orders_account.clone();
+
+        let open_orders_account_info = AccountInfo {
+            key: &open_orders_account_pubkey,
+            is_signer: false,
+            is_writable: false,
+            lamports: Rc::new(RefCell::new(&mut open_orders_account_clone.lamports)),
+            data: Rc::new(RefCell::new(&mut open_orders_account_clone.data)),
+            owner: &open_orders_account.owner().clone(),
+            executable: false,
+            rent_epoch: open_orders_account.rent_epoch,
+        };
+        let open_orders = serum_market.load_orders_mut(
+            &open_orders_account_info,
+            None,
+            &self.config.serum_program,
+            None,
+            None,
+        )?;
+
+        let cancel_ixs = self.make_cancel_order_ixs(serum_market, &self.trader, &open_orders);
+        if cancel_ixs.is_empty() {
+            return Ok(vec![])
+        }
+        // the settle instruction takes the last slot of the transaction
+        let batch = cancel_ixs.len().min(self.config.settings.max_ixs - 1);
+        // same order as the cancel instructions, one per non empty slot
+        self.cancelled_on_shutdown = (0..open_orders.orders.len())
+            .filter(|slot| open_orders.orders[*slot] != 0)
+            .take(batch)
+            .filter_map(|slot| self.find_grid_by_client_order_id(&self.trader, open_orders.client_order_ids[slot]))
+            .collect();
+        self.log_str(
+            &format!("Shutting down, cancelling {} of {} resting orders", batch, cancel_ixs.len()),
+            ThreadLogLevel::Info,
+        );
+        let mut ixs: Vec<Instruction> = cancel_ixs.into_iter().take(batch).collect();
+        ixs.push(self.make_settle_funds_ix(serum_market, &self.trader));
+        Ok(ixs)
+    }
+
+    /// Grids cancelled on shutdown are free again on the next start, what was
+    /// already filled still gets its counter order
+    pub(crate) fn release_cancelled_grids(&mut self) {
+        for grid_index in self.cancelled_on_shutdown.drain(..) {
+            if let Some(grid) = self.trader.grids.get_mut(grid_index) {
+                if grid.order.as_ref().map_or(false, |order| order.filled_base_quantity > 0) {
+                    grid.status = GridStatus::Violated;
+                } else {
+                    grid.status = GridStatus::Idle;
+                    grid.order = None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+use std::num::NonZeroU64;
+use std::rc::Rc;
+
+use mongodb::bson::{doc, to_bson};
+use mongodb::options::UpdateModifications;
+use serum_dex::instruction::SelfTradeBehavior;
+use serum_dex::matching::{OrderType, Side};
+use serum_dex::state::Market;
+use crate::rpc::client::RpcConnection;
+use solana_program::account_info::AccountInfo;
+use solana_program::instruction::Instruction;
+use solana_program::program_pack::Pack;
+use solana_program::rent::Rent;
+use solana_program::sysvar::SysvarId;
+use solana_sdk::account::ReadableAccount;
+use solana_sdk::signature::Signer;
+
+use crate::mongodb::client::MongoClient;
+use crate::mongodb::models::{StopExit, StopReason, Trader, TraderStatus};
+use crate::str_to_pubkey;
+use crate::workers::base::BotThread;
+use crate::workers::error::{TradeBotErrors, TradeBotResult};
+use crate::workers::message::{ThreadLogLevel, ThreadMessageCompiler};
+use crate::workers::trade::TraderThread;
+
+/// Exit orders are given up on after this many transactions that did not flatten the inventory
+const MAX_EXIT_ATTEMPTS: u32 = 5;
+/// Exit orders accept up to this much slippage from the top of the book, in basis points
+const EXIT_SLIPPAGE_BPS: u64 = 1000;
+
+/// Returns why the trader should stop at `mid_price`, if it should
+pub fn check_stop(trader: &Trader, mid_price: u64) -> Option<StopReason> {
+    if let Some(stopping_price_low) = trader.stopping_price_low {
+        if mid_price <= stopping_price_low {
+            return Some(StopReason::StopLoss);
+        }
+    }
+    if let Some(stopping_price_high) = trader.stopping_price_high {
+        if mid_price >= stopping_price_high {
+            return Some(StopReason::TakeProfit);
+        }
+    }
+    None
+}
+
+impl TraderThread {
+    /// Moves the trader to `Stopping`, the orders winding the grid down are built on
+    /// the following iterations until nothing is left to do
+    pub(crate) fn trigger_stop(&mut self, mongo_client: &MongoClient, reason: StopReason, mid_price: u64) -> TradeBotResult<()> {
+        let log = match &reason {
+            StopReason::StopLoss => format!("{} at {}, stopping grid", TradeBotErrors::StopLossLimit, mid_price),
+            StopReason::TakeProfit => format!("Price reached take profit at {}, stopping grid", mid_price),
+            StopReason::InvalidConfig(reason) => format!("Invalid config: {}, stopping grid", reason),
+        };
+        println!("[-] {}", log);
+        self.log_str(&log, ThreadLogLevel::Warn);
+        self.trader.status = TraderStatus::Stopping;
+        self.trader.stop_reason = Some(reason);
+        self.trader.stopped_price = Some(mid_price);
+        self.trader.stop_exit_attempts = Some(0);
+        self.update_stop_state(mongo_client)
+    }
+
+    /// Cancels the grid, exits the inventory if asked to and settles, one step per transaction
+    pub(crate) fn compile_stop_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        let open_orders_account_pubkey = match self.trader.serum_open_orders.get(0) {
+            Some(open_orders) => str_to_pubkey(open_orders),
+            None => return Ok(vec![])
+        };
+        let open_orders_account = connection.get_account(&open_orders_account_pubkey)?;
+        let mut open_orders_account_clone = open_orders_account.clone();
+
+        let open_orders_account_info = AccountInfo {
+            key: &open_orders_account_pubkey,
+            is_signer: false,
+            is_writable: false,
+            lamports: Rc::new(RefCell::new(&mut open_orders_account_clone.lamports)),
+            data: Rc::new(RefCell::new(&mut open_orders_account_clone.data)),
+            owner: &open_orders_account.owner().clone(),
+            executable: false,
+            rent_epoch: open_orders_account.rent_epoch,
+        };
+        let open_orders = serum_market.load_orders_mut(
+            &open_orders_account_info,
+            None,
+            &self.config.serum_program,
+            None,
+            None,
+        )?;
+
+        let cancel_ixs = self.make_cancel_order_ixs(serum_market, &self.trader, &open_orders);
+        if !cancel_ixs.is_empty() {
+            println!("[?] Cancelling {} grid orders", cancel_ixs.len());
+            let mut ixs: Vec<Instruction> = cancel_ixs.into_iter().take(self.config.settings.max_ixs - 1).collect();
+            ixs.push(self.make_settle_funds_ix(serum_market, &self.trader));
+            return Ok(ixs)
+        }
+
+        let stop_exit = self.trader.stop_exit.clone().unwrap_or(StopExit::Hold);
+        if stop_exit != StopExit::Hold {
+            let attempts = self.trader.stop_exit_attempts.unwrap_or(0);
+            if attempts < MAX_EXIT_ATTEMPTS {
+                let base_wallet_account = connection.get_account(&str_to_pubkey(&self.trader.base_trader_wallet))?;
+                let base_wallet = spl_token::state::Account::unpack(base_wallet_account.data())?;
+                let quote_wallet_account = connection.get_account(&str_to_pubkey(&self.trader.quote_trader_wallet))?;
+                let quote_wallet = spl_token::state::Account::unpack(quote_wallet_account.data())?;
+                let base_balance = base_wallet.amount + open_orders.native_coin_free;
+                let quote_balance = quote_wallet.amount + open_orders.native_pc_free;
+
+                if let Some(exit_ix) = self.make_exit_order_ix(serum_market, &stop_exit, base_balance, quote_balance) {
+                    self.trader.stop_exit_attempts = Some(attempts + 1);
+                    self.update_stop_state(mongo_client)?;
+                    return Ok(vec![exit_ix, self.make_settle_funds_ix(serum_market, &self.trader)])
+                }
+            } else {
+                self.log_str(
+                    &format!("Could not exit inventory into {:?} after {} attempts", stop_exit, MAX_EXIT_ATTEMPTS),
+                    ThreadLogLevel::Error,
+                );
+            }
+        }
+
+        if open_orders.native_coin_free > 0 || open_orders.native_pc_free > 0 {
+            return Ok(vec![self.make_settle_funds_ix(serum_market, &self.trader)])
+        }
+
+        self.trader.status = TraderStatus::Stopped;
+        self.update_stop_state(mongo_client)?;
+        self.log_str(&format!("Trader stopped: {:?}", self.trader.stop_reason), ThreadLogLevel::Info);
+        Ok(vec![])
+    }
+
+    /// Immediate or cancel order swapping the inventory into the exit token, `None`
+    /// once there is less than a lot left to swap
+    fn make_exit_order_ix(&self, serum_market: &Market, stop_exit: &StopExit, base_balance: u64, quote_balance: u64) -> Option<Instruction> {
+        let price = self.data.as_ref()?.last_price.as_ref()?;
+        let (side, limit_price, max_coin_qty, max_native_pc_qty) = match stop_exit {
+            StopExit::Hold => return None,
+            StopExit::Quote => {
+                let limit_price = price.buy * (10000 - EXIT_SLIPPAGE_BPS) / 10000;
+                (Side::Ask, limit_price, base_balance / serum_market.coin_lot_size, 1)
+            }
+            StopExit::Base => {
+                let limit_price = price.sell * (10000 + EXIT_SLIPPAGE_BPS) / 10000;
+                let max_coin_qty = quote_balance / (limit_price.max(1) * serum_market.pc_lot_size);
+                (Side::Bid, limit_price, max_coin_qty, quote_balance)
+            }
+        };
+        if max_coin_qty == 0 {
+            return None
+        }
+        println!("[?] Exiting {} lots into {:?} at {}", max_coin_qty, stop_exit, limit_price);
+        let keys = self.market_keys(serum_market);
+        Some(serum_dex::instruction::new_order(
+            &keys.market,
+            &str_to_pubkey(self.trader.serum_open_orders.get(0).unwrap()),
+            &keys.request_queue,
+            &keys.event_queue,
+            &keys.bids,
+            &keys.asks,
+            &str_to_pubkey(if side == Side::Bid { &self.trader.quote_trader_wallet } else { &self.trader.base_trader_wallet }),
+            &self.config.fee_payer.pubkey(),
+            &keys.coin_vault,
+            &keys.pc_vault,
+            &spl_token::id(),
+            &Rent::id(),
+            None,
+            &self.config.serum_program,
+            side,
+            NonZeroU64::new(limit_price.max(1)).unwrap(),
+            NonZeroU64::new(max_coin_qty).unwrap(),
+            OrderType::ImmediateOrCancel,
+            0,
+            SelfTradeBehavior::CancelProvide,
+            0,
+            NonZeroU64::new(max_native_pc_qty).unwrap(),
+        ).unwrap())
+    }
+
+    fn update_stop_state(&self, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        mongo_client.traders.find_one_and_update(
+            doc! {
+                "market_address": self.trader.market_address.clone(),
+                "owner": self.trader.owner.clone(),
+            },
+            UpdateModifications::Document(doc! {
+                "$set": {
+                    "status": to_bson(&self.trader.status)?,
+                    "stop_reason": to_bson(&self.trader.stop_reason)?,
+                    "stopped_price": to_bson(&self.trader.stopped_price)?,
+                    "stop_exit_attempts": to_bson(&self.trader.stop_exit_attempts)?,
+                }
+            }),
+            None,
+        )?;
+        Ok(())
+    }
+}
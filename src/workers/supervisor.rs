@@ -0,0 +1,232 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::workers::base::BotThread;
+use crate::workers::message::{ThreadLogLevel, ThreadMessage, ThreadMessageSource};
+
+/// Wait before the first restart of a worker, doubled on every restart that follows
+const FIRST_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+/// A worker that stayed up this long starts over from the first backoff
+const STABLE_RUN: Duration = Duration::from_secs(600);
+/// A worker restarted more than `MAX_RESTARTS` times within `RESTART_WINDOW` is given up on
+const MAX_RESTARTS: usize = 5;
+const RESTART_WINDOW: Duration = Duration::from_secs(3600);
+/// How often pending restarts are checked when no worker exits
+const SUPERVISOR_TICK: Duration = Duration::from_secs(1);
+
+/// Builds a fresh worker every time the previous one has to be replaced
+pub type WorkerFactory = Box<dyn Fn() -> Box<dyn BotThread + Send> + Send>;
+
+struct WorkerExit {
+    worker: usize,
+    /// Panic payload of the worker, `None` when its loop returned
+    panic: Option<String>,
+}
+
+struct SupervisedWorker {
+    group: String,
+    name: String,
+    factory: WorkerFactory,
+    handle: Option<JoinHandle<()>>,
+    started: Instant,
+    restarts: VecDeque<Instant>,
+    backoff: Duration,
+    restart_at: Option<Instant>,
+}
+
+/// Owns the threads of every worker, restarts the ones that panicked and reports
+/// what happened to them over the thread message channel
+pub struct Supervisor {
+    pub stdout: Sender<ThreadMessage>,
+    workers: HashMap<usize, SupervisedWorker>,
+    next_worker: usize,
+    exits_tx: Sender<WorkerExit>,
+    exits_rx: Receiver<WorkerExit>,
+}
+
+impl Supervisor {
+    pub fn new(stdout: Sender<ThreadMessage>) -> Self {
+        let (exits_tx, exits_rx) = channel();
+        Supervisor {
+            stdout,
+            workers: HashMap::new(),
+            next_worker: 0,
+            exits_tx,
+            exits_rx,
+        }
+    }
+
+    /// Starts a worker built by `factory` and keeps it running, workers of the same
+    /// trader share a `group`
+    pub fn spawn<F, T>(&mut self, group: &str, name: String, factory: F)
+        where F: Fn() -> T + Send + 'static, T: BotThread + Send + 'static {
+        let index = self.next_worker;
+        self.next_worker += 1;
+        self.workers.insert(index, SupervisedWorker {
+            group: group.to_string(),
+            name,
+            factory: Box::new(move || -> Box<dyn BotThread + Send> { Box::new(factory()) }),
+            handle: None,
+            started: Instant::now(),
+            restarts: VecDeque::new(),
+            backoff: FIRST_RESTART_BACKOFF,
+            restart_at: None,
+        });
+        self.start(index);
+    }
+
+    /// Workers that are running or waiting to be restarted, finished workers are forgotten
+    pub fn active_workers(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Whether any worker of `group` is still running or waiting to be restarted
+    pub fn group_active(&self, group: &str) -> bool {
+        self.workers.values().any(|worker| worker.group == group)
+    }
+
+    /// Workers of `group` waiting to be restarted are not restarted anymore, the running
+    /// ones are left to wind down on their own
+    pub fn cancel_restarts(&mut self, group: &str) {
+        self.workers.retain(|_, worker| worker.group != group || worker.restart_at.is_none());
+    }
+
+    /// Handles the workers that exited within `timeout` and restarts the ones that are due
+    pub fn poll(&mut self, timeout: Duration) {
+        match self.exits_rx.recv_timeout(timeout) {
+            Ok(exit) => self.on_exit(exit),
+            Err(RecvTimeoutError::Timeout) => {}
+            // the supervisor holds a sender itself
+            Err(RecvTimeoutError::Disconnected) => unreachable!(),
+        }
+        while let Ok(exit) = self.exits_rx.try_recv() {
+            self.on_exit(exit);
+        }
+        self.restart_due();
+    }
+
+    /// Watches the workers until none of them is left running
+    pub fn run(mut self) {
+        while self.active_workers() > 0 {
+            self.poll(SUPERVISOR_TICK);
+        }
+        self.report("All workers stopped".to_string(), ThreadLogLevel::Info);
+    }
+
+    fn start(&mut self, index: usize) {
+        let exits = self.exits_tx.clone();
+        let worker = match self.workers.get_mut(&index) {
+            Some(worker) => worker,
+            None => return
+        };
+        let mut bot_thread = (worker.factory)();
+        let handle = std::thread::Builder::new()
+            .name(worker.name.clone())
+            .spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| bot_thread.worker()));
+                let _ = exits.send(WorkerExit {
+                    worker: index,
+                    panic: result.err().map(panic_message),
+                });
+            });
+        match handle {
+            Ok(handle) => {
+                worker.handle = Some(handle);
+                worker.started = Instant::now();
+            }
+            Err(e) => {
+                let name = worker.name.clone();
+                self.report(format!("Failed to spawn {}: {}", name, e), ThreadLogLevel::Error);
+                self.schedule_restart(index);
+            }
+        }
+    }
+
+    fn on_exit(&mut self, exit: WorkerExit) {
+        let worker = match self.workers.get_mut(&exit.worker) {
+            Some(worker) => worker,
+            None => return
+        };
+        if let Some(handle) = worker.handle.take() {
+            let _ = handle.join();
+        }
+        let name = worker.name.clone();
+        match exit.panic {
+            // workers only return once their trader is closed, can't be traded at all or were asked to stop
+            None => {
+                self.workers.remove(&exit.worker);
+                self.report(format!("{} exited", name), ThreadLogLevel::Info)
+            }
+            Some(message) => {
+                self.report(format!("{} panicked: {}", name, message), ThreadLogLevel::Error);
+                self.schedule_restart(exit.worker);
+            }
+        }
+    }
+
+    fn schedule_restart(&mut self, index: usize) {
+        let now = Instant::now();
+        let worker = match self.workers.get_mut(&index) {
+            Some(worker) => worker,
+            None => return
+        };
+        if now.duration_since(worker.started) >= STABLE_RUN {
+            worker.backoff = FIRST_RESTART_BACKOFF;
+        }
+        while worker.restarts.front().map_or(false, |restart| now.duration_since(*restart) > RESTART_WINDOW) {
+            worker.restarts.pop_front();
+        }
+        if worker.restarts.len() >= MAX_RESTARTS {
+            let log = format!(
+                "{} restarted {} times within {} minutes, giving up on it",
+                worker.name, worker.restarts.len(), RESTART_WINDOW.as_secs() / 60
+            );
+            self.workers.remove(&index);
+            self.report(log, ThreadLogLevel::Error);
+            return
+        }
+        let wait = worker.backoff;
+        worker.backoff = (worker.backoff * 2).min(MAX_RESTART_BACKOFF);
+        worker.restarts.push_back(now);
+        worker.restart_at = Some(now + wait);
+        let log = format!("Restarting {} in {} seconds ({} of {})", worker.name, wait.as_secs(), worker.restarts.len(), MAX_RESTARTS);
+        self.report(log, ThreadLogLevel::Warn);
+    }
+
+    fn restart_due(&mut self) {
+        let now = Instant::now();
+        let due: Vec<usize> = self.workers.iter()
+            .filter(|(_, worker)| worker.restart_at.map_or(false, |restart_at| restart_at <= now))
+            .map(|(index, _)| *index)
+            .collect();
+        for index in due {
+            if let Some(worker) = self.workers.get_mut(&index) {
+                worker.restart_at = None;
+            }
+            self.start(index);
+        }
+    }
+
+    fn report(&self, log: String, level: ThreadLogLevel) {
+        match level {
+            ThreadLogLevel::Info => println!("[?] {}", log),
+            _ => eprintln!("[-] {}", log),
+        }
+        let _ = self.stdout.send(ThreadMessage::compile_log_message(ThreadMessageSource::Supervisor, log, level));
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
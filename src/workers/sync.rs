@@ -2,8 +2,6 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::mpsc::Sender;
-use std::thread::sleep;
-use std::time::Duration;
 use mongodb::bson::{doc, to_bson};
 use mongodb::options::UpdateModifications;
 use serum_dex::critbit::Slab;
@@ -12,7 +10,7 @@ use serum_dex::matching::Side;
 
 use serum_dex::state::{Market};
 use solana_client::client_error::ClientError;
-use solana_client::rpc_client::RpcClient;
+use crate::rpc::client::RpcConnection;
 use solana_program::account_info::AccountInfo;
 
 
@@ -27,6 +25,7 @@ use crate::{MongoClient, str_to_pubkey, TraderStatus};
 use crate::mongodb::models::Trader;
 use crate::serum::state::Order;
 use crate::workers::base::{BotConfig, BotThread};
+use crate::workers::error::{DecodedError, TradeBotErrors, TradeBotResult};
 use crate::workers::message::{ThreadMessage, ThreadMessageCompiler, ThreadMessageSource};
 
 pub struct SyncThread {
@@ -41,17 +40,21 @@ impl SyncThread {
 impl ThreadMessageCompiler for SyncThread {}
 
 impl BotThread for SyncThread {
-    fn setup(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) {
+    fn setup(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<()> {
 
 
-        let mut trader = self.get_updated_trader(mongo_client, &self.config.trader);
-        if trader.status == TraderStatus::Decommissioned || trader.status == TraderStatus::Stopped {
-            return
+        let mut trader = self.get_updated_trader(mongo_client, &self.config.trader)?;
+        if trader.status == TraderStatus::Registered || trader.status == TraderStatus::Decommissioned || trader.status == TraderStatus::Stopped {
+            self.config.pause(self.config.settings.sync_interval());
+            return Ok(())
         }
         println!("[?] Trader In db: {}", trader.to_string());
-        sleep(Duration::from_secs(30));
-        let open_orders_account_pubkey = str_to_pubkey(trader.serum_open_orders.get(0).unwrap());
-        let open_orders_account = connection.get_account(&open_orders_account_pubkey).unwrap();
+        self.config.pause(self.config.settings.sync_interval());
+        let open_orders_account_pubkey = match trader.serum_open_orders.get(0) {
+            Some(open_orders) => str_to_pubkey(open_orders),
+            None => return Ok(())
+        };
+        let open_orders_account = connection.get_account(&open_orders_account_pubkey)?;
         let mut open_orders_account_clone = open_orders_account.clone();
 
         let open_orders_account_info = AccountInfo {
@@ -74,29 +77,29 @@ impl BotThread for SyncThread {
             );
 
         if let Ok(open_orders) = open_orders_result {
-            let base_wallet_account = connection.get_account(&str_to_pubkey(&trader.base_trader_wallet));
-            let base_wallet = spl_token::state::Account::unpack(base_wallet_account.unwrap().data()).unwrap();
+            let base_wallet_account = connection.get_account(&str_to_pubkey(&trader.base_trader_wallet))?;
+            let base_wallet = spl_token::state::Account::unpack(base_wallet_account.data())
+                .map_err(|e| TradeBotErrors::Decoding(format!("base wallet {}: {:?}", trader.base_trader_wallet, e)))?;
             trader.base_balance = base_wallet.amount + open_orders.native_coin_free;
-            let quote_wallet_account = connection.get_account(&str_to_pubkey(&trader.quote_trader_wallet));
-            let quote_wallet = spl_token::state::Account::unpack(quote_wallet_account.unwrap().data()).unwrap();
+            let quote_wallet_account = connection.get_account(&str_to_pubkey(&trader.quote_trader_wallet))?;
+            let quote_wallet = spl_token::state::Account::unpack(quote_wallet_account.data())
+                .map_err(|e| TradeBotErrors::Decoding(format!("quote wallet {}: {:?}", trader.quote_trader_wallet, e)))?;
             trader.quote_balance = quote_wallet.amount + open_orders.native_pc_free;
         }
 
-        let trader_bson = to_bson(&trader.clone()).unwrap();
-        let trader_document = trader_bson.as_document().unwrap().to_owned();
-        let update_result = mongo_client.traders.find_one_and_update(
+        mongo_client.traders.find_one_and_update(
             doc! {
             "market_address": trader.market_address.clone(),
             "owner": trader.owner.clone(),
         }, UpdateModifications::Document(doc! {
                 "$set": {
-                    "base_balance": to_bson(&trader.base_balance).unwrap(),
-                    "quote_balance": to_bson(&trader.quote_balance).unwrap()
+                    "base_balance": to_bson(&trader.base_balance)?,
+                    "quote_balance": to_bson(&trader.quote_balance)?
                 }
             }),
             None
-        );
-        update_result.unwrap();
+        )?;
+        Ok(())
     }
 
     fn get_config(&self) -> Arc<BotConfig> {
@@ -118,15 +121,19 @@ impl BotThread for SyncThread {
     }
 
 
-    fn compile_ixs(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) -> Vec<Instruction> {
-        vec![]
+    fn compile_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        Ok(vec![])
     }
 
     fn log_rpc_client_error_(&self, err: ClientError) {
         self.log_rpc_client_error(err);
     }
 
-    fn log_transaction_logs_(&self, connection: &RpcClient, sig: &Signature) {
+    fn log_transaction_error_(&self, err: ClientError, ixs: &[Instruction]) -> DecodedError {
+        self.log_transaction_error(err, ixs)
+    }
+
+    fn log_transaction_logs_(&self, connection: &dyn RpcConnection, sig: &Signature) {
         self.log_transaction_logs(connection, sig)
     }
 }
\ No newline at end of file
@@ -5,7 +5,8 @@ use std::num::NonZeroU64;
 use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::Arc;
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
 
 use itertools::Itertools;
 use mongodb::bson::{doc, to_bson};
@@ -16,7 +17,7 @@ use serum_dex::instruction::{NewOrderInstructionV3, SelfTradeBehavior};
 use serum_dex::matching::{OrderType, Side};
 use serum_dex::state::{Market, ToAlignedBytes};
 use solana_client::client_error::ClientError;
-use solana_client::rpc_client::RpcClient;
+use crate::rpc::client::RpcConnection;
 use solana_program::account_info::AccountInfo;
 use solana_program::instruction::Instruction;
 use solana_program::pubkey::Pubkey;
@@ -25,16 +26,23 @@ use solana_program::sysvar::SysvarId;
 use solana_sdk::account::ReadableAccount;
 use solana_sdk::signature::{Signature, Signer};
 
+use crate::grid::generator::MarketLots;
 use crate::mongodb::client::MongoClient;
-use crate::mongodb::models::{GridPosition, GridStatus, Order as OrderDb, Trader};
-use crate::serum::state::Order;
+use crate::mongodb::models::{GridIntent, GridPosition, GridStatus, Order as OrderDb, Trader};
+use crate::serum::events::{Event, EventQueue};
+use crate::serum::state::{ClientOrderId, Order};
 use crate::{str_to_pubkey, TraderStatus};
-use crate::workers::base::{BotConfig, BotThread, MAX_IXS};
-use crate::workers::error::TradeBotResult;
+use crate::workers::base::{BotConfig, BotThread};
+use crate::workers::error::{DecodedError, TradeBotErrors, TradeBotResult};
+use crate::workers::fills::{FillStore, FillTracker};
+use crate::workers::market_data::{MarketData, MarketFeed};
+use crate::replay::session::SessionState;
+use crate::workers::recorder::BookRecorder;
 use crate::workers::message::{ThreadMessage, ThreadMessageCompiler, ThreadMessageSource};
+use crate::workers::stop::check_stop;
 
-const MAX_NEW_ORDER_IXS: usize = 10;
-const CLIENT_ORDER_ID: u64 = 66935256;
+/// How often a trader waiting on the shared market data checks whether it was asked to stop
+const MARKET_DATA_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 pub struct Price {
     pub buy: u64,
@@ -50,23 +58,128 @@ pub struct TraderThread {
     pub config: Arc<BotConfig>,
     pub data: Option<TraderData>,
     pub trader: Trader,
+    pub fills: FillTracker,
+    pub recorder: BookRecorder,
+    /// Grids whose orders are being cancelled because the bot shuts down
+    pub cancelled_on_shutdown: Vec<usize>,
+    /// Subscribed on the first read of the book when the market data is shared
+    pub market_feed: Option<MarketFeed>,
 }
 
 impl TraderThread {
 
+    /// Finds the awaiting grid an event of our open orders account belongs to, by
+    /// client order id and for orders placed before those were unique by order id
+    /// or price
+    fn find_grid_for_event(&self, trader: &Trader, event: &Event) -> Option<usize> {
+        if let Some(grid_index) = self.find_grid_by_client_order_id(trader, event.client_order_id) {
+            return Some(grid_index);
+        }
+        trader.grids.iter().position(|grid| {
+            if grid.awaiting_side().is_none() {
+                return false;
+            }
+            match &grid.order {
+                Some(order) => {
+                    order.side == event.side() && if order.order_id.is_empty() {
+                        grid.price == event.price()
+                    } else {
+                        order.order_id == event.order_id.to_string()
+                    }
+                }
+                None => false
+            }
+        })
+    }
+
+    /// Finds the grid an order on the book belongs to
+    fn find_grid_for_order(&self, trader: &Trader, order: &Order) -> Option<usize> {
+        if let Some(grid_index) = self.find_grid_by_client_order_id(trader, order.client_id) {
+            return Some(grid_index);
+        }
+        if let Some(decoded) = ClientOrderId::decode(order.client_id) {
+            // the latest order of a grid whose transaction was rolled back but landed after all
+            // is adopted, anything older is from a previous generation of the grid
+            let grid_index = decoded.grid_index as usize;
+            return trader.grids.get(grid_index)
+                .filter(|grid| grid.awaiting_side().is_none() && grid.generation == decoded.generation as u64)
+                .map(|_| grid_index);
+        }
+        trader.grids.iter().position(|grid| grid.price == order.price)
+    }
+
+    pub(crate) fn find_grid_by_client_order_id(&self, trader: &Trader, client_order_id: u64) -> Option<usize> {
+        let decoded = ClientOrderId::decode(client_order_id)?;
+        let grid_index = decoded.grid_index as usize;
+        match trader.grids.get(grid_index) {
+            Some(grid) if grid.client_order_id == Some(client_order_id) => Some(grid_index),
+            _ => None
+        }
+    }
+
+    /// Book and event queue of the market, the next update of the shared market data worker
+    /// when there is one so the grid is only evaluated again once something changed. The
+    /// trader reads them itself while that worker is late or gone
+    fn market_data(&mut self, connection: &dyn RpcConnection, serum_market: &Market) -> TradeBotResult<Arc<MarketData>> {
+        let keys = self.market_keys(serum_market);
+        let hub = match &self.config.market_data {
+            Some(hub) => hub.clone(),
+            None => return MarketData::fetch(connection, serum_market, &keys).map(Arc::new)
+        };
+        let market = keys.market;
+        let open_orders = self.trader.serum_open_orders.get(0).map(|open_orders| str_to_pubkey(open_orders));
+        let feed = self.market_feed.get_or_insert_with(|| hub.subscribe(&market, open_orders));
+        let deadline = Instant::now() + hub.update_timeout();
+        loop {
+            match feed.recv_timeout(MARKET_DATA_POLL_INTERVAL) {
+                Ok(update) => {
+                    // a trader that fell behind only cares about the latest book
+                    let mut latest = update;
+                    while let Ok(update) = feed.try_recv() {
+                        latest = update;
+                    }
+                    return latest
+                }
+                Err(RecvTimeoutError::Timeout) if self.config.stop_requested() => {
+                    return MarketData::fetch(connection, serum_market, &keys).map(Arc::new)
+                }
+                Err(RecvTimeoutError::Timeout) if Instant::now() >= deadline => {
+                    println!("[?] No market data for {} within {}s, reading the book directly", market, hub.update_timeout().as_secs());
+                    return MarketData::fetch(connection, serum_market, &keys).map(Arc::new)
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    // subscribed again on the next iteration
+                    self.market_feed = None;
+                    return MarketData::fetch(connection, serum_market, &keys).map(Arc::new)
+                }
+            }
+        }
+    }
+
+    /// The counter order on the adjacent grid only trades what was actually filled,
+    /// orders persisted before fills were tracked fall back to `amount_per_grid`
+    fn counter_order_base_size(&self, filled_order: &OrderDb) -> u64 {
+        if filled_order.filled_base_quantity > 0 {
+            filled_order.filled_base_quantity
+        } else {
+            self.trader.amount_per_grid
+        }
+    }
 
-    fn make_new_order_ix(&self, serum_market: &Market, trader: &Trader, side: Side, price: u64, qty: u64) -> Instruction{
+    fn make_new_order_ix(&self, serum_market: &Market, trader: &Trader, side: Side, price: u64, qty: u64, client_order_id: u64) -> Instruction{
+        let keys = self.market_keys(serum_market);
         serum_dex::instruction::new_order(
-            &str_to_pubkey(&trader.market_address),
+            &keys.market,
             &str_to_pubkey(trader.serum_open_orders.get(0).unwrap()),
-            &self.bytes_to_pubkey(&serum_market.req_q),
-            &self.bytes_to_pubkey(&serum_market.event_q),
-            &self.bytes_to_pubkey(&serum_market.bids),
-            &self.bytes_to_pubkey(&serum_market.asks),
+            &keys.request_queue,
+            &keys.event_queue,
+            &keys.bids,
+            &keys.asks,
             &str_to_pubkey(if side == Side::Bid{&trader.quote_trader_wallet} else {&trader.base_trader_wallet}),
             &self.config.fee_payer.pubkey(),
-            &self.bytes_to_pubkey(&serum_market.coin_vault),
-            &self.bytes_to_pubkey(&serum_market.pc_vault),
+            &keys.coin_vault,
+            &keys.pc_vault,
             &spl_token::id(),
             &Rent::id(),
             None,
@@ -75,12 +188,89 @@ impl TraderThread {
             NonZeroU64::new(price).unwrap(),
             NonZeroU64::new(qty).unwrap(),
             OrderType::Limit,
-            CLIENT_ORDER_ID,
+            client_order_id,
             SelfTradeBehavior::AbortTransaction,
             0,
             NonZeroU64::new(if side == Side::Bid{qty} else {1}).unwrap(),
         ).unwrap()
     }
+
+    /// Applies the events of our open orders account pushed since the last poll to the grids of
+    /// `trader` and returns the grids whose order left the book. Fills the trader has applied
+    /// before are skipped, those are only read again after a restart or a failed grid save
+    fn apply_events(&mut self, trader: &mut Trader, event_queue: &EventQueue, open_orders: &Pubkey, fill_store: &dyn FillStore) -> TradeBotResult<Vec<usize>> {
+        let mut resolved_grids: Vec<usize> = vec![];
+        let owner = open_orders.to_aligned_bytes();
+        for event in self.fills.poll(event_queue, &owner) {
+            let grid_index = self.find_grid_for_event(trader, &event);
+            if event.is_fill() {
+                if trader.last_fill_seq_num.map_or(false, |last_fill_seq_num| event.seq_num <= last_fill_seq_num) {
+                    println!("[?] Fill {} was applied before, skipping", event.seq_num);
+                    continue
+                }
+                let fill = self.fills.to_fill(trader, open_orders, &event, grid_index.map(|i| trader.grids[i].price));
+                println!("[+] {:?} filled at {}, paid {} received {} fee {}", fill.side, fill.price, fill.native_qty_paid, fill.native_qty_received, fill.native_fee_or_rebate);
+                fill_store.record_fill(&fill)?;
+                trader.last_fill_seq_num = Some(event.seq_num);
+                if let Some(grid_index) = grid_index {
+                    let grid = trader.grids.get_mut(grid_index).unwrap();
+                    if let Some(order) = grid.order.as_mut() {
+                        // bids receive base, asks pay it
+                        order.apply_fill(if event.side() == Side::Bid { event.native_qty_released } else { event.native_qty_paid });
+                        if order.is_partially_filled() {
+                            grid.status = GridStatus::PartiallyFilled;
+                        }
+                    }
+                }
+            } else if event.is_out() {
+                if let Some(grid_index) = grid_index {
+                    let grid = trader.grids.get_mut(grid_index).unwrap();
+                    if event.is_filled_out() {
+                        grid.status = GridStatus::Violated;
+                        if let Some(order) = grid.order.as_mut() {
+                            order.is_filled = true;
+                        }
+                    } else if grid.order.as_ref().map_or(false, |order| order.filled_base_quantity > 0) {
+                        // cancelled after a partial fill, the filled part still needs its counter order
+                        println!("[?] Partially filled order at {} was cancelled", grid.price);
+                        grid.status = GridStatus::Violated;
+                    } else {
+                        println!("[?] Order at {} was cancelled", grid.price);
+                        grid.status = GridStatus::Idle;
+                        grid.order = None;
+                    }
+                    resolved_grids.push(grid_index);
+                }
+            }
+        }
+        Ok(resolved_grids)
+    }
+
+    /// Saves the grids with the fills applied to them, the events they were built from
+    /// are not read again once they are saved
+    fn update_grids(&mut self, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        let trader_update_bson = to_bson(&self.trader.clone().grids)?;
+        mongo_client.traders.find_one_and_update(
+            doc! {
+            "market_address": self.trader.market_address.clone(),
+            "owner": self.trader.owner.clone(),
+        }, UpdateModifications::Document(doc! {
+                "$set": {
+                    "grids": trader_update_bson,
+                    "last_fill_seq_num": to_bson(&self.trader.last_fill_seq_num)?
+                }
+            }),
+            None
+        )?;
+        self.fills.commit();
+        Ok(())
+    }
+}
+
+/// Bumps the generation of the grid and returns the client order id for the order about to be placed on it
+fn next_client_order_id(grid: &mut GridPosition, grid_index: usize) -> u64 {
+    grid.generation += 1;
+    ClientOrderId::new(grid_index, grid.generation).encode()
 }
 
 impl ThreadMessageCompiler for TraderThread {}
@@ -91,55 +281,28 @@ pub enum Movement {
     Upward
 }
 impl BotThread for TraderThread {
-    fn setup(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) {
+    fn setup(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<()> {
         if self.trader.status == TraderStatus::Decommissioned || self.trader.status == TraderStatus::Stopped {
-            return
+            return Ok(())
         }
         let config = self.get_config();
-        let mut trader = self.get_updated_trader(mongo_client, &config.trader);
-
-
-        let bids_account_pubkey = self.bytes_to_pubkey(&serum_market.bids);
-        let asks_account_pubkey = self.bytes_to_pubkey(&serum_market.asks);
-        let open_orders_account_pubkey = str_to_pubkey(trader.serum_open_orders.get(0).unwrap());
-
-        let bids_account = connection.get_account(&bids_account_pubkey).unwrap();
-        let mut bids_account_clone = bids_account.clone();
-
-        let bids_account_info = AccountInfo {
-            key: &bids_account_pubkey,
-            is_signer: false,
-            is_writable: false,
-            lamports: Rc::new(RefCell::new(&mut bids_account_clone.lamports)),
-            data: Rc::new(RefCell::new(&mut bids_account_clone.data)),
-            owner: &bids_account.owner().clone(),
-            executable: false,
-            rent_epoch: bids_account.rent_epoch,
-        };
-        let all_bids = serum_market.load_bids_mut(
-            &bids_account_info,
-        )
-            .unwrap();
+        let mut trader = self.get_updated_trader(mongo_client, &config.trader)?;
+        if trader.status == TraderStatus::Registered {
+            // waiting on the initializer
+            self.trader = trader;
+            return Ok(())
+        }
+        // client order ids encode the position of the grid in this order
+        trader.grids.sort_by_key(|grid| Reverse(grid.price));
 
-        let asks_account = connection.get_account(&asks_account_pubkey).unwrap();
-        let mut asks_account_clone = asks_account.clone();
 
-        let asks_account_info = AccountInfo {
-            key: &asks_account_pubkey,
-            is_signer: false,
-            is_writable: false,
-            lamports: Rc::new(RefCell::new(&mut asks_account_clone.lamports)),
-            data: Rc::new(RefCell::new(&mut asks_account_clone.data)),
-            owner: &asks_account.owner().clone(),
-            executable: false,
-            rent_epoch: asks_account.rent_epoch,
+        let open_orders_account_pubkey = match trader.serum_open_orders.get(0) {
+            Some(open_orders) => str_to_pubkey(open_orders),
+            None => return Err(TradeBotErrors::Decoding(format!("trader {} has no open orders account", trader.owner)))
         };
-        let all_asks = serum_market.load_asks_mut(
-            &asks_account_info,
-        )
-            .unwrap();
+        let market_data = self.market_data(connection, serum_market)?;
 
-        let open_orders_account = connection.get_account(&open_orders_account_pubkey).unwrap();
+        let open_orders_account = connection.get_account(&open_orders_account_pubkey)?;
         let mut open_orders_account_clone = open_orders_account.clone();
 
         let open_orders_account_info = AccountInfo {
@@ -152,37 +315,47 @@ impl BotThread for TraderThread {
             executable: false,
             rent_epoch: open_orders_account.rent_epoch,
         };
-        let mut bids = self.parse_order_book(Side::Bid, all_bids.deref().clone());
-        let mut asks = self.parse_order_book(Side::Ask, all_asks.deref().clone());
-
-        let mut my_bids = self.parse_order_book_for_owner(Side::Bid, all_bids.deref().clone(), &open_orders_account_info.key.to_aligned_bytes());
-        let mut my_asks = self.parse_order_book_for_owner(Side::Ask, all_asks.deref().clone(), &open_orders_account_info.key.to_aligned_bytes());
-
-        let mut my_orders = vec![my_bids, my_asks];
-        let mut my_orders_flat: Vec<Order> = my_orders.into_iter().flatten().collect::<Vec<Order>>();
-
-        let awaiting_grids: Vec<GridPosition> = trader.grids.clone().into_iter().filter(|grid| grid.status == GridStatus::AwaitingBuy || grid.status == GridStatus::AwaitingSell).collect();
-
-        for grid in awaiting_grids {
-            let open_order = my_orders_flat
-                .clone()
-                .into_iter()
-                .find_position(|order| grid.price == order.price);
-            if let Some(order) = open_order {
-                // the order is still active
-            } else {
-                // the order has been filled mark as violated
-                let grid_order = trader.grids.clone()
-                    .clone()
-                    .into_iter()
-                    .find_position(|order| grid.price == order.price);
-                if let Some((grid_index, mut grid)) = grid_order {
-                    if grid.order.is_some() {
-                        trader.grids.get_mut(grid_index).unwrap().status = GridStatus::Violated;
-                    } else {
-                        trader.grids.get_mut(grid_index).unwrap().status = GridStatus::Idle;
+        let mut bids = market_data.bids.clone();
+        let mut asks = market_data.asks.clone();
+        let my_orders_flat = market_data.orders_of(&open_orders_account_pubkey.to_aligned_bytes());
+
+        if self.recorder.is_due(&trader) {
+            let slot = connection.get_slot().ok();
+            let snapshot = self.recorder.snapshot(&trader, slot, &bids, &asks, &my_orders_flat);
+            self.recorder.record(mongo_client, &snapshot);
+        }
 
+        // the book and the event queue are read in one request, anything that took one of
+        // our orders off the book is still on the queue unless it has been cranked already
+        let resolved_grids = match EventQueue::from_buffer(market_data.event_queue.data()) {
+            Some(event_queue) => self.apply_events(&mut trader, &event_queue, &open_orders_account_pubkey, mongo_client)?,
+            None => vec![],
+        };
+
+        for (grid_index, grid) in trader.grids.clone().into_iter().enumerate() {
+            if grid.awaiting_side().is_none() || resolved_grids.contains(&grid_index) {
+                continue
+            }
+            let is_open = my_orders_flat
+                .iter()
+                .any(|order| self.find_grid_for_order(&trader, order) == Some(grid_index));
+            if !is_open {
+                // the events were consumed before we saw them, only the fills applied so far tell
+                // a filled order from a cancelled one
+                let grid = trader.grids.get_mut(grid_index).unwrap();
+                match grid.order.as_mut() {
+                    Some(order) if order.base_quantity > 0 && order.filled_base_quantity >= order.base_quantity => {
+                        println!("[?] Order at {} left the book after it was filled", grid.price);
+                        order.is_filled = true;
+                        grid.status = GridStatus::Violated;
                     }
+                    Some(order) => {
+                        eprintln!(
+                            "[-] Order at {} left the book without an event, {} of {} filled, leaving the grid as it is",
+                            grid.price, order.filled_base_quantity, order.base_quantity,
+                        );
+                    }
+                    None => grid.status = GridStatus::Idle,
                 }
             }
         }
@@ -204,13 +377,26 @@ impl BotThread for TraderThread {
             );
         if let Ok(open_orders) = open_orders_result {
             for mut order in my_orders_flat {
-                let grid_order = trader.grids.clone()
-                    .clone()
-                    .into_iter()
-                    .find_position(|grid| grid.price == order.price);
+                let grid_order = self.find_grid_for_order(&trader, &order)
+                    .map(|grid_index| (grid_index, trader.grids[grid_index].clone()));
                 if let Some((grid_index, mut grid)) = grid_order {
-
-                    trader.grids.get_mut(grid_index).unwrap().status = if order.side == Side::Bid {GridStatus::AwaitingBuy} else {GridStatus::AwaitingSell};
+                    // the book is authoritative for what is left on the order
+                    let remaining_base_quantity = order.quantity * serum_market.coin_lot_size;
+                    let base_quantity = grid.order.as_ref()
+                        .filter(|grid_order| grid_order.side == order.side && grid_order.base_quantity > 0)
+                        .map_or(remaining_base_quantity, |grid_order| grid_order.base_quantity);
+                    let filled_base_quantity = base_quantity.saturating_sub(remaining_base_quantity);
+
+                    trader.grids.get_mut(grid_index).unwrap().status = if filled_base_quantity > 0 {
+                        GridStatus::PartiallyFilled
+                    } else if order.side == Side::Bid {
+                        GridStatus::AwaitingBuy
+                    } else {
+                        GridStatus::AwaitingSell
+                    };
+                    if ClientOrderId::decode(order.client_id).is_some() {
+                        trader.grids.get_mut(grid_index).unwrap().client_order_id = Some(order.client_id);
+                    }
                     trader.grids.get_mut(grid_index).unwrap().order = Some(OrderDb {
                         price: grid.price,
                         side: order.side,
@@ -218,7 +404,9 @@ impl BotThread for TraderThread {
                         is_filled: false,
                         owner: self.bytes_to_pubkey(&order.owner).to_string(),
                         order_id: order.order_id.to_string(),
-
+                        base_quantity,
+                        filled_base_quantity,
+                        remaining_base_quantity,
                     });
                 } else {
                     println!("Unknown order")
@@ -253,26 +441,35 @@ impl BotThread for TraderThread {
             }
         }
         self.trader = trader;
-        self.trader.grids.sort_by_key(|grid| Reverse(grid.price))
+        Ok(())
     }
 
-    fn cleanup(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) {
-        if self.trader.status == TraderStatus::Decommissioned || self.trader.status == TraderStatus::Stopped {
+    fn cleanup(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<()> {
+        if self.trader.status == TraderStatus::Registered || self.trader.status == TraderStatus::Decommissioned || self.trader.status == TraderStatus::Stopped {
+            return Ok(())
+        }
+        self.release_cancelled_grids();
+        for grid in self.trader.grids.iter_mut() {
+            grid.commit_pending();
+        }
+        self.update_grids(mongo_client)
+    }
+
+    fn rollback(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) {
+        // the cancels did not go through, the grids are still resting on the book
+        self.cancelled_on_shutdown.clear();
+        if self.trader.status == TraderStatus::Registered || self.trader.status == TraderStatus::Decommissioned || self.trader.status == TraderStatus::Stopped {
             return
         }
-        let trader_update_bson = to_bson(&self.trader.clone().grids).unwrap();
-        let update_result = mongo_client.traders.find_one_and_update(
-            doc! {
-            "market_address": self.trader.market_address.clone(),
-            "owner": self.trader.owner.clone(),
-        }, UpdateModifications::Document(doc! {
-                "$set": {
-                    "grids": trader_update_bson
-                }
-            }),
-            None
-        );
-        update_result.unwrap();
+        for grid in self.trader.grids.iter_mut() {
+            if grid.rollback_pending() {
+                println!("[-] Order at {} was not placed, grid stays {:?}", grid.price, grid.status);
+            }
+        }
+        // the bumped generations are still saved so a late landing order can be told apart
+        if let Err(e) = self.update_grids(mongo_client) {
+            eprintln!("[-] Failed to save rolled back grids: {}", e);
+        }
     }
 
     fn get_config(&self) -> Arc<BotConfig> {
@@ -291,56 +488,78 @@ impl BotThread for TraderThread {
         "Trader".to_string()
     }
 
+    fn compile_shutdown_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, _mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        self.compile_shutdown_cancel_ixs(connection, serum_market)
+    }
 
-    fn compile_ixs(&mut self, connection: &RpcClient, serum_market: &Market, mongo_client: &MongoClient) -> Vec<Instruction> {
-        if self.trader.status == TraderStatus::Decommissioned || self.trader.status == TraderStatus::Stopped {
-            return vec![]
+    fn session_state(&self) -> Option<SessionState> {
+        Some(SessionState::new(&self.trader, self.fills.last_seq_num))
+    }
+
+
+    fn compile_ixs(&mut self, connection: &dyn RpcConnection, serum_market: &Market, mongo_client: &MongoClient) -> TradeBotResult<Vec<Instruction>> {
+        if self.trader.status == TraderStatus::Registered || self.trader.status == TraderStatus::Decommissioned || self.trader.status == TraderStatus::Stopped {
+            return Ok(vec![])
+        }
+        if self.trader.status == TraderStatus::Stopping {
+            return self.compile_stop_ixs(connection, serum_market, mongo_client)
+        }
+
+        let mid_price = self.data.as_ref()
+            .and_then(|data| data.last_price.as_ref())
+            .map(|price| (price.buy + price.sell) / 2);
+        if let Some(mid_price) = mid_price {
+            if let Some(reason) = check_stop(&self.trader, mid_price) {
+                self.trigger_stop(mongo_client, reason, mid_price)?;
+                return self.compile_stop_ixs(connection, serum_market, mongo_client)
+            }
         }
 
         let mut idleGrids: Vec<GridPosition> = self.trader.grids.clone().into_iter().filter(|grid| {
             return grid.status == GridStatus::Idle || grid.status == GridStatus::Violated;
         }).collect();
         let mut ixs: Vec<Instruction> = vec![];
-        println!("[?] Using Trader \n{}", self.trader.to_string());
+        let lots = MarketLots {
+            coin_lot_size: serum_market.coin_lot_size,
+            pc_lot_size: serum_market.pc_lot_size,
+        };
 
         if let Some(data) = &self.data {
             if let Some(price) = &data.last_price {
                 let spread_price = (price.buy + price.sell) / 2;
                 if spread_price > self.trader.upper_price_range || spread_price < self.trader.lower_price_range {
                     println!("[?] Price went over bounds {}", spread_price);
-                    return ixs
+                    return Ok(ixs)
                 }
 
-                for mut i in 0..min(idleGrids.len(), MAX_IXS) {
+                for mut i in 0..min(idleGrids.len(), self.config.settings.max_new_order_ixs) {
                     let grid_position = idleGrids.get(i).unwrap();
                     let grid_order = self.trader.grids.clone()
                         .clone()
                         .into_iter()
                         .find_position(|grid| grid.price == grid_position.price);
+                    if grid_order.as_ref().map_or(false, |(_, grid)| grid.pending.is_some()) {
+                        // already has an order going out in this transaction
+                        continue
+                    }
                     match grid_position.status {
                         GridStatus::Idle => {
+                            let grid_index = match grid_order {
+                                Some((grid_index, _)) => grid_index,
+                                None => continue
+                            };
                             let base_size = self.trader.amount_per_grid;
-                            let base_size_lots = base_size / serum_market.coin_lot_size;
-                            let quote_size_lots = base_size_lots * serum_market.pc_lot_size * grid_position.price;
+                            let base_size_lots = lots.base_lots(base_size);
+                            let quote_size_lots = lots.quote_native(base_size_lots, grid_position.price);
 
                             if spread_price > grid_position.price {
                                 //buy
-                                let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Bid, grid_position.price, quote_size_lots);
+                                let client_order_id = next_client_order_id(self.trader.grids.get_mut(grid_index).unwrap(), grid_index);
+                                let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Bid, grid_position.price, quote_size_lots, client_order_id);
 
                                 ixs.push(new_order_ix);
 
-                                if let Some((grid_index, mut grid)) = grid_order {
-                                    self.trader.grids.get_mut(grid_index).unwrap().status = GridStatus::AwaitingBuy;
-                                    self.trader.grids.get_mut(grid_index).unwrap().order = Some(OrderDb {
-                                        price: grid_position.price,
-                                        side: Side::Bid,
-                                        client_order_id: CLIENT_ORDER_ID,
-                                        is_filled: false,
-                                        owner: "".to_string(),
-                                        order_id: "".to_string()
-
-                                    })
-                                }
+                                self.trader.grids.get_mut(grid_index).unwrap().pending = Some(GridIntent::new(OrderDb::new(grid_position.price, Side::Bid, client_order_id, base_size_lots * serum_market.coin_lot_size)))
                             } else {
                                 //sell
 
@@ -348,19 +567,10 @@ impl BotThread for TraderThread {
                                     println!("[?] Size Below Miniumum");
                                     continue;
                                 }
-                                let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Ask, grid_position.price, base_size_lots);
+                                let client_order_id = next_client_order_id(self.trader.grids.get_mut(grid_index).unwrap(), grid_index);
+                                let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Ask, grid_position.price, base_size_lots, client_order_id);
                                 ixs.push(new_order_ix);
-                                if let Some((grid_index, mut grid)) = grid_order {
-                                    self.trader.grids.get_mut(grid_index).unwrap().status = GridStatus::AwaitingSell;
-                                    self.trader.grids.get_mut(grid_index).unwrap().order = Some(OrderDb {
-                                        price: grid_position.price,
-                                        side: Side::Ask,
-                                        client_order_id: CLIENT_ORDER_ID,
-                                        is_filled: false,
-                                        owner: "".to_string(),
-                                        order_id: "".to_string()
-                                    })
-                                }
+                                self.trader.grids.get_mut(grid_index).unwrap().pending = Some(GridIntent::new(OrderDb::new(grid_position.price, Side::Ask, client_order_id, base_size_lots * serum_market.coin_lot_size)))
                             }
                         }
 
@@ -368,20 +578,20 @@ impl BotThread for TraderThread {
                             // place order on next grid if it doesnt exist
                             // if the violated grid was a buy order sell on the next higher grid if no orders exist there vice versa if it was a sell order
 
-                            let mut buy_indexes: Vec<usize> = vec![];
-                            let mut sell_indexes: Vec<usize> = vec![];
                             if let Some(order) = grid_position.clone().order {
                                 if let Some((grid_index, mut grid)) = grid_order {
 
                                 match order.side {
-                                    // TODO: recalculate size
                                     Side::Ask  => {
 
                                             if  grid_index == 0 {
                                                 println!("[-] I don't know how this happened, should have never happened");
                                                 continue
                                             }
-                                            if let Some(next_grid) = self.trader.grids.get(grid_index + 1) {
+                                            if let Some(next_grid) = self.trader.grids.get(grid_index + 1).cloned() {
+                                                if next_grid.pending.is_some() {
+                                                    continue
+                                                }
 
                                                 // i'm never going to understand why i did this but it makes sense now
                                                 // only covered for one strike TODO: find an efficient algorithm to detect reversals
@@ -393,46 +603,34 @@ impl BotThread for TraderThread {
                                                         }
                                                     }
                                                 }
-                                                let base_size = self.trader.amount_per_grid;
-                                                let base_size_lots = base_size / serum_market.coin_lot_size;
-                                                let quote_size_lots = base_size_lots * serum_market.pc_lot_size * next_grid.price;
+                                                let base_size = self.counter_order_base_size(&order);
+                                                let base_size_lots = lots.base_lots(base_size);
+                                                if base_size_lots == 0 {
+                                                    println!("[?] Filled size below minimum, nothing to counter");
+                                                    continue
+                                                }
+                                                let quote_size_lots = lots.quote_native(base_size_lots, next_grid.price);
                                                 if next_grid.status == GridStatus::Violated {
 
                                                     // place buy order for previous closed order
-                                                    let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Bid, next_grid.price, quote_size_lots);
+                                                    let client_order_id = next_client_order_id(self.trader.grids.get_mut(grid_index + 1).unwrap(), grid_index + 1);
+                                                    let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Bid, next_grid.price, quote_size_lots, client_order_id);
                                                     ixs.push(new_order_ix);
-                                                    buy_indexes.push(grid_index + 1);
-                                                    self.trader.grids.get_mut(grid_index + 1).unwrap().order = Some(OrderDb {
-                                                        price: next_grid.price,
-                                                        side: Side::Bid,
-                                                        client_order_id: CLIENT_ORDER_ID,
-                                                        is_filled: false,
-                                                        owner: "".to_string(),
-                                                        order_id: "".to_string()
-
-                                                    })
+                                                    self.trader.grids.get_mut(grid_index + 1).unwrap().pending = Some(GridIntent::new(OrderDb::new(next_grid.price, Side::Bid, client_order_id, base_size_lots * serum_market.coin_lot_size)))
                                                 } else {
-                                                    let quote_size_lots = base_size_lots * serum_market.pc_lot_size * grid_position.price;
+                                                    let quote_size_lots = lots.quote_native(base_size_lots, grid_position.price);
 
                                                     if let Some(prev_grid) = self.trader.grids.get(grid_index - 1) {
                                                         if next_grid.order.is_some()
                                                             && prev_grid.order.is_some()
                                                             && next_grid.order.as_ref().unwrap().side == prev_grid.order.as_ref().unwrap().side
-                                                            && next_grid.status == GridStatus::AwaitingBuy
-                                                            && prev_grid.status == GridStatus::AwaitingBuy
+                                                            && next_grid.awaiting_side() == Some(Side::Bid)
+                                                            && prev_grid.awaiting_side() == Some(Side::Bid)
                                                         {
-                                                            let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Bid, grid_position.price, quote_size_lots);
+                                                            let client_order_id = next_client_order_id(self.trader.grids.get_mut(grid_index).unwrap(), grid_index);
+                                                            let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Bid, grid_position.price, quote_size_lots, client_order_id);
                                                             ixs.push(new_order_ix);
-                                                            buy_indexes.push(grid_index);
-                                                            self.trader.grids.get_mut(grid_index ).unwrap().order = Some(OrderDb {
-                                                                price: grid_position.price,
-                                                                side: Side::Bid,
-                                                                client_order_id: CLIENT_ORDER_ID,
-                                                                is_filled: false,
-                                                                owner: "".to_string(),
-                                                                order_id: "".to_string()
-
-                                                            })
+                                                            self.trader.grids.get_mut(grid_index).unwrap().pending = Some(GridIntent::new(OrderDb::new(grid_position.price, Side::Bid, client_order_id, base_size_lots * serum_market.coin_lot_size)))
                                                         } else {
                                                             println!("[-] Next grid is still awaiting filling, skipping buy");
 
@@ -453,7 +651,10 @@ impl BotThread for TraderThread {
                                             }
 
 
-                                        if let Some(next_grid) = self.trader.grids.get(grid_index  - 1) {
+                                        if let Some(next_grid) = self.trader.grids.get(grid_index  - 1).cloned() {
+                                            if next_grid.pending.is_some() {
+                                                continue
+                                            }
 
                                             if let Some(next_next_grid) = self.trader.grids.get(grid_index - 2) {
                                                 if next_next_grid.status == GridStatus::Violated && next_next_grid.order.is_some() && next_next_grid.order.as_ref().unwrap().side == Side::Ask {
@@ -465,43 +666,31 @@ impl BotThread for TraderThread {
                                                 }
                                             }
 
-                                            let base_size = self.trader.amount_per_grid;
-                                            let base_size_lots = base_size / serum_market.coin_lot_size;
+                                            let base_size = self.counter_order_base_size(&order);
+                                            let base_size_lots = lots.base_lots(base_size);
+                                            if base_size_lots == 0 {
+                                                println!("[?] Filled size below minimum, nothing to counter");
+                                                continue
+                                            }
                                             if next_grid.status == GridStatus::Violated {
                                                 // place sell order for previous closed order
 
-                                                let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Ask, next_grid.price, base_size_lots);
+                                                let client_order_id = next_client_order_id(self.trader.grids.get_mut(grid_index - 1).unwrap(), grid_index - 1);
+                                                let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Ask, next_grid.price, base_size_lots, client_order_id);
                                                 ixs.push(new_order_ix);
-                                                sell_indexes.push(grid_index-1);
-                                                self.trader.grids.get_mut(grid_index - 1).unwrap().order = Some(OrderDb {
-                                                    price: next_grid.price,
-                                                    side: Side::Ask,
-                                                    client_order_id: CLIENT_ORDER_ID,
-                                                    is_filled: false,
-                                                    owner: "".to_string(),
-                                                    order_id: "".to_string()
-
-                                                })
+                                                self.trader.grids.get_mut(grid_index - 1).unwrap().pending = Some(GridIntent::new(OrderDb::new(next_grid.price, Side::Ask, client_order_id, base_size_lots * serum_market.coin_lot_size)))
                                             }
                                             else {
                                                 if let Some(prev_grid) = self.trader.grids.get(grid_index + 1) {
                                                     if next_grid.order.is_some()
                                                         && prev_grid.order.is_some()
                                                         && next_grid.order.as_ref().unwrap().side == prev_grid.order.as_ref().unwrap().side
-                                                        && next_grid.status == GridStatus::AwaitingSell
-                                                        && prev_grid.status == GridStatus::AwaitingSell {
-                                                        let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Ask, grid_position.price, base_size_lots);
+                                                        && next_grid.awaiting_side() == Some(Side::Ask)
+                                                        && prev_grid.awaiting_side() == Some(Side::Ask) {
+                                                        let client_order_id = next_client_order_id(self.trader.grids.get_mut(grid_index).unwrap(), grid_index);
+                                                        let new_order_ix = self.make_new_order_ix(serum_market, &self.trader, Side::Ask, grid_position.price, base_size_lots, client_order_id);
                                                         ixs.push(new_order_ix);
-                                                        sell_indexes.push(grid_index);
-                                                        self.trader.grids.get_mut(grid_index).unwrap().order = Some(OrderDb {
-                                                            price: grid_position.price,
-                                                            side: Side::Ask,
-                                                            client_order_id: CLIENT_ORDER_ID,
-                                                            is_filled: false,
-                                                            owner: "".to_string(),
-                                                            order_id: "".to_string()
-
-                                                        })
+                                                        self.trader.grids.get_mut(grid_index).unwrap().pending = Some(GridIntent::new(OrderDb::new(grid_position.price, Side::Ask, client_order_id, base_size_lots * serum_market.coin_lot_size)))
                                                     } else {
                                                         println!("[-] Next grid is still awaiting filling, skipping sell");
                                                     }
@@ -515,12 +704,6 @@ impl BotThread for TraderThread {
                                     }
                                 }
                             }
-                            for grid_index in buy_indexes {
-                                self.trader.grids.get_mut(grid_index).unwrap().status = GridStatus::AwaitingBuy
-                            }
-                            for grid_index in sell_indexes {
-                                self.trader.grids.get_mut(grid_index).unwrap().status = GridStatus::AwaitingSell
-                            }
                         }
                         _ => {}
                     }
@@ -529,7 +712,7 @@ impl BotThread for TraderThread {
         }
 
 
-        ixs
+        Ok(ixs)
     }
 
 
@@ -537,7 +720,318 @@ impl BotThread for TraderThread {
         self.log_rpc_client_error(err);
     }
 
-    fn log_transaction_logs_(&self, connection: &RpcClient, sig: &Signature) {
+    fn log_transaction_error_(&self, err: ClientError, ixs: &[Instruction]) -> DecodedError {
+        self.log_transaction_error(err, ixs)
+    }
+
+    fn log_transaction_logs_(&self, connection: &dyn RpcConnection, sig: &Signature) {
         self.log_transaction_logs(connection, sig)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc::{channel, Receiver};
+
+    use enumflags2::BitFlags;
+    use serum_dex::critbit::LeafNode;
+    use serum_dex::state::{AccountFlag, MarketState, OpenOrders};
+    use solana_sdk::commitment_config::CommitmentConfig;
+    use solana_sdk::signature::Keypair;
+    use solana_sdk::transaction::{Transaction, TransactionError};
+
+    use crate::config::Settings;
+    use crate::mongodb::models::TokenInfo;
+    use crate::rpc::mock::{MockCluster, ScriptedOutcome};
+use crate::workers::fills::MemoryFillStore;
+    use crate::serum::events::{EVENT_FLAG_BID, EVENT_FLAG_FILL, EVENT_FLAG_MAKER, EVENT_FLAG_OUT};
+
+    use super::*;
+
+    const COIN_LOT_SIZE: u64 = 100;
+    const PC_LOT_SIZE: u64 = 10;
+    const AMOUNT_PER_GRID: u64 = 1000;
+    const EVENT_QUEUE_CAPACITY: usize = 8;
+
+    /// A trader on a market of the mock cluster with someone else's orders at 99 and 101
+    struct Fixture {
+        cluster: Arc<MockCluster>,
+        serum_program: Pubkey,
+        market: Pubkey,
+        open_orders: Pubkey,
+        thread: TraderThread,
+        _messages: Receiver<ThreadMessage>,
+    }
+
+    impl Fixture {
+        fn new(grids: Vec<GridPosition>) -> Self {
+            let cluster = Arc::new(MockCluster::new());
+            let serum_program = Pubkey::new_unique();
+            let market = Pubkey::new_unique();
+            let (bids, asks, event_queue, open_orders) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+            let fee_payer = Keypair::new();
+
+            let mut market_state: MarketState = bytemuck::Zeroable::zeroed();
+            let flags: BitFlags<AccountFlag> = AccountFlag::Initialized | AccountFlag::Market;
+            market_state.account_flags = flags.bits();
+            market_state.own_address = key(&market);
+            market_state.coin_lot_size = COIN_LOT_SIZE;
+            market_state.pc_lot_size = PC_LOT_SIZE;
+            market_state.bids = key(&bids);
+            market_state.asks = key(&asks);
+            market_state.event_q = key(&event_queue);
+            market_state.req_q = key(&Pubkey::new_unique());
+            market_state.coin_vault = key(&Pubkey::new_unique());
+            market_state.pc_vault = key(&Pubkey::new_unique());
+            cluster.set_serum_market(&market, &serum_program, &market_state);
+            cluster.set_slab(&bids, &serum_program, AccountFlag::Bids, 16, &[book_order(99)]);
+            cluster.set_slab(&asks, &serum_program, AccountFlag::Asks, 16, &[book_order(101)]);
+            cluster.set_account_data(&event_queue, &serum_program, event_queue_data(&[]));
+
+            let mut open_orders_state: OpenOrders = bytemuck::Zeroable::zeroed();
+            let flags: BitFlags<AccountFlag> = AccountFlag::Initialized | AccountFlag::OpenOrders;
+            open_orders_state.account_flags = flags.bits();
+            open_orders_state.market = key(&market);
+            open_orders_state.owner = key(&fee_payer.pubkey());
+            open_orders_state.free_slot_bits = !0;
+            cluster.set_open_orders(&open_orders, &serum_program, &open_orders_state);
+
+            let trader = test_trader(&market, &open_orders, grids);
+            let mut settings = Settings::default();
+            settings.max_new_order_ixs = 10;
+            let (stdout, messages) = channel::<ThreadMessage>();
+            let config = BotConfig {
+                serum_program,
+                token_program: spl_token::id(),
+                associated_token_program: Pubkey::new_unique(),
+                trader: trader.clone(),
+                rpc_url: "".to_string(),
+                fee_payer,
+                connection: cluster.clone(),
+                session_dir: None,
+                stop: Arc::new(AtomicBool::new(false)),
+                settings: Arc::new(settings),
+                market: None,
+                market_data: None,
+            };
+            let thread = TraderThread {
+                stdout,
+                config: Arc::new(config),
+                data: None,
+                trader,
+                fills: FillTracker::new(),
+                recorder: BookRecorder::new(),
+                cancelled_on_shutdown: vec![],
+                market_feed: None,
+            };
+            Fixture { cluster, serum_program, market, open_orders, thread, _messages: messages }
+        }
+
+        /// Runs `f` with the market loaded from the mock cluster, like the worker does
+        fn with_market<T>(&mut self, f: impl FnOnce(&mut TraderThread, &dyn RpcConnection, &Market) -> T) -> T {
+            let mut account = self.cluster.get_account(&self.market).unwrap();
+            let account_info = AccountInfo::new(
+                &self.market,
+                false,
+                false,
+                &mut account.lamports,
+                &mut account.data,
+                &self.serum_program,
+                false,
+                0,
+            );
+            let serum_market = Market::load(&account_info, &self.serum_program, true).unwrap();
+            let connection: &dyn RpcConnection = self.cluster.as_ref();
+            f(&mut self.thread, connection, &serum_market)
+        }
+
+        fn send(&self, ixs: &[Instruction]) -> Signature {
+            let fee_payer = &self.thread.config.fee_payer;
+            let mut tx = Transaction::new_with_payer(ixs, Some(&fee_payer.pubkey()));
+            tx.sign(&[fee_payer], self.cluster.get_latest_blockhash().unwrap());
+            self.cluster.send_transaction(&tx).unwrap()
+        }
+
+        fn pending_client_order_ids(&self) -> Vec<u64> {
+            self.thread.trader.grids.iter()
+                .filter_map(|grid| grid.pending.as_ref().map(|pending| pending.order.client_order_id))
+                .collect()
+        }
+    }
+
+    fn key(pubkey: &Pubkey) -> [u64; 4] {
+        bytemuck::cast(pubkey.to_bytes())
+    }
+
+    fn book_order(price: u64) -> LeafNode {
+        // serum keeps its fee tiers private, a zeroed leaf carries the base tier
+        let zeroed: LeafNode = bytemuck::Zeroable::zeroed();
+        LeafNode::new(0, ((price as u128) << 64) | 1, [9, 9, 9, 9], 10, zeroed.fee_tier(), 0)
+    }
+
+    fn event(event_flags: u8, native_qty_released: u64, native_qty_paid: u64, price: u64, owner: [u64; 4], client_order_id: u64) -> Event {
+        Event {
+            event_flags,
+            owner_slot: 0,
+            fee_tier: 0,
+            native_qty_released,
+            native_qty_paid,
+            native_fee_or_rebate: 0,
+            order_id: ((price as u128) << 64) | 1,
+            owner,
+            client_order_id,
+            seq_num: 0,
+        }
+    }
+
+    /// Raw event queue account holding `events` from its head on
+    fn event_queue_data(events: &[Event]) -> Vec<u8> {
+        let flags: BitFlags<AccountFlag> = AccountFlag::Initialized | AccountFlag::EventQueue;
+        let mut data = b"serum".to_vec();
+        data.extend_from_slice(&flags.bits().to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&(events.len() as u64).to_le_bytes());
+        data.extend_from_slice(&(events.len() as u64).to_le_bytes());
+        for index in 0..EVENT_QUEUE_CAPACITY {
+            let mut raw = [0u8; 88];
+            if let Some(event) = events.get(index) {
+                raw[0] = event.event_flags;
+                raw[8..16].copy_from_slice(&event.native_qty_released.to_le_bytes());
+                raw[16..24].copy_from_slice(&event.native_qty_paid.to_le_bytes());
+                raw[24..32].copy_from_slice(&event.native_fee_or_rebate.to_le_bytes());
+                raw[32..48].copy_from_slice(&event.order_id.to_le_bytes());
+                for (i, word) in event.owner.iter().enumerate() {
+                    raw[48 + i * 8..56 + i * 8].copy_from_slice(&word.to_le_bytes());
+                }
+                raw[80..88].copy_from_slice(&event.client_order_id.to_le_bytes());
+            }
+            data.extend_from_slice(&raw);
+        }
+        data.extend_from_slice(b"padding");
+        data
+    }
+
+    fn idle_grids() -> Vec<GridPosition> {
+        [110, 105, 95, 90].iter()
+            .map(|price| GridPosition {
+                price: *price,
+                status: GridStatus::Idle,
+                order: None,
+                generation: 0,
+                client_order_id: None,
+                pending: None,
+            })
+            .collect()
+    }
+
+    fn test_trader(market: &Pubkey, open_orders: &Pubkey, grids: Vec<GridPosition>) -> Trader {
+        Trader {
+            base_trader_wallet: Pubkey::new_unique().to_string(),
+            quote_trader_wallet: Pubkey::new_unique().to_string(),
+            serum_open_orders: vec![open_orders.to_string()],
+            grids_count: grids.len() as u64,
+            grids,
+            amount_per_grid: AMOUNT_PER_GRID,
+            upper_price_range: 110,
+            lower_price_range: 90,
+            starting_price_buy: 99,
+            starting_price_sell: 101,
+            status: TraderStatus::Initialized,
+            ..Trader::new(
+                market.to_string(),
+                Pubkey::new_unique().to_string(),
+                TokenInfo { symbol: "BASE".to_string(), address: Pubkey::new_unique().to_string(), decimals: 0 },
+                TokenInfo { symbol: "QUOTE".to_string(), address: Pubkey::new_unique().to_string(), decimals: 0 },
+            )
+        }
+    }
+
+    /// Nothing listens there, anything the thread writes to the database fails right away
+    fn unreachable_database() -> MongoClient {
+        MongoClient::connect("mongodb://127.0.0.1:9/?serverSelectionTimeoutMS=100", "gridbot_test")
+    }
+
+    #[test]
+    fn stages_bids_below_and_asks_above_the_price() {
+        let mut fixture = Fixture::new(idle_grids());
+        fixture.thread.data = Some(TraderData { last_price: Some(Price { buy: 99, sell: 101 }) });
+        let mongo_client = unreachable_database();
+
+        let ixs = fixture.with_market(|thread, connection, serum_market| thread.compile_ixs(connection, serum_market, &mongo_client)).unwrap();
+
+        assert_eq!(ixs.len(), 4);
+        assert!(ixs.iter().all(|ix| ix.program_id == fixture.serum_program));
+        let grids = &fixture.thread.trader.grids;
+        let sides: Vec<Option<Side>> = grids.iter().map(|grid| grid.pending.as_ref().map(|pending| pending.order.side)).collect();
+        assert_eq!(sides, vec![Some(Side::Ask), Some(Side::Ask), Some(Side::Bid), Some(Side::Bid)]);
+        assert!(grids.iter().all(|grid| grid.pending.as_ref().unwrap().order.base_quantity == AMOUNT_PER_GRID));
+        // nothing rests on a grid before its transaction is confirmed
+        assert!(grids.iter().all(|grid| grid.status == GridStatus::Idle && grid.order.is_none()));
+    }
+
+    #[test]
+    fn failed_transaction_rolls_the_orders_back() {
+        let mut fixture = Fixture::new(idle_grids());
+        fixture.thread.data = Some(TraderData { last_price: Some(Price { buy: 99, sell: 101 }) });
+        let mongo_client = unreachable_database();
+
+        let ixs = fixture.with_market(|thread, connection, serum_market| thread.compile_ixs(connection, serum_market, &mongo_client)).unwrap();
+        let first_client_order_ids = fixture.pending_client_order_ids();
+        fixture.cluster.push_outcome(ScriptedOutcome::Failed(TransactionError::AccountInUse));
+        let signature = fixture.send(&ixs);
+        let status = fixture.cluster.get_signature_status_with_commitment(&signature, CommitmentConfig::confirmed()).unwrap();
+        assert_eq!(status, Some(Err(TransactionError::AccountInUse)));
+        fixture.with_market(|thread, connection, serum_market| thread.rollback(connection, serum_market, &mongo_client));
+
+        for grid in &fixture.thread.trader.grids {
+            assert!(grid.pending.is_none());
+            assert_eq!(grid.status, GridStatus::Idle);
+            assert!(grid.order.is_none());
+            assert_eq!(grid.generation, 1);
+        }
+
+        // the orders placed again can't be mistaken for the ones that failed, should those land late
+        fixture.with_market(|thread, connection, serum_market| thread.compile_ixs(connection, serum_market, &mongo_client)).unwrap();
+        let second_client_order_ids = fixture.pending_client_order_ids();
+        assert_eq!(second_client_order_ids.len(), 4);
+        assert!(second_client_order_ids.iter().all(|id| !first_client_order_ids.contains(id)));
+    }
+
+    #[test]
+    fn fill_is_applied_once_across_restarts() {
+        let client_order_id = ClientOrderId::new(2, 1).encode();
+        let mut grids = idle_grids();
+        grids[2].status = GridStatus::AwaitingBuy;
+        grids[2].generation = 1;
+        grids[2].client_order_id = Some(client_order_id);
+        grids[2].order = Some(OrderDb::new(95, Side::Bid, client_order_id, AMOUNT_PER_GRID));
+        let mut fixture = Fixture::new(grids);
+        let owner = fixture.open_orders.to_aligned_bytes();
+        let event_queue = EventQueue::from_buffer(&event_queue_data(&[
+            event(EVENT_FLAG_FILL | EVENT_FLAG_BID | EVENT_FLAG_MAKER, 500, 475, 95, owner, client_order_id),
+            // cancelled with the other half unfilled
+            event(EVENT_FLAG_OUT | EVENT_FLAG_BID, 475, 0, 95, owner, client_order_id),
+        ])).unwrap();
+        let fill_store = MemoryFillStore::default();
+        let mut saved_trader = fixture.thread.trader.clone();
+
+        // the grid save fails, the events are read again on the next iteration
+        let mut trader = saved_trader.clone();
+        fixture.thread.apply_events(&mut trader, &event_queue, &fixture.open_orders, &fill_store).unwrap();
+        assert_eq!(trader.grids[2].order.as_ref().unwrap().filled_base_quantity, 500);
+
+        for _ in 0..2 {
+            let mut trader = saved_trader.clone();
+            let resolved_grids = fixture.thread.apply_events(&mut trader, &event_queue, &fixture.open_orders, &fill_store).unwrap();
+            assert_eq!(resolved_grids, vec![2]);
+            let grid = &trader.grids[2];
+            assert_eq!(grid.status, GridStatus::Violated);
+            assert_eq!(grid.order.as_ref().unwrap().filled_base_quantity, 500);
+            // what update_grids saves, a restarted thread reads the event queue from its head again
+            saved_trader = trader;
+            fixture.thread.fills = FillTracker::new();
+        }
+        assert_eq!(fill_store.fills.borrow().len(), 1);
+    }
+}